@@ -1,18 +1,66 @@
 use std::{
+    collections::{HashMap, HashSet},
     fmt::Debug,
     ops::{Add, AddAssign},
+    time::{Duration, Instant},
 };
 
-use crate::types::{HasSamplerResources, Logits, Sampler, TID};
+use anyhow::Result;
+
+use crate::{
+    configure::SamplerMetadata,
+    samplers::MirostatState,
+    types::{HasSamplerResources, Logit, Logits, Sampler, SamplerError, L, TID},
+};
+
+/// Sampler names and how long they took to run, see [SamplerChain::sample_timed].
+pub type SamplerTimings = Vec<(&'static str, Duration)>;
+
+/// State saved by a stateful [Sampler], see [Sampler::save_state]. It doesn't
+/// include RNG state or configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SamplerState {
+    /// Learning state of the Mirostat samplers.
+    Mirostat(MirostatState),
+    /// The last selected token of a token-picking sampler, if any.
+    Selected(Option<Logit>),
+    /// State of a nested [SamplerChain].
+    Chain(ChainSnapshot),
+}
+
+impl SamplerState {
+    /// Error for when a sampler named `name` can't load this state.
+    pub(crate) fn mismatch(&self, name: &str) -> SamplerError {
+        SamplerError::InternalError(format!("{name} can't load state {self:?}"))
+    }
+}
+
+/// Saved state of every sampler in a [SamplerChain], in order. Samplers
+/// without state have [None] entries. See [SamplerChain::snapshot].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChainSnapshot(pub Vec<Option<SamplerState>>);
 
 #[derive(Default, Debug)]
 /// A list of [Sampler]s that can be run in sequence. It implements `Sampler`
 /// so you can build samplers as modular components. A typical use case would
 /// be to have several filtering samplers and then a token-picking sampler as the last
 /// item to enable calling [Sampler::sample_token] on the chain.
+///
+/// The first token selected while running the chain is the one the chain reports, later
+/// samplers can't replace it. In strict mode (see [SamplerChain::strict]) a sampler
+/// selecting a token after one was already selected is an error.
+///
+/// Normally running the chain leaves the [Logits] filtered and reordered. In non-destructive
+/// mode (see [SamplerChain::non_destructive]) the original order is restored afterward and
+/// tokens that were removed are kept with a logit of negative infinity, so if the logits were
+/// built with [Logits::try_from_iter] the index still matches the token id.
 pub struct SamplerChain {
     samplers: Vec<Box<dyn Sampler>>,
     token: Option<TID>,
+    token_prob: Option<L>,
+    token_logit: Option<Logit>,
+    strict: bool,
+    non_destructive: bool,
 }
 
 impl SamplerChain {
@@ -20,14 +68,317 @@ impl SamplerChain {
         Self {
             samplers: vec![],
             token: None,
+            token_prob: None,
+            token_logit: None,
+            strict: false,
+            non_destructive: false,
         }
     }
 
-    pub fn push_sampler(&mut self, sampler: impl Sampler + Send + Sync + 'static) -> &mut Self {
+    /// In strict mode, running the chain fails with [SamplerError::ConflictingSelection]
+    /// if more than one sampler selects a token.
+    pub fn strict(mut self, val: bool) -> Self {
+        self.strict = val;
+        self
+    }
+
+    /// Sets strict mode in place. See [Self::strict].
+    pub fn set_strict(&mut self, val: bool) -> &mut Self {
+        self.strict = val;
+        self
+    }
+
+    /// In non-destructive mode the chain keeps the [Logits] at their original length
+    /// and order: removed tokens have their logit set to negative infinity rather than
+    /// being dropped. The sorted and softmax flags are cleared after running.
+    pub fn non_destructive(mut self, val: bool) -> Self {
+        self.non_destructive = val;
+        self
+    }
+
+    /// Sets non-destructive mode in place. See [Self::non_destructive].
+    pub fn set_non_destructive(&mut self, val: bool) -> &mut Self {
+        self.non_destructive = val;
+        self
+    }
+
+    /// Returns the probability of the last sampled token at the point
+    /// it was selected, if a token was selected.
+    pub fn sampled_probability(&self) -> Option<L> {
+        self.token.and(self.token_prob)
+    }
+
+    /// Returns the full [Logit] of the last sampled token as it was when the token
+    /// was selected, if a token was selected. See [Sampler::sampled_logit].
+    pub fn sampled_logit(&self) -> Option<Logit> {
+        self.token.and(self.token_logit)
+    }
+
+    fn token_probability(logits: &Logits, tid: TID) -> Option<L> {
+        let logit = logits.iter().find(|l| l.token_id == tid)?;
+        if logits.get_softmax() {
+            return Some(logit.prob);
+        }
+        let max_l = logits
+            .iter()
+            .map(|l| l.logit)
+            .fold(f32::NEG_INFINITY, f32::max);
+        let cum_sum = logits.iter().map(|l| (l.logit - max_l).exp()).sum::<L>();
+        Some((logit.logit - max_l).exp() / cum_sum)
+    }
+
+    /// Puts `logits` back in the token id order given by `order`, adding back
+    /// any removed tokens with a logit of negative infinity.
+    fn restore_order(logits: &mut Logits, order: &[TID]) {
+        let mut kept = logits
+            .drain(..)
+            .map(|l| (l.token_id, l))
+            .collect::<HashMap<_, _>>();
+        logits.extend(order.iter().map(|&token_id| {
+            kept.remove(&token_id).unwrap_or(Logit {
+                token_id,
+                logit: L::NEG_INFINITY,
+                prob: 0f32,
+            })
+        }));
+        logits.clear_flags();
+    }
+
+    pub fn push_sampler(&mut self, sampler: impl Sampler + 'static) -> &mut Self {
         self.token = None;
+        self.token_prob = None;
+        self.token_logit = None;
         self.samplers.push(Box::new(sampler));
         self
     }
+
+    /// Saves the state of the samplers in the chain (see [Sampler::save_state]) so
+    /// generation can be resumed later with [SamplerChain::restore].
+    pub fn snapshot(&self) -> ChainSnapshot {
+        ChainSnapshot(self.samplers.iter().map(|s| s.save_state()).collect())
+    }
+
+    /// Restores sampler state saved with [SamplerChain::snapshot]. The chain must
+    /// contain the same samplers in the same order as when the snapshot was taken.
+    pub fn restore(&mut self, snapshot: &ChainSnapshot) -> Result<()> {
+        if snapshot.0.len() != self.samplers.len() {
+            Err(SamplerError::InternalError(format!(
+                "Snapshot has {} samplers but the chain has {}",
+                snapshot.0.len(),
+                self.samplers.len()
+            )))?
+        }
+        self.samplers
+            .iter_mut()
+            .zip(snapshot.0.iter())
+            .filter_map(|(sampler, state)| Some((sampler, state.as_ref()?)))
+            .try_for_each(|(sampler, state)| sampler.load_state(state))
+    }
+
+    /// Returns an iterator over the [SamplerMetadata] of the samplers in the
+    /// chain, in the order they run. See [Sampler::metadata].
+    pub fn iter_metadata(&self) -> impl Iterator<Item = SamplerMetadata> + '_ {
+        self.samplers.iter().map(|sampler| sampler.metadata())
+    }
+
+    /// Runs the chain like [Sampler::sample] and additionally reports which
+    /// token ids each sampler removed. The result contains the index of the
+    /// sampler in the chain and the removed token ids for every sampler that
+    /// removed at least one token.
+    pub fn sample_collecting_removed(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &mut Logits,
+    ) -> Result<Vec<(usize, Vec<TID>)>> {
+        let mut removed = vec![];
+        self.sample_each(res, logits, |idx, sampler, res, logits| {
+            let before = logits.iter().map(|l| l.token_id).collect::<Vec<_>>();
+            sampler.sample(res, logits)?;
+            if logits.len() == before.len() {
+                return Ok(());
+            }
+            let after = logits.iter().map(|l| l.token_id).collect::<HashSet<_>>();
+            removed.push((
+                idx,
+                before
+                    .into_iter()
+                    .filter(|tid| !after.contains(tid))
+                    .collect(),
+            ));
+            Ok(())
+        })?;
+        Ok(removed)
+    }
+
+    /// Runs the chain like [Sampler::sample_token] and additionally measures how
+    /// long each sampler took. The result contains the selected token id and the
+    /// metadata name (see [Sampler::metadata]) and duration of every sampler in the order they
+    /// ran.
+    pub fn sample_timed(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &mut Logits,
+    ) -> Result<(Option<TID>, SamplerTimings)> {
+        let mut timings = Vec::with_capacity(self.samplers.len());
+        self.sample_each(res, logits, |_idx, sampler, res, logits| {
+            let start = Instant::now();
+            sampler.sample(res, logits)?;
+            timings.push((sampler.metadata().name, start.elapsed()));
+            Ok(())
+        })?;
+        Ok((self.token, timings))
+    }
+
+    /// Convenience method that builds [Logits] from a vector of probabilities
+    /// (see [Logits::from_probs]), runs the chain and returns the selected token id.
+    pub fn sample_from_probs(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        probs: &[L],
+    ) -> Result<Option<TID>> {
+        self.sample_token(res, &mut Logits::from_probs(probs)?)
+    }
+
+    /// Runs the chain on a copy of `logits` and returns the selected token id along
+    /// with the resulting logits, leaving `logits` unchanged. Note that the samplers
+    /// still run normally, so the RNG advances and stateful samplers update their state.
+    pub fn dry_run(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &Logits,
+    ) -> Result<(Option<TID>, Logits)> {
+        let mut result = logits.clone();
+        let tid = self.sample_token(res, &mut result)?;
+        Ok((tid, result))
+    }
+
+    /// Draws `n` tokens independently: each draw runs the chain on a copy of
+    /// `logits`, so they're left unchanged. The chain is reset (see [Sampler::reset])
+    /// before every draw so stateful samplers like Mirostat start from the same state.
+    /// Afterward the chain reports the result of the last draw.
+    pub fn sample_n(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &Logits,
+        n: usize,
+    ) -> Result<Vec<Option<TID>>> {
+        (0..n)
+            .map(|_| {
+                self.reset();
+                self.sample_token(res, &mut logits.clone())
+            })
+            .collect()
+    }
+
+    /// Runs a simple autoregressive generation loop: `next_logits` is called with the
+    /// last tokens from `res` to evaluate the model, then the chain selects a token
+    /// which is appended to the last tokens resource.
+    ///
+    /// Generation ends after `max_tokens` tokens, when `stop` returns `true` for the
+    /// selected token (the stop token is included in the result) or when the chain
+    /// doesn't select a token. Returns the generated tokens.
+    ///
+    /// `res` must provide the last tokens resource.
+    pub fn generate(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        next_logits: &mut dyn FnMut(&[TID]) -> Result<Logits>,
+        max_tokens: usize,
+        mut stop: impl FnMut(TID) -> bool,
+    ) -> Result<Vec<TID>> {
+        let mut generated = Vec::with_capacity(max_tokens);
+        while generated.len() < max_tokens {
+            let mut logits = None;
+            res.with_last_tokens(&mut |tokens| logits = Some(next_logits(tokens)))?;
+            let Some(mut logits) = logits.transpose()? else {
+                break;
+            };
+            let Some(tid) = self.sample_token(res, &mut logits)? else {
+                break;
+            };
+            res.with_last_tokens_mut(&mut |tokens| tokens.push(tid))?;
+            generated.push(tid);
+            if stop(tid) {
+                break;
+            }
+        }
+        Ok(generated)
+    }
+
+    /// Runs each sampler in the chain using `run`, keeping track of the
+    /// selected token.
+    fn sample_each<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+        run: impl FnMut(
+            usize,
+            &mut Box<dyn Sampler>,
+            &mut dyn HasSamplerResources,
+            &mut Logits,
+        ) -> Result<()>,
+    ) -> Result<&'a mut Logits> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("sampler_chain", samplers = self.samplers.len()).entered();
+
+        self.token = None;
+        self.token_prob = None;
+        self.token_logit = None;
+        let order = self
+            .non_destructive
+            .then(|| logits.iter().map(|l| l.token_id).collect::<Vec<_>>());
+        // The order is restored even if a sampler fails.
+        let result = self.run_samplers(res, logits, run);
+        if let Some(order) = order {
+            Self::restore_order(logits, &order);
+        }
+        result?;
+        Ok(logits)
+    }
+
+    /// Runs the samplers for [Self::sample_each].
+    fn run_samplers(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &mut Logits,
+        mut run: impl FnMut(
+            usize,
+            &mut Box<dyn Sampler>,
+            &mut dyn HasSamplerResources,
+            &mut Logits,
+        ) -> Result<()>,
+    ) -> Result<()> {
+        for (idx, sampler) in self.samplers.iter_mut().enumerate() {
+            run(idx, sampler, res, logits)?;
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                sampler = sampler.name(),
+                logits = logits.len(),
+                "sampler finished"
+            );
+            let Some(tid) = sampler.sampled_token_id() else {
+                continue;
+            };
+            if self.token.is_none() {
+                self.token = Some(tid);
+                self.token_prob = Self::token_probability(logits, tid);
+                self.token_logit = sampler
+                    .sampled_logit()
+                    .or_else(|| logits.iter().find(|l| l.token_id == tid).copied());
+                continue;
+            }
+            if self.strict {
+                Err(SamplerError::ConflictingSelection(idx))?
+            }
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                sampler = sampler.name(),
+                index = idx,
+                "sampler selected a token after one was already selected, ignoring it"
+            );
+        }
+        Ok(())
+    }
 }
 
 impl Sampler for SamplerChain {
@@ -35,20 +386,39 @@ impl Sampler for SamplerChain {
         &mut self,
         res: &mut dyn HasSamplerResources,
         logits: &'a mut Logits,
-    ) -> anyhow::Result<&'a mut Logits> {
-        self.token = None;
-        self.samplers
-            .iter_mut()
-            .try_fold(logits, |logits, sampler| {
-                let new_logits = sampler.sample(res, logits)?;
-                self.token = sampler.sampled_token_id();
-                Ok(new_logits)
-            })
+    ) -> Result<&'a mut Logits> {
+        self.sample_each(res, logits, |_idx, sampler, res, logits| {
+            sampler.sample(res, logits)?;
+            Ok(())
+        })
     }
 
     fn sampled_token_id(&self) -> Option<TID> {
         self.token
     }
+
+    fn sampled_logit(&self) -> Option<Logit> {
+        SamplerChain::sampled_logit(self)
+    }
+
+    /// Resets every sampler in the chain and clears the selected token.
+    fn reset(&mut self) {
+        self.token = None;
+        self.token_prob = None;
+        self.token_logit = None;
+        self.samplers.iter_mut().for_each(|sampler| sampler.reset());
+    }
+
+    fn save_state(&self) -> Option<SamplerState> {
+        Some(SamplerState::Chain(self.snapshot()))
+    }
+
+    fn load_state(&mut self, state: &SamplerState) -> Result<()> {
+        match state {
+            SamplerState::Chain(snapshot) => self.restore(snapshot),
+            _ => Err(state.mismatch(self.name()))?,
+        }
+    }
 }
 
 impl<Rhs> AddAssign<Rhs> for SamplerChain
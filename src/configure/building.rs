@@ -6,7 +6,7 @@ use std::{
 use anyhow::Result;
 use thiserror::Error;
 
-use crate::types::*;
+use crate::{samplers::*, types::*};
 
 use super::configurable::ConfigurableSampler;
 use super::*;
@@ -16,6 +16,9 @@ pub enum BuildSamplersError {
     #[error("unknown slot name {0}")]
     UnknownSlot(String),
 
+    #[error("unknown sampler name {0}")]
+    UnknownSampler(String),
+
     #[error("cannot configure static slot {0}")]
     CannotConfigureStatic(String),
 
@@ -249,6 +252,33 @@ where
         Ok(())
     }
 
+    /// Returns the option keys and types accepted when configuring the slot `name`
+    /// (see [Self::configure]), for example to implement completion. A fresh sampler is
+    /// built with the slot's factory to read the metadata, so this works for static
+    /// slots and empty slots too.
+    pub fn option_keys(
+        &mut self,
+        name: impl AsRef<str>,
+    ) -> Result<Vec<(&'static str, SamplerOptionType)>> {
+        let name = name.as_ref();
+        let (_, slot) = self
+            .slots
+            .iter_mut()
+            .find(|(slotname, _slot)| slotname == name)
+            .ok_or_else(|| BuildSamplersError::UnknownSlot(name.to_string()))?;
+        let factory = match slot {
+            SamplerSlot::Static { factory }
+            | SamplerSlot::Single { factory, .. }
+            | SamplerSlot::Chain { factory, .. } => factory,
+        };
+        Ok(factory()
+            .sampler_metadata()
+            .options
+            .into_iter()
+            .map(|md| (md.key, md.option_type))
+            .collect())
+    }
+
     pub fn into_chain(self) -> SamplerChain {
         let mut chain = SamplerChain::new();
 
@@ -265,4 +295,112 @@ where
         });
         chain
     }
+
+    /// Like [Self::into_chain] but calls [SamplerSlot::ensure_present] on
+    /// each slot first, so optional slots that were never configured
+    /// contribute their factory default rather than being left out.
+    pub fn into_chain_with_defaults(mut self) -> SamplerChain {
+        self.slots
+            .iter_mut()
+            .for_each(|(_name, slot)| slot.ensure_present());
+        self.into_chain()
+    }
+}
+
+impl SamplerChainBuilder<usize, L> {
+    /// Builds a [SamplerChainBuilder] with slots for the temperature sampling
+    /// order `llama.cpp` uses. The slot names are `flat_bias`, `repetition`,
+    /// `freq_presence`, `top_k`, `tail_free`, `locally_typical`, `top_p`,
+    /// `temperature` and `rand_distrib`.
+    ///
+    /// The samplers `llama.cpp` enables by default (`repetition`, `top_k`, `top_p`
+    /// and `temperature`) start out populated with their default settings.
+    /// The other slots are empty until configured. `rand_distrib` is static.
+    pub fn llama_cpp_defaults() -> Self {
+        Self::from([
+            (
+                "flat_bias",
+                Self::preset_slot(SampleFlatBias::default(), false),
+            ),
+            (
+                "repetition",
+                Self::preset_slot(SampleRepetition::default(), true),
+            ),
+            (
+                "freq_presence",
+                Self::preset_slot(SampleFreqPresence::default(), false),
+            ),
+            ("top_k", Self::preset_slot(SampleTopK::default(), true)),
+            (
+                "tail_free",
+                Self::preset_slot(SampleTailFree::default(), false),
+            ),
+            (
+                "locally_typical",
+                Self::preset_slot(SampleLocallyTypical::default(), false),
+            ),
+            ("top_p", Self::preset_slot(SampleTopP::default(), true)),
+            (
+                "temperature",
+                Self::preset_slot(SampleTemperature::new(0.8), true),
+            ),
+            (
+                "rand_distrib",
+                SamplerSlot::new_static(|| Box::new(SampleRandDistrib::new())),
+            ),
+        ])
+    }
+
+    /// Builds a [SamplerChainBuilder] with slots for Mirostat V1 sampling. The slot
+    /// names are `flat_bias`, `repetition`, `freq_presence`, `temperature` and `mirostat`.
+    ///
+    /// Only the `mirostat` slot starts out populated. Since Mirostat selects
+    /// the token, it is always the last slot.
+    pub fn mirostat_v1_defaults(n_vocab: usize, tau: L, eta: L) -> Self {
+        Self::mirostat_preset(Self::preset_slot(
+            SampleMirostat1::new(n_vocab, tau, eta),
+            true,
+        ))
+    }
+
+    /// Builds a [SamplerChainBuilder] with slots for Mirostat V2 sampling. The slot
+    /// names are `flat_bias`, `repetition`, `freq_presence`, `temperature` and `mirostat`.
+    ///
+    /// Only the `mirostat` slot starts out populated. Since Mirostat selects
+    /// the token, it is always the last slot.
+    pub fn mirostat_v2_defaults(tau: L, eta: L) -> Self {
+        Self::mirostat_preset(Self::preset_slot(SampleMirostat2::new(tau, eta), true))
+    }
+
+    fn mirostat_preset(mirostat: SamplerSlot<usize, L>) -> Self {
+        Self::from([
+            (
+                "flat_bias",
+                Self::preset_slot(SampleFlatBias::default(), false),
+            ),
+            (
+                "repetition",
+                Self::preset_slot(SampleRepetition::default(), false),
+            ),
+            (
+                "freq_presence",
+                Self::preset_slot(SampleFreqPresence::default(), false),
+            ),
+            (
+                "temperature",
+                Self::preset_slot(SampleTemperature::new(0.8), false),
+            ),
+            ("mirostat", mirostat),
+        ])
+    }
+
+    fn preset_slot(
+        sampler: impl BuildableSampler<usize, L> + Clone,
+        present: bool,
+    ) -> SamplerSlot<usize, L> {
+        SamplerSlot::Single {
+            sampler: present.then(|| Box::new(sampler.clone()) as Box<dyn BuildableSampler<_, _>>),
+            factory: Box::new(move || Box::new(sampler.clone())),
+        }
+    }
 }
@@ -53,7 +53,7 @@ where
     }
 
     /// Gets an option by name.
-    fn get_option(&self, key: &str) -> Result<SamplerOptionValue> {
+    fn get_option(&self, key: &str) -> Result<SamplerOptionValue<'_>> {
         configurable_sampler::get_option(self, key)
     }
 
@@ -72,6 +72,40 @@ where
         configurable_sampler::configure(self, s)?;
         Ok(())
     }
+
+    /// Like [Self::configure] but option keys must match exactly: prefixes
+    /// and blank keys are rejected. This is safer for automatically generated
+    /// configurations where a typo shouldn't silently set a different option.
+    fn configure_strict(&mut self, s: &str) -> Result<()> {
+        configurable_sampler::configure_strict(self, s)?;
+        Ok(())
+    }
+
+    /// Returns the option values of a default constructed instance of the sampler
+    /// as key/value pairs in option definition order, for example to reset
+    /// a UI to the defaults. Options that can't be read are skipped.
+    fn default_config(&self) -> Vec<(&'static str, SamplerOptionValue<'static>)>
+    where
+        Self: Default + Sized,
+    {
+        configurable_sampler::option_values(&Self::default())
+    }
+
+    /// Returns the current option values as a string in the format accepted
+    /// by [Self::configure], for example `k=40:min_keep=1`. Floats use the
+    /// shortest representation that parses back to the same value, so an `f32`
+    /// `0.8` is written as `0.8` rather than `0.800000011920929`. Options that
+    /// can't be read are skipped.
+    fn to_config_string(&self) -> String {
+        configurable_sampler::to_config_string(self)
+    }
+
+    /// Returns help text describing the sampler and its options, suitable
+    /// for command line `--help` output. Each option is listed on its own
+    /// line with its key, type and description.
+    fn help(&self) -> String {
+        configurable_sampler::help(self)
+    }
 }
 
 /// Since Rust traits don't allow calling base default methods from
@@ -161,7 +195,109 @@ pub mod configurable_sampler {
         })
     }
 
+    pub fn option_values<CS, UI, F>(slf: &CS) -> Vec<(&'static str, SamplerOptionValue<'static>)>
+    where
+        CS: ConfigurableSampler<UI, F> + HasSamplerMetadata<UI, F> + ?Sized,
+        UI: ConfigurableNumValue,
+        F: ConfigurableNumValue,
+    {
+        slf.sampler_metadata()
+            .options
+            .iter()
+            .filter_map(|omd| {
+                let val = match slf.get_option(omd.key).ok()? {
+                    SamplerOptionValue::UInt(v) => SamplerOptionValue::UInt(v),
+                    SamplerOptionValue::Float(v) => SamplerOptionValue::Float(v),
+                    SamplerOptionValue::Bool(v) => SamplerOptionValue::Bool(v),
+                    SamplerOptionValue::String(v) => {
+                        SamplerOptionValue::String(Cow::Owned(v.into_owned()))
+                    }
+                };
+                Some((omd.key, val))
+            })
+            .collect()
+    }
+
+    pub fn to_config_string<CS, UI, F>(slf: &CS) -> String
+    where
+        CS: ConfigurableSampler<UI, F> + HasSamplerMetadata<UI, F> + ?Sized,
+        UI: ConfigurableNumValue,
+        F: ConfigurableNumValue,
+    {
+        slf.sampler_options()
+            .iter()
+            .filter_map(|(omd, val)| {
+                let val = match val.as_ref()? {
+                    SamplerOptionValue::UInt(v) => <u64 as NumCast>::from(*v)?.to_string(),
+                    SamplerOptionValue::Float(v) => format_float(*v)?,
+                    SamplerOptionValue::Bool(v) => v.to_string(),
+                    SamplerOptionValue::String(v) => v.to_string(),
+                };
+                Some(format!("{}={val}", omd.key))
+            })
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+
+    /// Formats `v` with the shortest representation that still converts
+    /// back to the same value of type `F`.
+    fn format_float<F: ConfigurableNumValue>(v: F) -> Option<String> {
+        let v = <f64 as NumCast>::from(v)?;
+        if v.is_infinite() {
+            return Some(if v > 0.0 { "inf" } else { "-inf" }.to_string());
+        }
+        // Displaying as f32 avoids noise like 0.800000011920929 when F is f32.
+        let short = (v as f32).to_string();
+        let round_trips = short
+            .parse::<f64>()
+            .ok()
+            .and_then(F::from_f64)
+            .and_then(<f64 as NumCast>::from)
+            == Some(v);
+        Some(if round_trips { short } else { v.to_string() })
+    }
+
+    pub fn help<CS, UI, F>(slf: &CS) -> String
+    where
+        CS: ConfigurableSampler<UI, F> + HasSamplerMetadata<UI, F> + ?Sized,
+        UI: ConfigurableNumValue,
+        F: ConfigurableNumValue,
+    {
+        let md = slf.sampler_metadata();
+        let mut help = md.name.to_string();
+        if let Some(desc) = md.description {
+            help.push_str(": ");
+            help.push_str(desc);
+        }
+        md.options.iter().for_each(|omd| {
+            help.push_str(&format!("\n  {} ({})", omd.key, omd.option_type));
+            if let Some(desc) = omd.description {
+                help.push_str(": ");
+                help.push_str(desc);
+            }
+        });
+        help
+    }
+
     pub fn configure<CS, UI, F>(slf: &mut CS, s: &str) -> Result<()>
+    where
+        CS: ConfigurableSampler<UI, F> + HasSamplerMetadata<UI, F> + ?Sized,
+        UI: ConfigurableNumValue,
+        F: ConfigurableNumValue,
+    {
+        configure_ll(slf, s, false)
+    }
+
+    pub fn configure_strict<CS, UI, F>(slf: &mut CS, s: &str) -> Result<()>
+    where
+        CS: ConfigurableSampler<UI, F> + HasSamplerMetadata<UI, F> + ?Sized,
+        UI: ConfigurableNumValue,
+        F: ConfigurableNumValue,
+    {
+        configure_ll(slf, s, true)
+    }
+
+    fn configure_ll<CS, UI, F>(slf: &mut CS, s: &str, exact: bool) -> Result<()>
     where
         CS: ConfigurableSampler<UI, F> + HasSamplerMetadata<UI, F> + ?Sized,
         UI: ConfigurableNumValue,
@@ -178,7 +314,12 @@ pub mod configurable_sampler {
             .filter(|s| !s.is_empty())
             .try_for_each(|kv| {
                 let (k, v) = kv.split_once('=').unwrap_or(("", kv));
-                let (omd, Some(_)) = opts.find_option_definition(k)? else {
+                let found = if exact {
+                    opts.find_option_definition_exact(k)?
+                } else {
+                    opts.find_option_definition(k)?
+                };
+                let (omd, Some(_)) = found else {
                     Err(ConfigureSamplerError::UnknownOrBadType(k.to_string()))?
                 };
 
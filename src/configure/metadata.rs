@@ -87,7 +87,39 @@ impl<T> SamplerOptions<T> {
 
         Ok((optdef.clone(), optidx))
     }
+
+    /// Like [Self::find_option_definition] but the key must match
+    /// an option name exactly rather than being a prefix.
+    pub fn find_option_definition_exact(
+        &self,
+        key: &str,
+    ) -> Result<(SamplerOptionMetadata, Option<usize>)> {
+        let key = key.trim();
+        self.iter()
+            .enumerate()
+            .find(|(_idx, (omd, _acc))| omd.key == key)
+            .map(|(idx, (omd, acc))| (omd.clone(), acc.is_some().then_some(idx)))
+            .ok_or_else(|| {
+                ConfigureSamplerError::UnknownOrBadType(if key.is_empty() {
+                    "<unspecified>".to_string()
+                } else {
+                    key.to_string()
+                })
+                .into()
+            })
+    }
+}
+
+/// Implements [Sampler::metadata](crate::types::Sampler::metadata) inside an
+/// `impl Sampler` block by returning the sampler's [HasSamplerMetadata] metadata.
+macro_rules! sampler_metadata_from_options {
+    () => {
+        fn metadata(&self) -> $crate::configure::SamplerMetadata {
+            $crate::configure::HasSamplerMetadata::<usize, $crate::types::L>::sampler_metadata(self)
+        }
+    };
 }
+pub(crate) use sampler_metadata_from_options;
 
 /// Configurable samplers will need to implement this trait. It provides
 /// metadata for a sampler like its name, description as well as a list of
@@ -115,27 +147,3 @@ where
         SamplerOptions::default()
     }
 }
-
-// /// Convenience trait for samplers that supply metadata.
-// ///
-// /// This takes four type arguments because a sampler's option types
-// /// don't necessarily have to match its tokenid/logit types.
-// pub trait SamplerWithMetadata<TID = u32, L = f32, UI = usize, F = f32>:
-//     Sampler<TID, L> + HasSamplerMetadata<UI, F>
-// where
-//     TID: CanTokenId,
-//     F: CanLogit,
-//     UI: ConfigurableNumValue,
-//     F: ConfigurableNumValue,
-// {
-// }
-
-// impl<T, TID, L, UI, F> SamplerWithMetadata<TID, L, UI, F> for T
-// where
-//     Self: Sampler<TID, L> + HasSamplerMetadata<UI, F>,
-//     TID: CanTokenId,
-//     F: CanLogit,
-//     UI: ConfigurableNumValue,
-//     F: ConfigurableNumValue,
-// {
-// }
@@ -15,12 +15,13 @@
 mod building;
 mod configurable;
 mod metadata;
+mod registry;
 mod value;
 
 use thiserror::Error;
 
 #[doc(inline)]
-pub use self::{building::*, configurable::*, metadata::*, value::*};
+pub use self::{building::*, configurable::*, metadata::*, registry::*, value::*};
 
 /// Sampler option handling errors.
 #[derive(Debug, Error, Clone, PartialEq)]
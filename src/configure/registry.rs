@@ -0,0 +1,135 @@
+use std::fmt::Debug;
+
+use anyhow::Result;
+
+use crate::{samplers::*, types::*};
+
+use super::*;
+
+/// Maps sampler names to factories so a single sampler can be created,
+/// configured and run by name without building a chain.
+pub struct SamplerRegistry<UI, F> {
+    factories: Vec<(String, Box<SamplerFactory<UI, F>>)>,
+}
+
+impl<UI, F> Debug for SamplerRegistry<UI, F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.factories.iter().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+impl<UI, F> Default for SamplerRegistry<UI, F> {
+    fn default() -> Self {
+        Self {
+            factories: Default::default(),
+        }
+    }
+}
+
+impl<UI, F> SamplerRegistry<UI, F>
+where
+    UI: ConfigurableNumValue,
+    F: ConfigurableNumValue,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a sampler factory under `name`, replacing any existing
+    /// factory with the same name.
+    pub fn register(
+        &mut self,
+        name: impl AsRef<str>,
+        factory: impl FnMut() -> Box<dyn BuildableSampler<UI, F>> + 'static,
+    ) -> &mut Self {
+        let name = name.as_ref();
+        let factory = Box::new(factory) as Box<SamplerFactory<UI, F>>;
+        match self.factories.iter_mut().find(|(n, _)| n == name) {
+            Some((_, existing)) => *existing = factory,
+            None => self.factories.push((name.to_string(), factory)),
+        }
+        self
+    }
+
+    /// Returns the registered sampler names.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.factories.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Builds a fresh sampler registered as `name` and configures it
+    /// using `config` (see [ConfigurableSampler::configure]).
+    pub fn build(
+        &mut self,
+        name: impl AsRef<str>,
+        config: impl AsRef<str>,
+    ) -> Result<Box<dyn BuildableSampler<UI, F>>> {
+        let name = name.as_ref();
+        let (_, factory) = self
+            .factories
+            .iter_mut()
+            .find(|(n, _)| n == name)
+            .ok_or_else(|| BuildSamplersError::UnknownSampler(name.to_string()))?;
+        let mut sampler = factory();
+        sampler
+            .configure(config.as_ref())
+            .map_err(|err| BuildSamplersError::ConfigureFailed {
+                name: name.to_string(),
+                err,
+            })?;
+        Ok(sampler)
+    }
+
+    /// Builds the sampler registered as `name`, configures it using `config` and
+    /// runs it on `logits`. Returns the selected token id, if the sampler selects one.
+    pub fn apply(
+        &mut self,
+        name: impl AsRef<str>,
+        config: impl AsRef<str>,
+        res: &mut dyn HasSamplerResources,
+        logits: &mut Logits,
+    ) -> Result<Option<TID>> {
+        self.build(name, config)?.sample_token(res, logits)
+    }
+}
+
+impl SamplerRegistry<usize, L> {
+    /// Builds a [SamplerRegistry] containing the built in samplers with their
+    /// default settings. The names are `clamp`, `flat_bias`, `freq_presence`, `greedy`,
+    /// `locally_typical`, `min_p`, `mirostat1`, `mirostat2`, `penalties`, `percentile`,
+    /// `rand_distrib`, `repetition`, `scale`, `sequence_repetition`, `tail_free`,
+    /// `temperature`, `top_a`, `top_k`, `top_p`, `truncate` and `windowed_repetition`.
+    ///
+    /// Note that `mirostat1` requires `n_vocab` to be configured.
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry
+            .register("clamp", || Box::<SampleClamp>::default())
+            .register("flat_bias", || Box::<SampleFlatBias>::default())
+            .register("freq_presence", || Box::<SampleFreqPresence>::default())
+            .register("greedy", || Box::<SampleGreedy>::default())
+            .register("locally_typical", || Box::<SampleLocallyTypical>::default())
+            .register("min_p", || Box::<SampleMinP>::default())
+            .register("mirostat1", || Box::<SampleMirostat1>::default())
+            .register("mirostat2", || Box::<SampleMirostat2>::default())
+            .register("penalties", || Box::<SamplePenalties>::default())
+            .register("percentile", || Box::<SamplePercentile>::default())
+            .register("rand_distrib", || Box::<SampleRandDistrib>::default())
+            .register("repetition", || Box::<SampleRepetition>::default())
+            .register("scale", || Box::<SampleScale>::default())
+            .register("sequence_repetition", || {
+                Box::<SampleSeqRepetition>::default()
+            })
+            .register("tail_free", || Box::<SampleTailFree>::default())
+            .register("temperature", || Box::<SampleTemperature>::default())
+            .register("top_a", || Box::<SampleTopA>::default())
+            .register("top_k", || Box::<SampleTopK>::default())
+            .register("top_p", || Box::<SampleTopP>::default())
+            .register("truncate", || Box::<SampleTruncate>::default())
+            .register("windowed_repetition", || {
+                Box::<SampleWindowedRepetition>::default()
+            });
+        registry
+    }
+}
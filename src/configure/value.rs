@@ -55,6 +55,17 @@ pub enum SamplerOptionType {
     String,
 }
 
+impl std::fmt::Display for SamplerOptionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::UInt => "uint",
+            Self::Float => "float",
+            Self::Bool => "bool",
+            Self::String => "string",
+        })
+    }
+}
+
 /// Numeric values that can be used for configuring samplers.
 pub trait ConfigurableNumValue: 'static + Copy + NumCast + FromPrimitive {}
 impl<T> ConfigurableNumValue for T where T: 'static + Copy + NumCast + FromPrimitive {}
@@ -63,7 +74,8 @@ impl<'a> SamplerOptionValue<'a> {
     /// Try to parse a string reference to an option value.
     ///
     /// Float options allow specifying `-inf` for negative infinity
-    /// and `inf` or `+inf` for infinity.
+    /// and `inf` or `+inf` for infinity. A trailing `%` divides the value
+    /// by 100, so `90%` is the same as `0.9`.
     pub fn parse_value(typ: SamplerOptionType, s: impl AsRef<str>) -> Result<Self> {
         let s = s.as_ref();
         match typ {
@@ -79,7 +91,15 @@ impl<'a> SamplerOptionValue<'a> {
     }
 
     fn parse_float(s: &str) -> Result<f64> {
-        Ok(match s.trim() {
+        let s = s.trim();
+        if let Some(pct) = s.strip_suffix('%') {
+            return Ok(Self::parse_float_ll(pct.trim_end())? / 100.0);
+        }
+        Self::parse_float_ll(s)
+    }
+
+    fn parse_float_ll(s: &str) -> Result<f64> {
+        Ok(match s {
             "-inf" | "-INF" => f64::neg_infinity(),
             "inf" | "INF" | "+inf" | "+INF" => f64::infinity(),
             other => f64::from_str(other)?,
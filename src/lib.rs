@@ -95,6 +95,13 @@
 //! # }
 //! ```
 //!
+//! ## Token id and logit types
+//!
+//! Token ids are always [u32] ([crate::types::TID]) and logits are always [f32]
+//! ([crate::types::L]). The samplers are not generic over these types, so it isn't
+//! possible to build something like an all-[f64] chain. If your model produces
+//! another type, convert the values when building [crate::types::Logits].
+//!
 //! ## Suggested chains/ordering
 //! Suggestions based on the way `llama.cpp` does it.
 //!
@@ -147,7 +154,6 @@ mod tests;
 pub mod prelude {
     #[doc(inline)]
     pub use crate::{
-        chain::*,
         configure::{ConfigurableSampler, ConfigureSamplerError},
         resource::*,
         samplers::*,
@@ -1,4 +1,10 @@
-use std::fmt::Debug;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use rand::{rngs::StdRng, SeedableRng};
 
 use crate::types::{SamplerError, TID};
 
@@ -12,6 +18,21 @@ pub trait HasSamplerResources: Debug {
         Err(SamplerError::MissingResource("rng".to_string()))
     }
 
+    /// Allows a sampler to access a source of uniformly distributed `f64` values
+    /// in the range `[0, 1)`. This is an alternative to [Self::with_rng_mut] for
+    /// environments where providing a [rand::RngCore] is awkward, for example
+    /// when the entropy comes from JavaScript in WASM.
+    ///
+    /// The default implementation derives the values from [Self::with_rng_mut].
+    fn with_rand_f64(
+        &mut self,
+        fun: &mut dyn FnMut(&mut dyn FnMut() -> f64),
+    ) -> Result<(), SamplerError> {
+        use rand::Rng;
+
+        self.with_rng_mut(&mut |rng| fun(&mut || rng.gen::<f64>()))
+    }
+
     /// Allows a sampler to immutably access the last tokens (if present).
     fn with_last_tokens(&self, _fun: &mut dyn FnMut(&[TID])) -> Result<(), SamplerError> {
         Err(SamplerError::MissingResource("last_tokens".to_string()))
@@ -24,8 +45,47 @@ pub trait HasSamplerResources: Debug {
     ) -> Result<(), SamplerError> {
         Err(SamplerError::MissingResource("last_tokens".to_string()))
     }
+
+    /// Allows a sampler to immutably access only the tokens that were generated,
+    /// i.e. the last tokens after the prompt (if present).
+    fn with_generated_tokens(&self, _fun: &mut dyn FnMut(&[TID])) -> Result<(), SamplerError> {
+        Err(SamplerError::MissingResource(
+            "generated_tokens".to_string(),
+        ))
+    }
+
+    /// Allows a sampler to access precomputed occurrence counts for the last `last_n`
+    /// tokens (if present). Penalty samplers use this to avoid recounting the same
+    /// window and fall back to [Self::with_last_tokens] when it's unavailable.
+    ///
+    /// Implementations should return an error rather than counts for a different window size.
+    fn with_token_counts(
+        &self,
+        _last_n: usize,
+        _fun: &mut dyn FnMut(&HashMap<TID, usize>),
+    ) -> Result<(), SamplerError> {
+        Err(SamplerError::MissingResource("token_counts".to_string()))
+    }
+
+    /// Allows a sampler to access the current generation step count.
+    ///
+    /// The default implementation always reports step `0`.
+    fn with_step_count(&self, fun: &mut dyn FnMut(usize)) -> Result<(), SamplerError> {
+        fun(0);
+        Ok(())
+    }
+
+    /// Allows a sampler to access the bytes token `tid` decodes to (if a
+    /// token decoder is present).
+    fn with_token_bytes(&self, _tid: TID, _fun: &mut dyn FnMut(&[u8])) -> Result<(), SamplerError> {
+        Err(SamplerError::MissingResource("token_decoder".to_string()))
+    }
 }
 
+/// Function mapping a token id to the bytes it decodes to, see
+/// [HasSamplerResources::with_token_bytes].
+pub type TokenDecoder = Arc<dyn Fn(TID) -> Vec<u8> + Send + Sync>;
+
 #[derive(Debug, Clone, Default)]
 /// Empty resource structure for use with samplers that don't require
 /// any resources.
@@ -41,11 +101,31 @@ impl HasSamplerResources for NilSamplerResources {}
 
 impl HasSamplerResources for () {}
 
+/// Resources that only provide a borrowed slice of last tokens. Used
+/// to implement the `sample_with_tokens` convenience methods.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TokenSliceSamplerResources<'a>(pub(crate) &'a [TID]);
+
+impl<'a> HasSamplerResources for TokenSliceSamplerResources<'a> {
+    fn with_last_tokens(&self, fun: &mut dyn FnMut(&[TID])) -> Result<(), SamplerError> {
+        fun(self.0);
+        Ok(())
+    }
+}
+
 /// Simple resources that can provide an RNG and/or last tokens to samplers.
 pub struct SimpleSamplerResources {
     pub(crate) rng: Option<Box<dyn rand::RngCore + Send + Sync>>,
 
     pub(crate) last_tokens: Option<Vec<TID>>,
+
+    pub(crate) prompt_len: usize,
+
+    pub(crate) step_count: usize,
+
+    pub(crate) token_counts: Option<(usize, HashMap<TID, usize>)>,
+
+    pub(crate) token_decoder: Option<TokenDecoder>,
 }
 
 impl Debug for SimpleSamplerResources {
@@ -53,6 +133,10 @@ impl Debug for SimpleSamplerResources {
         f.debug_struct("SamplerResources")
             .field("rng", &self.rng.is_some())
             .field("last_tokens", &self.last_tokens)
+            .field("prompt_len", &self.prompt_len)
+            .field("step_count", &self.step_count)
+            .field("token_counts", &self.token_counts.as_ref().map(|(n, _)| n))
+            .field("token_decoder", &self.token_decoder.is_some())
             .finish()
     }
 }
@@ -62,7 +146,62 @@ impl SimpleSamplerResources {
         rng: Option<Box<dyn rand::RngCore + Send + Sync>>,
         last_tokens: Option<Vec<TID>>,
     ) -> Self {
-        Self { rng, last_tokens }
+        Self {
+            rng,
+            last_tokens,
+            prompt_len: 0,
+            step_count: 0,
+            token_counts: None,
+            token_decoder: None,
+        }
+    }
+
+    /// Sets the number of tokens at the start of `last_tokens` that
+    /// belong to the prompt. Tokens after this boundary are considered
+    /// generated.
+    pub fn prompt_len(mut self, val: usize) -> Self {
+        self.prompt_len = val;
+        self
+    }
+
+    /// Sets the prompt length in place. See [Self::prompt_len].
+    pub fn set_prompt_len(&mut self, val: usize) -> &mut Self {
+        self.prompt_len = val;
+        self
+    }
+
+    /// Sets the function used to decode token ids to bytes. See
+    /// [HasSamplerResources::with_token_bytes].
+    pub fn token_decoder(mut self, fun: impl Fn(TID) -> Vec<u8> + Send + Sync + 'static) -> Self {
+        self.token_decoder = Some(Arc::new(fun));
+        self
+    }
+
+    /// Sets the current step count in place.
+    pub fn set_step_count(&mut self, val: usize) -> &mut Self {
+        self.step_count = val;
+        self
+    }
+
+    /// Advances the step count by one. Call this after each token is generated.
+    pub fn increment_step(&mut self) -> &mut Self {
+        self.step_count += 1;
+        self
+    }
+
+    /// Counts the occurrences of each token in the last `last_n` tokens so
+    /// penalty samplers using the same window can share them. See
+    /// [HasSamplerResources::with_token_counts]. The counts are discarded
+    /// when the last tokens are modified, so call this once per step.
+    pub fn precompute_token_counts(&mut self, last_n: usize) -> &mut Self {
+        self.token_counts = self.last_tokens.as_ref().map(|lt| {
+            let mut counts = HashMap::new();
+            lt[lt.len().saturating_sub(last_n)..]
+                .iter()
+                .for_each(|tid| *counts.entry(*tid).or_insert(0) += 1);
+            (last_n, counts)
+        });
+        self
     }
 }
 
@@ -94,6 +233,7 @@ impl HasSamplerResources for SimpleSamplerResources {
         &mut self,
         fun: &mut dyn FnMut(&mut Vec<TID>),
     ) -> Result<(), SamplerError> {
+        self.token_counts = None;
         self.last_tokens.as_mut().map_or_else(
             || Err(SamplerError::MissingResource("last_tokens".to_string())),
             |lt| {
@@ -102,4 +242,274 @@ impl HasSamplerResources for SimpleSamplerResources {
             },
         )
     }
+
+    fn with_generated_tokens(&self, fun: &mut dyn FnMut(&[TID])) -> Result<(), SamplerError> {
+        self.last_tokens.as_ref().map_or_else(
+            || Err(SamplerError::MissingResource("last_tokens".to_string())),
+            |lt| {
+                fun(&lt[self.prompt_len.min(lt.len())..]);
+                Ok(())
+            },
+        )
+    }
+
+    fn with_token_counts(
+        &self,
+        last_n: usize,
+        fun: &mut dyn FnMut(&HashMap<TID, usize>),
+    ) -> Result<(), SamplerError> {
+        match &self.token_counts {
+            Some((n, counts)) if *n == last_n => {
+                fun(counts);
+                Ok(())
+            }
+            _ => Err(SamplerError::MissingResource("token_counts".to_string())),
+        }
+    }
+
+    fn with_step_count(&self, fun: &mut dyn FnMut(usize)) -> Result<(), SamplerError> {
+        fun(self.step_count);
+        Ok(())
+    }
+
+    fn with_token_bytes(&self, tid: TID, fun: &mut dyn FnMut(&[u8])) -> Result<(), SamplerError> {
+        let decoder = self
+            .token_decoder
+            .as_ref()
+            .ok_or_else(|| SamplerError::MissingResource("token_decoder".to_string()))?;
+        fun(&decoder(tid));
+        Ok(())
+    }
+}
+
+/// Resources with a seeded RNG that can be reseeded later. This is useful when
+/// the seed needs to be logged or set per request for reproducibility.
+pub struct SeedableSamplerResources {
+    pub(crate) seed: u64,
+
+    pub(crate) rng: StdRng,
+
+    pub(crate) last_tokens: Option<Vec<TID>>,
+}
+
+impl Debug for SeedableSamplerResources {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SeedableSamplerResources")
+            .field("seed", &self.seed)
+            .field("last_tokens", &self.last_tokens)
+            .finish()
+    }
+}
+
+impl SeedableSamplerResources {
+    pub fn new(seed: u64, last_tokens: Option<Vec<TID>>) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            last_tokens,
+        }
+    }
+
+    /// Replaces the RNG with a new one seeded with `seed`.
+    pub fn reseed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Returns the seed the RNG was last seeded with.
+    pub fn current_seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl HasSamplerResources for SeedableSamplerResources {
+    fn with_rng_mut(
+        &mut self,
+        fun: &mut dyn FnMut(&mut dyn rand::RngCore),
+    ) -> Result<(), SamplerError> {
+        fun(&mut self.rng);
+        Ok(())
+    }
+
+    fn with_last_tokens(&self, fun: &mut dyn FnMut(&[TID])) -> Result<(), SamplerError> {
+        self.last_tokens.as_ref().map_or_else(
+            || Err(SamplerError::MissingResource("last_tokens".to_string())),
+            |lt| {
+                fun(lt);
+                Ok(())
+            },
+        )
+    }
+
+    fn with_last_tokens_mut(
+        &mut self,
+        fun: &mut dyn FnMut(&mut Vec<TID>),
+    ) -> Result<(), SamplerError> {
+        self.last_tokens.as_mut().map_or_else(
+            || Err(SamplerError::MissingResource("last_tokens".to_string())),
+            |lt| {
+                fun(lt);
+                Ok(())
+            },
+        )
+    }
+}
+
+/// Shared RNG handle used by [SharedTokenResources].
+pub type SharedRng = Arc<Mutex<Box<dyn rand::RngCore + Send + Sync>>>;
+
+/// Resources where the last tokens (and optionally the RNG) live behind shared
+/// handles. Other threads can append tokens through the handle returned by
+/// [SharedTokenResources::last_tokens_handle] and samplers will see them. Cloning
+/// the resources shares the same last tokens and RNG.
+///
+/// Accessing a resource behind a poisoned lock fails with [SamplerError::InternalError].
+#[derive(Clone, Default)]
+pub struct SharedTokenResources {
+    pub(crate) rng: Option<SharedRng>,
+
+    pub(crate) last_tokens: Arc<RwLock<Vec<TID>>>,
+}
+
+impl Debug for SharedTokenResources {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedTokenResources")
+            .field("rng", &self.rng.is_some())
+            .field("last_tokens", &self.last_tokens)
+            .finish()
+    }
+}
+
+impl SharedTokenResources {
+    pub fn new(rng: Option<SharedRng>, last_tokens: Arc<RwLock<Vec<TID>>>) -> Self {
+        Self { rng, last_tokens }
+    }
+
+    /// Builds shared resources from an owned RNG and last tokens.
+    pub fn from_parts(
+        rng: Option<Box<dyn rand::RngCore + Send + Sync>>,
+        last_tokens: Vec<TID>,
+    ) -> Self {
+        Self::new(
+            rng.map(|rng| Arc::new(Mutex::new(rng))),
+            Arc::new(RwLock::new(last_tokens)),
+        )
+    }
+
+    /// Returns a handle to the shared last tokens.
+    pub fn last_tokens_handle(&self) -> Arc<RwLock<Vec<TID>>> {
+        self.last_tokens.clone()
+    }
+
+    /// Returns a handle to the shared RNG, if present.
+    pub fn rng_handle(&self) -> Option<SharedRng> {
+        self.rng.clone()
+    }
+
+    fn poisoned(resource: &str) -> SamplerError {
+        SamplerError::InternalError(format!("{resource} lock is poisoned"))
+    }
+}
+
+impl HasSamplerResources for SharedTokenResources {
+    fn with_rng_mut(
+        &mut self,
+        fun: &mut dyn FnMut(&mut dyn rand::RngCore),
+    ) -> Result<(), SamplerError> {
+        let rng = self
+            .rng
+            .as_ref()
+            .ok_or_else(|| SamplerError::MissingResource("rng".to_string()))?;
+        let mut rng = rng.lock().map_err(|_| Self::poisoned("rng"))?;
+        fun(rng.as_mut());
+        Ok(())
+    }
+
+    fn with_last_tokens(&self, fun: &mut dyn FnMut(&[TID])) -> Result<(), SamplerError> {
+        let tokens = self
+            .last_tokens
+            .read()
+            .map_err(|_| Self::poisoned("last_tokens"))?;
+        fun(&tokens);
+        Ok(())
+    }
+
+    fn with_last_tokens_mut(
+        &mut self,
+        fun: &mut dyn FnMut(&mut Vec<TID>),
+    ) -> Result<(), SamplerError> {
+        let mut tokens = self
+            .last_tokens
+            .write()
+            .map_err(|_| Self::poisoned("last_tokens"))?;
+        fun(&mut tokens);
+        Ok(())
+    }
+}
+
+/// Resources that combine two providers: the RNG comes from `rng` and everything
+/// else (last tokens, token counts, step count and so on) comes from `tokens`. This
+/// allows mixing, for example, a shared RNG with a per-request token buffer.
+#[derive(Debug)]
+pub struct CompositeSamplerResources<'a> {
+    pub(crate) rng: &'a mut dyn HasSamplerResources,
+
+    pub(crate) tokens: &'a mut dyn HasSamplerResources,
+}
+
+impl<'a> CompositeSamplerResources<'a> {
+    pub fn new(
+        rng: &'a mut dyn HasSamplerResources,
+        tokens: &'a mut dyn HasSamplerResources,
+    ) -> Self {
+        Self { rng, tokens }
+    }
+}
+
+impl<'a> HasSamplerResources for CompositeSamplerResources<'a> {
+    fn with_rng_mut(
+        &mut self,
+        fun: &mut dyn FnMut(&mut dyn rand::RngCore),
+    ) -> Result<(), SamplerError> {
+        self.rng.with_rng_mut(fun)
+    }
+
+    fn with_rand_f64(
+        &mut self,
+        fun: &mut dyn FnMut(&mut dyn FnMut() -> f64),
+    ) -> Result<(), SamplerError> {
+        self.rng.with_rand_f64(fun)
+    }
+
+    fn with_last_tokens(&self, fun: &mut dyn FnMut(&[TID])) -> Result<(), SamplerError> {
+        self.tokens.with_last_tokens(fun)
+    }
+
+    fn with_last_tokens_mut(
+        &mut self,
+        fun: &mut dyn FnMut(&mut Vec<TID>),
+    ) -> Result<(), SamplerError> {
+        self.tokens.with_last_tokens_mut(fun)
+    }
+
+    fn with_generated_tokens(&self, fun: &mut dyn FnMut(&[TID])) -> Result<(), SamplerError> {
+        self.tokens.with_generated_tokens(fun)
+    }
+
+    fn with_token_counts(
+        &self,
+        last_n: usize,
+        fun: &mut dyn FnMut(&HashMap<TID, usize>),
+    ) -> Result<(), SamplerError> {
+        self.tokens.with_token_counts(last_n, fun)
+    }
+
+    fn with_step_count(&self, fun: &mut dyn FnMut(usize)) -> Result<(), SamplerError> {
+        self.tokens.with_step_count(fun)
+    }
+
+    fn with_token_bytes(&self, tid: TID, fun: &mut dyn FnMut(&[u8])) -> Result<(), SamplerError> {
+        self.tokens.with_token_bytes(tid, fun)
+    }
 }
@@ -0,0 +1,117 @@
+use crate::{configure::*, types::*};
+
+/// # Clamp sampling
+/// Clamps every logit into the range `[min, max]`. This can be used as a
+/// stabilizer to guard against extreme outliers from a misbehaving model
+/// before running other samplers. Entries with a NaN logit are left alone.
+///
+/// **Properties**:
+/// - Modifies logits
+///
+/// **Parameters**:
+/// - `min`: Lowest allowed logit value. (default: `-inf`)
+/// - `max`: Highest allowed logit value. (default: `inf`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleClamp {
+    pub(crate) min: L,
+    pub(crate) max: L,
+}
+
+impl Default for SampleClamp {
+    fn default() -> Self {
+        Self {
+            min: L::NEG_INFINITY,
+            max: L::INFINITY,
+        }
+    }
+}
+
+impl SampleClamp {
+    pub fn new(min: L, max: L) -> Self {
+        Self { min, max }
+    }
+
+    pub fn min(mut self, val: L) -> Self {
+        self.min = val;
+        self
+    }
+
+    pub fn max(mut self, val: L) -> Self {
+        self.max = val;
+        self
+    }
+}
+
+impl Sampler for SampleClamp {
+    fn sample<'a>(
+        &mut self,
+        _res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        let Self { min, max } = *self;
+        let mut changed = 0;
+
+        logits.iter_mut().for_each(|l| {
+            // Unlike L::clamp this doesn't panic if min is greater than max.
+            let clamped = l.logit.max(min).min(max);
+            if clamped != l.logit && !l.logit.is_nan() {
+                l.logit = clamped;
+                changed += 1;
+            }
+        });
+        if changed > 0 {
+            // Clamping can't change the relative order, so only the softmax is invalid.
+            logits.set_softmax(false);
+        }
+        Ok(logits)
+    }
+
+    sampler_metadata_from_options!();
+}
+
+impl ConfigurableSampler<usize, L> for SampleClamp {}
+
+impl HasSamplerMetadata<usize, L> for SampleClamp {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "clamp",
+            description: Some("Clamps every logit into the range [min, max]."),
+            options: vec![
+                SamplerOptionMetadata {
+                    key: "min",
+                    description: Some("Lowest allowed logit value."),
+                    option_type: SamplerOptionType::Float,
+                },
+                SamplerOptionMetadata {
+                    key: "max",
+                    description: Some("Highest allowed logit value."),
+                    option_type: SamplerOptionType::Float,
+                },
+            ],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValueMut::Float(&mut self.min)),
+                    Some(SamplerOptionValueMut::Float(&mut self.max)),
+                ],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValue::Float(self.min)),
+                    Some(SamplerOptionValue::Float(self.max)),
+                ],
+            )
+        }
+    }
+}
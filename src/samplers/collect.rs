@@ -0,0 +1,74 @@
+use crate::{configure::*, types::*};
+
+/// # Collect sampling
+/// Records the token ids and probabilities of the current logits without
+/// selecting a token or otherwise changing them. Placed at the end of a
+/// [crate::prelude::SamplerChain] this allows inspecting the distribution
+/// that survived the filtering samplers. The entries are ordered by
+/// probability, highest first.
+///
+/// Since the chain owns its samplers, you'll generally want to share this
+/// one using `Arc<Mutex<_>>` to read the result back.
+///
+/// **Properties**:
+/// - Sorts logits
+///
+/// **Parameters**:
+/// - (none)
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SampleCollect {
+    collected: Option<Vec<(TID, L)>>,
+}
+
+impl SampleCollect {
+    pub fn new() -> Self {
+        Self { collected: None }
+    }
+
+    /// Returns the token ids and probabilities recorded by the last run, if any.
+    pub fn collected(&self) -> Option<Vec<(TID, L)>> {
+        self.collected.clone()
+    }
+}
+
+impl Sampler for SampleCollect {
+    fn sample<'a>(
+        &mut self,
+        _res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        logits.ensure_softmax()?;
+        self.collected = Some(logits.iter().map(|l| (l.token_id, l.prob)).collect());
+        Ok(logits)
+    }
+
+    fn reset(&mut self) {
+        self.collected = None;
+    }
+
+    sampler_metadata_from_options!();
+}
+
+impl<UI, F> ConfigurableSampler<UI, F> for SampleCollect
+where
+    UI: ConfigurableNumValue,
+    F: ConfigurableNumValue,
+{
+}
+
+impl<UI, F> HasSamplerMetadata<UI, F> for SampleCollect
+where
+    UI: ConfigurableNumValue,
+    F: ConfigurableNumValue,
+{
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "collect",
+            description: Some(concat!(
+                "Records the token ids and probabilities of the current logits ",
+                "without selecting a token."
+            )),
+            options: vec![],
+        }
+    }
+}
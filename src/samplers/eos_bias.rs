@@ -0,0 +1,170 @@
+use crate::{configure::*, types::*};
+
+/// # EOS bias sampling
+/// Boosts or forces the end-of-text (EOS) token to control when generation stops.
+/// Once the step count reaches `max_length` the EOS token gets a logit of positive
+/// infinity so it will be selected. Before that, if the EOS token's probability is
+/// at least `threshold`, `boost` is added to its logit.
+///
+/// The current step is read from the [HasSamplerResources::with_step_count] resource.
+/// If the EOS token has to be forced but has no entry in the logits, for example
+/// because an earlier sampler filtered it out, [SamplerError::TokenIdOutOfRange]
+/// is returned rather than letting generation continue.
+///
+/// **Properties**:
+/// - Modifies logits
+///
+/// **Parameters**:
+/// - `eos_id`: Token id of the EOS token. Only settable with [SampleEosBias::eos_id].
+///   (default: `0`)
+/// - `max_length`: Force the EOS token once this many steps have run. Use `0`
+///   to disable. (default: `0`)
+/// - `threshold`: Probability the EOS token must reach before `boost` is added to
+///   its logit. Use `0.0` to disable boosting, so only `max_length` applies.
+///   (default: `0.0`)
+/// - `boost`: Amount added to the EOS logit when its probability reaches `threshold`.
+///   (default: `0.0`)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SampleEosBias {
+    pub(crate) eos_id: TID,
+    pub(crate) max_length: usize,
+    pub(crate) threshold: L,
+    pub(crate) boost: L,
+}
+
+impl SampleEosBias {
+    pub fn new(eos_id: TID, max_length: usize, threshold: L, boost: L) -> Self {
+        Self {
+            eos_id,
+            max_length,
+            threshold,
+            boost,
+        }
+    }
+
+    pub fn eos_id(mut self, val: TID) -> Self {
+        self.eos_id = val;
+        self
+    }
+
+    pub fn max_length(mut self, val: usize) -> Self {
+        self.max_length = val;
+        self
+    }
+
+    pub fn threshold(mut self, val: L) -> Self {
+        self.threshold = val;
+        self
+    }
+
+    pub fn boost(mut self, val: L) -> Self {
+        self.boost = val;
+        self
+    }
+
+    /// Returns the probability of the entry at `idx` without modifying `logits`.
+    fn probability(logits: &Logits, idx: usize) -> L {
+        if logits.get_softmax() {
+            return logits[idx].prob;
+        }
+        let max_l = logits.iter().map(|l| l.logit).fold(L::NEG_INFINITY, L::max);
+        let cum_sum = logits.iter().map(|l| (l.logit - max_l).exp()).sum::<L>();
+        (logits[idx].logit - max_l).exp() / cum_sum
+    }
+}
+
+impl Sampler for SampleEosBias {
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        let Self {
+            eos_id,
+            max_length,
+            threshold,
+            boost,
+        } = *self;
+        let mut step = 0;
+        if max_length > 0 {
+            res.with_step_count(&mut |sc| step = sc)?;
+        }
+        let force = max_length > 0 && step >= max_length;
+        let Some(idx) = logits.logit_index_for(eos_id) else {
+            if force {
+                Err(SamplerError::TokenIdOutOfRange(eos_id as usize))?
+            }
+            return Ok(logits);
+        };
+
+        if force {
+            logits[idx].logit = L::INFINITY;
+        } else if threshold > 0f32 && boost != 0f32 && Self::probability(logits, idx) >= threshold {
+            logits[idx].logit += boost;
+        } else {
+            return Ok(logits);
+        }
+        logits.set_sorted(false);
+        logits.set_softmax(false);
+        Ok(logits)
+    }
+
+    sampler_metadata_from_options!();
+}
+
+impl ConfigurableSampler<usize, L> for SampleEosBias {}
+
+impl HasSamplerMetadata<usize, L> for SampleEosBias {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "eos bias",
+            description: Some(concat!(
+                "Forces the EOS token once max_length steps have run and boosts it",
+                " when its probability reaches threshold."
+            )),
+            options: vec![
+                SamplerOptionMetadata {
+                    key: "max_length",
+                    description: Some("Force the EOS token once this many steps have run."),
+                    option_type: SamplerOptionType::UInt,
+                },
+                SamplerOptionMetadata {
+                    key: "threshold",
+                    description: Some("Probability the EOS token must reach to be boosted."),
+                    option_type: SamplerOptionType::Float,
+                },
+                SamplerOptionMetadata {
+                    key: "boost",
+                    description: Some("Amount added to the EOS logit when it's boosted."),
+                    option_type: SamplerOptionType::Float,
+                },
+            ],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValueMut::UInt(&mut self.max_length)),
+                    Some(SamplerOptionValueMut::Float(&mut self.threshold)),
+                    Some(SamplerOptionValueMut::Float(&mut self.boost)),
+                ],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValue::UInt(self.max_length)),
+                    Some(SamplerOptionValue::Float(self.threshold)),
+                    Some(SamplerOptionValue::Float(self.boost)),
+                ],
+            )
+        }
+    }
+}
@@ -1,5 +1,16 @@
 use crate::{configure::*, types::*};
 
+/// How [SampleFlatBias] applies the bias values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BiasMode {
+    /// The bias value is added to the existing logit.
+    #[default]
+    Add,
+    /// The logit is overwritten with the bias value, ignoring any
+    /// changes previous samplers made to it.
+    Set,
+}
+
 /// # Flat bias sampling
 /// Used to bias specific tokens by either increasing or decreasing their probability.
 /// One common use case is to forbid certain tokens by setting them to negative infinity,
@@ -14,9 +25,12 @@ use crate::{configure::*, types::*};
 ///
 /// **Parameters**:
 /// - `bias`: A [Vec] of token id and bias value tuples. (default: empty)
+/// - `mode`: Whether the bias is added to the logit or replaces it. Only settable
+///   with [SampleFlatBias::mode]. (default: [BiasMode::Add])
 #[derive(Debug, Default, Clone, PartialEq)]
 pub struct SampleFlatBias {
     pub(crate) bias: Vec<(TID, L)>,
+    pub(crate) mode: BiasMode,
 }
 
 impl std::ops::Deref for SampleFlatBias {
@@ -39,8 +53,40 @@ impl SampleFlatBias {
     pub fn new<I: IntoIterator<Item = (TID, L)>>(it: I) -> Self {
         Self {
             bias: Vec::from_iter(it),
+            mode: BiasMode::Add,
         }
     }
+
+    pub fn mode(mut self, val: BiasMode) -> Self {
+        self.mode = val;
+        self
+    }
+
+    /// Construct the sampler from string keyed biases, for example an
+    /// OpenAI-style `logit_bias` map. Each key is converted to token ids with
+    /// `encoder` and the bias is applied to every token id it encodes to.
+    ///
+    /// Returns an error if a key doesn't encode to any tokens.
+    pub fn from_strings<S: AsRef<str>, I: IntoIterator<Item = (S, L)>>(
+        biases: I,
+        encoder: impl Fn(&str) -> Vec<TID>,
+    ) -> anyhow::Result<Self> {
+        let mut bias = Vec::new();
+        for (s, bv) in biases {
+            let s = s.as_ref();
+            let tids = encoder(s);
+            if tids.is_empty() {
+                Err(SamplerError::InternalError(format!(
+                    "Bias string {s:?} did not encode to any tokens"
+                )))?
+            }
+            bias.extend(tids.into_iter().map(|tid| (tid, bv)));
+        }
+        Ok(Self {
+            bias,
+            mode: BiasMode::Add,
+        })
+    }
 }
 
 impl Sampler for SampleFlatBias {
@@ -50,11 +96,15 @@ impl Sampler for SampleFlatBias {
         logits: &'a mut Logits,
     ) -> anyhow::Result<&'a mut Logits> {
         let bi = self.bias.iter();
+        let mode = self.mode;
         let mut changed = 0;
 
         logits.iter_mut().for_each(|l| {
             if let Some((_tid, bv)) = bi.clone().find(|(tid, _bv)| tid == &l.token_id) {
-                l.logit += bv;
+                match mode {
+                    BiasMode::Add => l.logit += bv,
+                    BiasMode::Set => l.logit = *bv,
+                }
                 changed += 1;
             }
         });
@@ -64,6 +114,8 @@ impl Sampler for SampleFlatBias {
         }
         Ok(logits)
     }
+
+    sampler_metadata_from_options!();
 }
 
 // FIXME: Find a sane way to implement this for the list of bias items.
@@ -1,6 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use crate::{configure::*, types::*};
+use crate::{
+    configure::*,
+    resource::TokenSliceSamplerResources,
+    samplers::{repetition::in_warmup, PenaltySpace},
+    types::*,
+};
 
 /// # Presence and frequency penalty sampling
 /// The **presence** penalty applies to a token that appears at least once in the `last_n` tokens.
@@ -9,6 +14,10 @@ use crate::{configure::*, types::*};
 /// tokens. For example, if `frequency_penalty` is `0.05` and token id `3` has appeared 3 times in the
 /// `last_n` tokens, then token id `3` will have its logit reduced by `0.05 * 3`.
 ///
+/// If the resources provide precomputed counts for the `last_n` window (see
+/// [HasSamplerResources::with_token_counts]) they're used instead of counting
+/// the last tokens, unless `decay` is set.
+///
 /// **Properties**:
 /// - Modifies logits
 /// - Filters logits
@@ -17,12 +26,27 @@ use crate::{configure::*, types::*};
 /// - `last_n`: Number of last tokens to consider. (default: `64`)
 /// - `presence_penalty`: Penalty to apply to tokens that are already present. (default: `0.0`)
 /// - `frequency_penalty`: Penalty to apply to tokens based on frequency. (default: `0.0`)
+/// - `decay`: Each occurrence counts as `decay^distance` where `distance` is how far
+///   the token is from the end of the `last_n` tokens. `1.0` counts every occurrence
+///   equally. (default: `1.0`)
+/// - `global_presence`: Apply the presence penalty to tokens that appear anywhere in the
+///   token history rather than only the `last_n` tokens. The frequency penalty still
+///   only uses the `last_n` tokens. (default: `false`)
+/// - `warmup`: Skip the penalty until there are at least this many last tokens, to avoid
+///   penalizing important early tokens. (default: `0`)
+/// - `space`: With [PenaltySpace::Prob] the penalties are subtracted from the probabilities
+///   rather than the logits. Only settable with [SampleFreqPresence::space].
+///   (default: [PenaltySpace::Logit])
 
 #[derive(Debug, Clone)]
 pub struct SampleFreqPresence {
     pub(crate) frequency_penalty: L,
     pub(crate) presence_penalty: L,
     pub(crate) last_n: usize,
+    pub(crate) decay: L,
+    pub(crate) global_presence: bool,
+    pub(crate) warmup: usize,
+    pub(crate) space: PenaltySpace,
 }
 
 impl Default for SampleFreqPresence {
@@ -31,6 +55,10 @@ impl Default for SampleFreqPresence {
             frequency_penalty: 0f32,
             presence_penalty: 0f32,
             last_n: 64,
+            decay: 1f32,
+            global_presence: false,
+            warmup: 0,
+            space: PenaltySpace::Logit,
         }
     }
 }
@@ -41,6 +69,10 @@ impl SampleFreqPresence {
             frequency_penalty,
             presence_penalty,
             last_n,
+            decay: 1f32,
+            global_presence: false,
+            warmup: 0,
+            space: PenaltySpace::Logit,
         }
     }
 
@@ -58,6 +90,36 @@ impl SampleFreqPresence {
         self.presence_penalty = val;
         self
     }
+
+    pub fn decay(mut self, val: L) -> Self {
+        self.decay = val;
+        self
+    }
+
+    pub fn global_presence(mut self, val: bool) -> Self {
+        self.global_presence = val;
+        self
+    }
+
+    pub fn warmup(mut self, val: usize) -> Self {
+        self.warmup = val;
+        self
+    }
+
+    pub fn space(mut self, val: PenaltySpace) -> Self {
+        self.space = val;
+        self
+    }
+
+    /// Runs the sampler using `tokens` as the last tokens rather than
+    /// requiring a [HasSamplerResources] implementation.
+    pub fn sample_with_tokens<'a>(
+        &mut self,
+        tokens: &[TID],
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        self.sample(&mut TokenSliceSamplerResources(tokens), logits)
+    }
 }
 
 impl Sampler for SampleFreqPresence {
@@ -70,48 +132,81 @@ impl Sampler for SampleFreqPresence {
             frequency_penalty,
             presence_penalty,
             last_n,
-            ..
+            decay,
+            global_presence,
+            warmup,
+            space,
         } = *self;
+        let global_presence = global_presence && presence_penalty != 0f32;
 
         if logits.is_empty()
-            || last_n == 0
+            || (last_n == 0 && !global_presence)
             || (frequency_penalty == 0f32 && presence_penalty == 0f32)
+            || in_warmup(res, warmup)?
         {
             return Ok(logits);
         }
 
-        let mut counts = HashMap::<TID, L>::default();
+        let mut present = HashSet::<TID>::default();
         let mut changed = 0;
 
-        res.with_last_tokens(&mut |orig_tokens| {
-            let tokens = if last_n > orig_tokens.len() {
-                orig_tokens
-            } else {
-                &orig_tokens[orig_tokens.len() - last_n..]
-            };
-            counts.reserve(tokens.len());
-            tokens.iter().copied().for_each(|tid| {
-                let cnt = counts.entry(tid).or_insert(0f32);
-                *cnt += 1f32
-            });
-        })?;
-
-        logits.iter_mut().for_each(|l| {
-            let Some(cnt) = counts.get(&l.token_id) else {
-                return;
-            };
-            if cnt > &0.0 {
-                l.logit -= *cnt * frequency_penalty
-                    + if cnt > &0f32 { 1f32 } else { 0f32 } * presence_penalty;
-                changed += 1;
-            }
-        });
+        if global_presence {
+            res.with_last_tokens(&mut |tokens| present.extend(tokens.iter().copied()))?;
+        }
+        if space == PenaltySpace::Prob {
+            logits.ensure_softmax()?;
+        }
+
+        let mut apply = |count: &dyn Fn(TID) -> L| {
+            logits.iter_mut().for_each(|l| {
+                let cnt = count(l.token_id);
+                if cnt > 0f32 || present.contains(&l.token_id) {
+                    let penalty = cnt * frequency_penalty + presence_penalty;
+                    match space {
+                        PenaltySpace::Logit => l.logit -= penalty,
+                        PenaltySpace::Prob => l.prob = (l.prob - penalty).max(0f32),
+                    }
+                    changed += 1;
+                }
+            })
+        };
+
+        // Precomputed counts can only be used when every occurrence has the same weight.
+        let precomputed = decay == 1f32
+            && res
+                .with_token_counts(last_n, &mut |counts| {
+                    apply(&|tid| counts.get(&tid).copied().unwrap_or_default() as L)
+                })
+                .is_ok();
+        if !precomputed {
+            let mut counts = HashMap::<TID, L>::default();
+            res.with_last_tokens(&mut |orig_tokens| {
+                let tokens = if last_n > orig_tokens.len() {
+                    orig_tokens
+                } else {
+                    &orig_tokens[orig_tokens.len() - last_n..]
+                };
+                counts.reserve(tokens.len());
+                let mut weight = 1f32;
+                tokens.iter().rev().copied().for_each(|tid| {
+                    let cnt = counts.entry(tid).or_insert(0f32);
+                    *cnt += weight;
+                    weight *= decay;
+                });
+            })?;
+            apply(&|tid| counts.get(&tid).copied().unwrap_or_default());
+        }
         if changed > 0 {
+            if space == PenaltySpace::Prob {
+                logits.iter_mut().for_each(|l| l.logit = l.prob.ln());
+            }
             logits.set_sorted(false);
             logits.set_softmax(false);
         }
         Ok(logits)
     }
+
+    sampler_metadata_from_options!();
 }
 
 impl ConfigurableSampler<usize, L> for SampleFreqPresence {}
@@ -153,6 +248,30 @@ impl HasSamplerMetadata<usize, L> for SampleFreqPresence {
                     )),
                     option_type: SamplerOptionType::UInt,
                 },
+                SamplerOptionMetadata {
+                    key: "decay",
+                    description: Some(concat!(
+                        "Weight multiplier applied to older occurrences. ",
+                        "An occurrence counts as decay^distance from the end ",
+                        "of the last_n tokens."
+                    )),
+                    option_type: SamplerOptionType::Float,
+                },
+                SamplerOptionMetadata {
+                    key: "global_presence",
+                    description: Some(concat!(
+                        "Apply the presence penalty to tokens anywhere in the token history ",
+                        "rather than only within the last_n tokens."
+                    )),
+                    option_type: SamplerOptionType::Bool,
+                },
+                SamplerOptionMetadata {
+                    key: "warmup",
+                    description: Some(
+                        "Skip the penalties until there are at least this many previous tokens.",
+                    ),
+                    option_type: SamplerOptionType::UInt,
+                },
             ],
         }
     }
@@ -165,6 +284,9 @@ impl HasSamplerMetadata<usize, L> for SampleFreqPresence {
                     Some(SamplerOptionValueMut::Float(&mut self.frequency_penalty)),
                     Some(SamplerOptionValueMut::Float(&mut self.presence_penalty)),
                     Some(SamplerOptionValueMut::UInt(&mut self.last_n)),
+                    Some(SamplerOptionValueMut::Float(&mut self.decay)),
+                    Some(SamplerOptionValueMut::Bool(&mut self.global_presence)),
+                    Some(SamplerOptionValueMut::UInt(&mut self.warmup)),
                 ],
             )
         }
@@ -178,6 +300,9 @@ impl HasSamplerMetadata<usize, L> for SampleFreqPresence {
                     Some(SamplerOptionValue::Float(self.frequency_penalty)),
                     Some(SamplerOptionValue::Float(self.presence_penalty)),
                     Some(SamplerOptionValue::UInt(self.last_n)),
+                    Some(SamplerOptionValue::Float(self.decay)),
+                    Some(SamplerOptionValue::Bool(self.global_presence)),
+                    Some(SamplerOptionValue::UInt(self.warmup)),
                 ],
             )
         }
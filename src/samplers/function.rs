@@ -0,0 +1,47 @@
+use std::fmt::Debug;
+
+use crate::types::*;
+
+/// # Function sampler
+/// Wraps a closure so it can be used as a [Sampler], for example to push a quick
+/// custom logit transform into a [crate::prelude::SamplerChain] without defining a struct.
+///
+/// The closure is called with the resources and logits and must be `Send + Sync`.
+/// It should clear the sorted and softmax flags (see [Logits::clear_flags]) if it
+/// changes the logits.
+///
+/// **Properties**:
+/// - Depends on the closure
+///
+/// **Parameters**:
+/// - (none)
+pub struct FnSampler<F>(pub F);
+
+impl<F> FnSampler<F>
+where
+    F: FnMut(&mut dyn HasSamplerResources, &mut Logits) -> anyhow::Result<()> + Send + Sync,
+{
+    pub fn new(fun: F) -> Self {
+        Self(fun)
+    }
+}
+
+impl<F> Debug for FnSampler<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FnSampler").finish_non_exhaustive()
+    }
+}
+
+impl<F> Sampler for FnSampler<F>
+where
+    F: FnMut(&mut dyn HasSamplerResources, &mut Logits) -> anyhow::Result<()> + Send + Sync,
+{
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        (self.0)(res, logits)?;
+        Ok(logits)
+    }
+}
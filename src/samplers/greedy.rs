@@ -7,22 +7,105 @@ use crate::{configure::*, types::*};
 /// - Selects a token
 ///
 /// **Parameters**:
-/// - (none)
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// - `debug_k`: When set, the ids of the `debug_k` tokens with the highest logits are
+///   recorded for inspection, see [SampleGreedy::runners_up]. At most
+///   [SampleGreedy::MAX_DEBUG_K] ids are recorded. Only settable with
+///   [SampleGreedy::debug_k]. (default: `None`)
+///
+/// In strict mode (see [SampleGreedy::strict]) sampling empty logits
+/// results in [SamplerError::EmptyLogits] rather than no token being selected.
+#[derive(Debug, Default, Clone, Copy)]
 pub struct SampleGreedy {
     token_id: Option<TID>,
+    logit: Option<Logit>,
+    strict: bool,
+    debug_k: Option<usize>,
+    runners_up: [TID; SampleGreedy::MAX_DEBUG_K],
+    runners_up_len: usize,
 }
 
 impl SampleGreedy {
+    /// Maximum number of runners-up recorded with [SampleGreedy::debug_k]. They're
+    /// stored inline so the sampler stays [Copy].
+    pub const MAX_DEBUG_K: usize = 16;
+
     pub fn new() -> Self {
-        Self { token_id: None }
+        Self {
+            token_id: None,
+            logit: None,
+            strict: false,
+            debug_k: None,
+            runners_up: [0; Self::MAX_DEBUG_K],
+            runners_up_len: 0,
+        }
+    }
+
+    pub fn strict(mut self, val: bool) -> Self {
+        self.strict = val;
+        self
+    }
+
+    pub fn debug_k(mut self, val: Option<usize>) -> Self {
+        self.debug_k = val;
+        self
     }
 
     pub fn get_token_id(&self) -> Option<TID> {
         self.token_id
     }
+
+    /// Returns the ids of the tokens with the highest logits from the last run,
+    /// highest first. Empty unless [SampleGreedy::debug_k] is set.
+    pub fn runners_up(&self) -> &[TID] {
+        &self.runners_up[..self.runners_up_len]
+    }
+
+    fn set_runners_up(&mut self, tids: impl Iterator<Item = TID>) {
+        self.runners_up_len = 0;
+        self.runners_up
+            .iter_mut()
+            .zip(tids)
+            .for_each(|(slot, tid)| {
+                *slot = tid;
+                self.runners_up_len += 1;
+            });
+    }
+
+    fn record_runners_up(&mut self, logits: &Logits, k: usize) {
+        let k = k.min(Self::MAX_DEBUG_K);
+        if logits.get_sorted() {
+            self.set_runners_up(logits.iter().take(k).map(|l| l.token_id));
+            return;
+        }
+        let mut candidates = logits
+            .iter()
+            .filter(|l| !l.logit.is_nan())
+            .collect::<Vec<_>>();
+        let cmp = |x: &&Logit, y: &&Logit| y.logit.total_cmp(&x.logit);
+        if k < candidates.len() {
+            candidates.select_nth_unstable_by(k, cmp);
+            candidates.truncate(k);
+        }
+        candidates.sort_unstable_by(cmp);
+        self.set_runners_up(candidates.into_iter().map(|l| l.token_id));
+    }
 }
 
+// The selected logit is compared by its bits, which is an equivalence relation
+// even for floats, so SampleGreedy can still be Eq.
+impl PartialEq for SampleGreedy {
+    fn eq(&self, other: &Self) -> bool {
+        let bits = |l: Option<Logit>| l.map(|l| (l.token_id, l.logit.to_bits(), l.prob.to_bits()));
+        self.token_id == other.token_id
+            && bits(self.logit) == bits(other.logit)
+            && self.strict == other.strict
+            && self.debug_k == other.debug_k
+            && self.runners_up() == other.runners_up()
+    }
+}
+
+impl Eq for SampleGreedy {}
+
 impl std::ops::Deref for SampleGreedy {
     type Target = Option<TID>;
 
@@ -39,10 +122,15 @@ impl Sampler for SampleGreedy {
     ) -> anyhow::Result<&'a mut Logits> {
         if logits.is_empty() {
             self.token_id = None;
+            self.logit = None;
+            self.runners_up_len = 0;
+            if self.strict {
+                Err(SamplerError::EmptyLogits)?
+            }
             return Ok(logits);
         }
 
-        self.token_id = if logits.get_sorted() {
+        self.logit = if logits.get_sorted() {
             logits.first()
         } else {
             logits
@@ -50,7 +138,11 @@ impl Sampler for SampleGreedy {
                 .filter(|l| !l.logit.is_nan())
                 .max_by(|x, y| x.logit.total_cmp(&y.logit))
         }
-        .map(|l| l.token_id);
+        .copied();
+        self.token_id = self.logit.map(|l| l.token_id);
+        if let Some(k) = self.debug_k {
+            self.record_runners_up(logits, k);
+        }
 
         Ok(logits)
     }
@@ -58,6 +150,31 @@ impl Sampler for SampleGreedy {
     fn sampled_token_id(&self) -> Option<TID> {
         self.token_id
     }
+
+    fn sampled_logit(&self) -> Option<Logit> {
+        self.logit
+    }
+
+    fn reset(&mut self) {
+        self.token_id = None;
+        self.logit = None;
+        self.runners_up_len = 0;
+    }
+
+    fn save_state(&self) -> Option<SamplerState> {
+        Some(SamplerState::Selected(self.logit))
+    }
+
+    fn load_state(&mut self, state: &SamplerState) -> anyhow::Result<()> {
+        let SamplerState::Selected(logit) = state else {
+            Err(state.mismatch(self.name()))?
+        };
+        self.logit = *logit;
+        self.token_id = logit.map(|l| l.token_id);
+        Ok(())
+    }
+
+    sampler_metadata_from_options!();
 }
 
 impl<UI, F> ConfigurableSampler<UI, F> for SampleGreedy
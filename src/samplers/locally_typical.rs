@@ -19,10 +19,14 @@ use crate::{configure::*, types::*};
 /// - `p`: Referred to as τ in the paper. It suggests using 0.2
 ///   as a value for story generation and `0.95` for "abstractive summarization"
 ///   (presumably this means more factual output). (default: `1.0`)
+/// - `max_consider`: If set, only the top `max_consider` entries by logit are kept before
+///   doing the entropy calculations, which bounds the cost on large vocabularies.
+///   Only settable with [SampleLocallyTypical::max_consider]. (default: `None`)
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct SampleLocallyTypical {
     pub(crate) p: L,
     pub(crate) min_keep: usize,
+    pub(crate) max_consider: Option<usize>,
 }
 
 impl Default for SampleLocallyTypical {
@@ -30,13 +34,18 @@ impl Default for SampleLocallyTypical {
         Self {
             p: 1f32,
             min_keep: 1,
+            max_consider: None,
         }
     }
 }
 
 impl SampleLocallyTypical {
     pub fn new(p: L, min_keep: usize) -> Self {
-        Self { p, min_keep }
+        Self {
+            p,
+            min_keep,
+            max_consider: None,
+        }
     }
 
     pub fn min_keep(mut self, val: usize) -> Self {
@@ -48,6 +57,11 @@ impl SampleLocallyTypical {
         self.p = val;
         self
     }
+
+    pub fn max_consider(mut self, val: Option<usize>) -> Self {
+        self.max_consider = val;
+        self
+    }
 }
 
 impl Sampler for SampleLocallyTypical {
@@ -56,10 +70,15 @@ impl Sampler for SampleLocallyTypical {
         _res: &mut dyn HasSamplerResources,
         logits: &'a mut Logits,
     ) -> anyhow::Result<&'a mut Logits> {
-        use std::ops::ControlFlow::*;
-
-        let Self { p, min_keep } = *self;
-        let min_keep = if min_keep == 0 { 0 } else { min_keep - 1 };
+        let Self {
+            p,
+            min_keep,
+            max_consider,
+        } = *self;
+        let max_consider = max_consider.map(|mc| mc.max(effective_min_keep(min_keep)));
+        if let Some(max_consider) = max_consider.filter(|mc| *mc < logits.len()) {
+            *logits = std::mem::take(logits).into_top_k(max_consider);
+        }
         logits.ensure_softmax()?;
 
         let ent = logits
@@ -68,7 +87,7 @@ impl Sampler for SampleLocallyTypical {
 
         let mut shifted = logits
             .iter()
-            .map(|l| (l.clone(), (-l.prob.ln() - ent).abs()))
+            .map(|l| (*l, (-l.prob.ln() - ent).abs()))
             .collect::<Vec<_>>();
         {
             let mut sort_err = Ok(());
@@ -84,28 +103,24 @@ impl Sampler for SampleLocallyTypical {
         }
 
         let mut cum_sum = 0f32;
-        let last_idx = match shifted.iter().enumerate().try_fold(
-            shifted.len(),
-            |last_idx, (idx, (logit, _score))| {
+        let last_idx = shifted
+            .iter()
+            .position(|(logit, _score)| {
                 cum_sum += logit.prob;
-                if cum_sum > p && idx >= min_keep {
-                    return Break(idx + 1);
-                }
-                Continue(last_idx)
-            },
-        ) {
-            Continue(i) => i,
-            Break(i) => i,
-        };
+                cum_sum > p
+            })
+            .map_or_else(|| shifted.len(), |idx| idx + 1);
         logits.clear();
         logits.set_sorted(false);
         logits.set_softmax(false);
         shifted
             .into_iter()
-            .take(last_idx)
             .for_each(|(logit, _score)| logits.push(logit));
+        logits.truncate_keeping(last_idx, min_keep);
         Ok(logits)
     }
+
+    sampler_metadata_from_options!();
 }
 
 impl ConfigurableSampler<usize, L> for SampleLocallyTypical {}
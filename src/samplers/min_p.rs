@@ -8,7 +8,6 @@ use crate::{configure::*, types::*};
 ///
 /// Credit to @kalomaze on GitHub for design. See this link for a more in-depth
 /// explanation: https://github.com/ggerganov/llama.cpp/issues/3483#issuecomment-1783920998
-
 ///
 /// **Properties**:
 /// - Filters logits
@@ -16,10 +15,26 @@ use crate::{configure::*, types::*};
 /// **Parameters**:
 /// - `min_keep`: Minimum number of entries to keep. (default: `1`)
 /// - `p`: Threshold value. Use `0.0` to disable. (default: `0.9`)
+/// - `renormalize`: Recompute the probabilities of the remaining tokens like
+///   `llama.cpp` does. (default: `true`)
+/// - `log_space`: Compare `logit - max_logit` against `ln(p)` rather than
+///   computing probabilities. This avoids underflow with very large
+///   vocabularies. (default: `false`)
+/// - `slope`: Amount `p` changes by every step, so the threshold used is
+///   `p + slope * step` where the step is read from the
+///   [HasSamplerResources::with_step_count] resource. Negative values loosen
+///   the threshold over time. Use `0.0` for a constant `p`. (default: `0.0`)
+/// - `cap`: Limit the threshold won't move past when `slope` is nonzero. The
+///   threshold is always kept between `0.0` and `1.0`. Only settable with
+///   [SampleMinP::cap]. (default: `None`)
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct SampleMinP {
     pub(crate) p: L,
     pub(crate) min_keep: usize,
+    pub(crate) renormalize: bool,
+    pub(crate) log_space: bool,
+    pub(crate) slope: L,
+    pub(crate) cap: Option<L>,
 }
 
 impl Default for SampleMinP {
@@ -27,13 +42,31 @@ impl Default for SampleMinP {
         Self {
             p: 0.05f32,
             min_keep: 1,
+            renormalize: true,
+            log_space: false,
+            slope: 0f32,
+            cap: None,
         }
     }
 }
 
 impl SampleMinP {
     pub fn new(p: L, min_keep: usize) -> Self {
-        Self { p, min_keep }
+        Self {
+            p,
+            min_keep,
+            ..Self::default()
+        }
+    }
+
+    pub fn renormalize(mut self, val: bool) -> Self {
+        self.renormalize = val;
+        self
+    }
+
+    pub fn log_space(mut self, val: bool) -> Self {
+        self.log_space = val;
+        self
     }
 
     pub fn min_keep(mut self, val: usize) -> Self {
@@ -45,39 +78,82 @@ impl SampleMinP {
         self.p = val;
         self
     }
+
+    pub fn slope(mut self, val: L) -> Self {
+        self.slope = val;
+        self
+    }
+
+    pub fn cap(mut self, val: Option<L>) -> Self {
+        self.cap = val;
+        self
+    }
+
+    /// Returns the threshold used at `step`, see the `slope` and `cap` parameters.
+    pub fn p_at(&self, step: usize) -> L {
+        if self.slope == 0f32 {
+            return self.p;
+        }
+        let p = self.p + self.slope * step as L;
+        let p = match self.cap {
+            Some(cap) if self.slope > 0f32 => p.min(cap),
+            Some(cap) => p.max(cap),
+            None => p,
+        };
+        p.clamp(0f32, 1f32)
+    }
 }
 
 impl Sampler for SampleMinP {
     fn sample<'a>(
         &mut self,
-        _res: &mut dyn HasSamplerResources,
+        res: &mut dyn HasSamplerResources,
         logits: &'a mut Logits,
     ) -> anyhow::Result<&'a mut Logits> {
-        let Self { p, min_keep } = *self;
+        let Self {
+            min_keep,
+            renormalize,
+            log_space,
+            slope,
+            ..
+        } = *self;
+        let mut step = 0;
+        if slope != 0f32 {
+            res.with_step_count(&mut |sc| step = sc)?;
+        }
+        let p = self.p_at(step);
         if p == 0f32 || logits.is_empty() {
             return Ok(logits);
         }
 
-        logits.ensure_softmax()?;
-
-        if logits.len() <= min_keep {
-            return Ok(logits);
+        let last_idx = if log_space {
+            logits.ensure_sorted()?;
+            let (max_logit, threshold) = (logits[0].logit, p.ln());
+            logits
+                .iter()
+                .enumerate()
+                .skip(1)
+                .find(|(_, l)| l.logit - max_logit < threshold)
+                .map(|(idx, _)| idx)
+        } else {
+            logits.ensure_softmax()?;
+            let threshold = logits[0].prob * p;
+            logits
+                .iter()
+                .enumerate()
+                .skip(1)
+                .find(|(_, l)| l.prob < threshold)
+                .map(|(idx, _)| idx)
         }
-
-        let threshold = logits[0].prob * p;
-        let last_idx = logits
-            .iter()
-            .enumerate()
-            .skip(1.max(min_keep))
-            .find(|(_, l)| l.prob < threshold)
-            .map(|(idx, _)| idx)
-            .unwrap_or_else(|| logits.len());
-        if last_idx != logits.len() {
-            logits.truncate(last_idx);
-            logits.set_softmax(false);
+        .unwrap_or_else(|| logits.len());
+        logits.truncate_keeping(last_idx, min_keep);
+        if renormalize {
+            logits.ensure_softmax()?;
         }
         Ok(logits)
     }
+
+    sampler_metadata_from_options!();
 }
 
 impl ConfigurableSampler<usize, L> for SampleMinP {}
@@ -106,6 +182,23 @@ impl HasSamplerMetadata<usize, L> for SampleMinP {
                     )),
                     option_type: SamplerOptionType::UInt,
                 },
+                SamplerOptionMetadata {
+                    key: "renormalize",
+                    description: Some(
+                        "Recompute the probabilities of the remaining tokens after filtering.",
+                    ),
+                    option_type: SamplerOptionType::Bool,
+                },
+                SamplerOptionMetadata {
+                    key: "log_space",
+                    description: Some("Compare against the threshold using log-probabilities."),
+                    option_type: SamplerOptionType::Bool,
+                },
+                SamplerOptionMetadata {
+                    key: "slope",
+                    description: Some("Amount the threshold changes by every step."),
+                    option_type: SamplerOptionType::Float,
+                },
             ],
         }
     }
@@ -117,6 +210,9 @@ impl HasSamplerMetadata<usize, L> for SampleMinP {
                 [
                     Some(SamplerOptionValueMut::Float(&mut self.p)),
                     Some(SamplerOptionValueMut::UInt(&mut self.min_keep)),
+                    Some(SamplerOptionValueMut::Bool(&mut self.renormalize)),
+                    Some(SamplerOptionValueMut::Bool(&mut self.log_space)),
+                    Some(SamplerOptionValueMut::Float(&mut self.slope)),
                 ],
             )
         }
@@ -129,6 +225,9 @@ impl HasSamplerMetadata<usize, L> for SampleMinP {
                 [
                     Some(SamplerOptionValue::Float(self.p)),
                     Some(SamplerOptionValue::UInt(self.min_keep)),
+                    Some(SamplerOptionValue::Bool(self.renormalize)),
+                    Some(SamplerOptionValue::Bool(self.log_space)),
+                    Some(SamplerOptionValue::Float(self.slope)),
                 ],
             )
         }
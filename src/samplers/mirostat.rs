@@ -1,4 +1,3 @@
-#![deny(implied_bounds_entailment)]
 use std::fmt::Debug;
 
 use anyhow::Result;
@@ -10,6 +9,18 @@ use crate::{
     types::*,
 };
 
+/// Saved learning state for [SampleMirostat1] and [SampleMirostat2].
+///
+/// This allows one sampler instance to be shared between several sequences
+/// without the learning state leaking from one to another.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MirostatState {
+    /// Current learning state.
+    pub mu: L,
+    /// Last selected token, if any.
+    pub token: Option<TID>,
+}
+
 /// # Mirostat V1 sampling
 /// See: <https://arxiv.org/abs/2007.14966>
 ///
@@ -26,7 +37,8 @@ use crate::{
 /// - `eta`: Learning rate. (default: `0.1`)
 /// - `tau`: Target entropy. (default: `5.0`)
 /// - `m`: Unknown. Can be set manually after construction. (default: `100`)
-/// - `mu`: Current learning state. Can be set manually after construction. (default: `tau * 2`)
+/// - `mu`: Current learning state. Can be set manually after construction, which also
+///   sets the value [Sampler::reset] returns to. (default: `tau * 2`)
 #[derive(Debug, Clone)]
 pub struct SampleMirostat1 {
     pub(crate) n_vocab: usize,
@@ -34,7 +46,9 @@ pub struct SampleMirostat1 {
     pub(crate) tau: L,
     pub(crate) eta: L,
     pub(crate) mu: L,
+    pub(crate) initial_mu: L,
     pub(crate) token: Option<TID>,
+    pub(crate) strict: bool,
     rd_sampler: SampleRandDistrib,
 }
 
@@ -48,7 +62,9 @@ impl Default for SampleMirostat1 {
             eta: 1f32 / ten,
             tau: five,
             mu: ten,
+            initial_mu: ten,
             token: None,
+            strict: false,
             rd_sampler: SampleRandDistrib::new(),
             n_vocab: 0,
         }
@@ -63,8 +79,10 @@ impl SampleMirostat1 {
             eta,
             m: 100,
             mu: tau * (1f32 + 1f32),
+            initial_mu: tau * (1f32 + 1f32),
             rd_sampler: SampleRandDistrib::new(),
             token: None,
+            strict: false,
         }
     }
 
@@ -83,8 +101,7 @@ impl SampleMirostat1 {
     /// value for mu, be sure to set it after tau.
     pub fn tau(mut self, val: L) -> Self {
         self.tau = val;
-        self.mu = val * (1f32 + 1f32);
-        self
+        self.mu(val * (1f32 + 1f32))
     }
 
     pub fn eta(mut self, val: L) -> Self {
@@ -94,8 +111,67 @@ impl SampleMirostat1 {
 
     pub fn mu(mut self, val: L) -> Self {
         self.mu = val;
+        self.initial_mu = val;
+        self
+    }
+
+    /// In strict mode, sampling empty logits results in
+    /// [SamplerError::EmptyLogits] rather than no token being selected.
+    pub fn strict(mut self, val: bool) -> Self {
+        self.strict = val;
+        self
+    }
+
+    /// Returns the current learning state.
+    pub fn snapshot_state(&self) -> MirostatState {
+        MirostatState {
+            mu: self.mu,
+            token: self.token,
+        }
+    }
+
+    /// Restores learning state previously saved with [Self::snapshot_state].
+    pub fn restore_state(&mut self, state: MirostatState) -> &mut Self {
+        self.mu = state.mu;
+        self.token = state.token;
         self
     }
+
+    /// Estimates the number of tokens to keep from the probabilities in `logits`,
+    /// which must already have softmax applied. The result is clamped to `1..=n_vocab`
+    /// and falls back to `m` if the estimate isn't finite, which can happen with
+    /// degenerate distributions.
+    pub(crate) fn estimate_k(&self, logits: &Logits) -> usize {
+        let Self { n_vocab, m, mu, .. } = *self;
+        let max_k = n_vocab.max(1);
+        if logits.is_empty() || m < 1 {
+            return m.clamp(1, max_k);
+        }
+        let n_vocab = n_vocab as L;
+        let (sum_ti_bi, sum_ti_sq) = {
+            let mut idx = 0f32;
+            logits
+                .iter()
+                .zip(logits.iter().skip(1))
+                .take((m - 1).min(logits.len() - 1))
+                .fold((0f32, 0f32), |(sum_ti_bi, sum_ti_sq), (l, l_next)| {
+                    let t_i = ((idx + 2f32) / (idx + 1f32)).ln();
+                    let b_i = l.prob / l_next.prob;
+                    let result = (sum_ti_bi + t_i * b_i, sum_ti_sq + t_i * t_i);
+                    idx += 1f32;
+                    result
+                })
+        };
+        let s_hat = sum_ti_bi / sum_ti_sq;
+        let epsilon_hat = s_hat - 1f32;
+        let k =
+            (epsilon_hat * mu.powf(2f32) / 1f32 - n_vocab.powf(-epsilon_hat)).powf(1f32 / s_hat);
+        if k.is_finite() {
+            AsPrimitive::<usize>::as_(k.max(1f32)).min(max_k)
+        } else {
+            m.clamp(1, max_k)
+        }
+    }
 }
 
 impl Sampler for SampleMirostat1 {
@@ -109,45 +185,33 @@ impl Sampler for SampleMirostat1 {
             tau,
             eta,
             m,
-            mu,
             ..
         } = *self;
         self.token = None;
+        if logits.is_empty() && self.strict {
+            Err(SamplerError::EmptyLogits)?
+        }
         if logits.is_empty() || m < 1 {
             return Ok(logits);
         }
-        if self.n_vocab == 0 {
+        if n_vocab == 0 {
             Err(SamplerError::InternalError(
                 "Mirostat v1 sampler requires n_vocab".to_string(),
             ))?
         }
-        let n_vocab = n_vocab as L;
-
         logits.ensure_softmax()?;
-        let (sum_ti_bi, sum_ti_sq) = {
-            let mut idx = 0f32;
-            logits
+        let k = self.estimate_k(logits);
+        logits.sample(res, &mut SampleTopK::new(k, 1))?;
+
+        if let Some(tid) = self
+            .rd_sampler
+            .sample_token(res, logits)
+            .map_err(rng_context("mirostat 1"))?
+        {
+            let logit = logits
                 .iter()
-                .zip(logits.iter().skip(1))
-                .take((m - 1).min(logits.len() - 1))
-                .fold((0f32, 0f32), |(sum_ti_bi, sum_ti_sq), (l, l_next)| {
-                    let t_i = ((idx + 2f32) / (idx + 1f32)).ln();
-                    let b_i = l.prob / l_next.prob;
-                    let result = (sum_ti_bi + t_i * b_i, sum_ti_sq + t_i * t_i);
-                    idx += 1f32;
-                    result
-                })
-        };
-        let s_hat = sum_ti_bi / sum_ti_sq;
-        let epsilon_hat = s_hat - 1f32;
-        let k =
-            (epsilon_hat * mu.powf(2f32) / 1f32 - n_vocab.powf(-epsilon_hat)).powf(1f32 / s_hat);
-        logits.sample(res, &mut SampleTopK::new(k.as_(), 1))?;
-
-        if let Some(tid) = self.rd_sampler.sample_token(res, logits)? {
-            let logit = logits.iter().find(|l| l.token_id == tid).ok_or_else(|| {
-                SamplerError::InternalError(String::from("Impossible: sample token not in logits?"))
-            })?;
+                .find(|l| l.token_id == tid)
+                .ok_or(SamplerError::TokenIdOutOfRange(tid as usize))?;
 
             self.mu -= eta * (-logit.prob.log2() - tau);
             self.token = Some(tid);
@@ -158,13 +222,42 @@ impl Sampler for SampleMirostat1 {
     fn sampled_token_id(&self) -> Option<TID> {
         self.token
     }
+
+    fn sampled_logit(&self) -> Option<Logit> {
+        self.rd_sampler
+            .sampled_logit()
+            .filter(|l| Some(l.token_id) == self.token)
+    }
+
+    fn reset(&mut self) {
+        self.mu = self.initial_mu;
+        self.token = None;
+        self.rd_sampler.reset();
+    }
+
+    fn save_state(&self) -> Option<SamplerState> {
+        Some(SamplerState::Mirostat(self.snapshot_state()))
+    }
+
+    fn load_state(&mut self, state: &SamplerState) -> Result<()> {
+        let SamplerState::Mirostat(state) = state else {
+            Err(state.mismatch(self.name()))?
+        };
+        self.restore_state(*state);
+        Ok(())
+    }
+
+    sampler_metadata_from_options!();
 }
 
 impl ConfigurableSampler<usize, L> for SampleMirostat1 {
     fn post_set_option(&mut self, md: &SamplerOptionMetadata) -> Result<()> {
-        if md.key == "tau" {
-            self.mu = self.tau * (1f32 + 1f32);
+        match md.key {
+            "tau" => self.mu = self.tau * (1f32 + 1f32),
+            "mu" => {}
+            _ => return Ok(()),
         }
+        self.initial_mu = self.mu;
         Ok(())
     }
 }
@@ -248,13 +341,16 @@ impl HasSamplerMetadata<usize, L> for SampleMirostat1 {
 /// **Parameters**:
 /// - `eta`: Learning rate. (default: `0.1`)
 /// - `tau`: Target entropy. (default: `5.0`)
-/// - `mu`: Current learning state. Can be set manually after construction. (default: `tau * 2`)
+/// - `mu`: Current learning state. Can be set manually after construction, which also
+///   sets the value [Sampler::reset] returns to. (default: `tau * 2`)
 #[derive(Debug, Clone)]
 pub struct SampleMirostat2<TID = u32, L = f32> {
     pub(crate) tau: L,
     pub(crate) eta: L,
     pub(crate) mu: L,
+    pub(crate) initial_mu: L,
     pub(crate) token: Option<TID>,
+    pub(crate) strict: bool,
     rd_sampler: SampleRandDistrib,
 }
 
@@ -267,7 +363,9 @@ impl Default for SampleMirostat2 {
             eta: 1f32 / ten,
             tau: five,
             mu: ten,
+            initial_mu: ten,
             token: None,
+            strict: false,
             rd_sampler: SampleRandDistrib::new(),
         }
     }
@@ -279,8 +377,10 @@ impl SampleMirostat2 {
             tau,
             eta,
             mu: tau * (1f32 + 1f32),
+            initial_mu: tau * (1f32 + 1f32),
             rd_sampler: SampleRandDistrib::new(),
             token: None,
+            strict: false,
         }
     }
 
@@ -289,8 +389,7 @@ impl SampleMirostat2 {
     /// value for mu, be sure to set it after tau.
     pub fn tau(mut self, val: L) -> Self {
         self.tau = val;
-        self.mu = val * (1f32 + 1f32);
-        self
+        self.mu(val * (1f32 + 1f32))
     }
 
     pub fn eta(mut self, val: L) -> Self {
@@ -300,6 +399,29 @@ impl SampleMirostat2 {
 
     pub fn mu(mut self, val: L) -> Self {
         self.mu = val;
+        self.initial_mu = val;
+        self
+    }
+
+    /// In strict mode, sampling empty logits results in
+    /// [SamplerError::EmptyLogits] rather than no token being selected.
+    pub fn strict(mut self, val: bool) -> Self {
+        self.strict = val;
+        self
+    }
+
+    /// Returns the current learning state.
+    pub fn snapshot_state(&self) -> MirostatState {
+        MirostatState {
+            mu: self.mu,
+            token: self.token,
+        }
+    }
+
+    /// Restores learning state previously saved with [Self::snapshot_state].
+    pub fn restore_state(&mut self, state: MirostatState) -> &mut Self {
+        self.mu = state.mu;
+        self.token = state.token;
         self
     }
 }
@@ -312,6 +434,9 @@ impl Sampler for SampleMirostat2 {
     ) -> anyhow::Result<&'a mut Logits> {
         self.token = None;
         if logits.is_empty() {
+            if self.strict {
+                Err(SamplerError::EmptyLogits)?
+            }
             return Ok(logits);
         }
 
@@ -330,10 +455,15 @@ impl Sampler for SampleMirostat2 {
         }
         logits.ensure_softmax()?;
 
-        if let Some(tid) = self.rd_sampler.sample_token(res, logits)? {
-            let logit = logits.iter().find(|l| l.token_id == tid).ok_or_else(|| {
-                SamplerError::InternalError(String::from("Impossible: sample token not in logits?"))
-            })?;
+        if let Some(tid) = self
+            .rd_sampler
+            .sample_token(res, logits)
+            .map_err(rng_context("mirostat 2"))?
+        {
+            let logit = logits
+                .iter()
+                .find(|l| l.token_id == tid)
+                .ok_or(SamplerError::TokenIdOutOfRange(tid as usize))?;
 
             self.mu -= eta * (-logit.prob.log2() - tau);
             self.token = Some(tid);
@@ -344,13 +474,42 @@ impl Sampler for SampleMirostat2 {
     fn sampled_token_id(&self) -> Option<TID> {
         self.token
     }
+
+    fn sampled_logit(&self) -> Option<Logit> {
+        self.rd_sampler
+            .sampled_logit()
+            .filter(|l| Some(l.token_id) == self.token)
+    }
+
+    fn reset(&mut self) {
+        self.mu = self.initial_mu;
+        self.token = None;
+        self.rd_sampler.reset();
+    }
+
+    fn save_state(&self) -> Option<SamplerState> {
+        Some(SamplerState::Mirostat(self.snapshot_state()))
+    }
+
+    fn load_state(&mut self, state: &SamplerState) -> Result<()> {
+        let SamplerState::Mirostat(state) = state else {
+            Err(state.mismatch(self.name()))?
+        };
+        self.restore_state(*state);
+        Ok(())
+    }
+
+    sampler_metadata_from_options!();
 }
 
 impl ConfigurableSampler<usize, L> for SampleMirostat2 {
     fn post_set_option(&mut self, md: &SamplerOptionMetadata) -> Result<()> {
-        if md.key == "tau" {
-            self.mu = self.tau * (1f32 + 1f32);
+        match md.key {
+            "tau" => self.mu = self.tau * (1f32 + 1f32),
+            "mu" => {}
+            _ => return Ok(()),
         }
+        self.initial_mu = self.mu;
         Ok(())
     }
 }
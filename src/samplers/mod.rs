@@ -1,21 +1,36 @@
+pub mod clamp;
+pub mod collect;
+pub mod eos_bias;
 pub mod flat_bias;
 pub mod freq_presence;
+pub mod function;
 pub mod greedy;
 pub mod locally_typical;
 pub mod min_p;
 pub mod mirostat;
+pub mod penalties;
+pub mod percentile;
 pub mod rand_distrib;
+pub mod range_scoped;
 pub mod repetition;
+pub mod scale;
+pub mod scheduled_temperature;
 pub mod sequence_repetition;
+pub mod speculative;
 pub mod tail_free;
 pub mod temperature;
+pub mod toggle;
 pub mod top_a;
 pub mod top_k;
 pub mod top_p;
+pub mod truncate;
+pub mod windowed_repetition;
 
 #[doc(inline)]
 pub use self::{
-    flat_bias::*, freq_presence::*, greedy::*, locally_typical::*, min_p::*, mirostat::*,
-    rand_distrib::*, repetition::*, sequence_repetition::*, tail_free::*, temperature::*, top_a::*,
-    top_k::*, top_p::*,
+    clamp::*, collect::*, eos_bias::*, flat_bias::*, freq_presence::*, function::*, greedy::*,
+    locally_typical::*, min_p::*, mirostat::*, penalties::*, percentile::*, rand_distrib::*,
+    range_scoped::*, repetition::*, scale::*, scheduled_temperature::*, sequence_repetition::*,
+    speculative::*, tail_free::*, temperature::*, toggle::*, top_a::*, top_k::*, top_p::*,
+    truncate::*, windowed_repetition::*,
 };
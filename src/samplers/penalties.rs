@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use crate::{configure::*, resource::TokenSliceSamplerResources, types::*};
+
+/// # Combined penalty sampling
+/// Applies the **repetition**, **frequency** and **presence** penalties in a single
+/// pass over the `last_n` tokens, matching the penalties processor in llama.cpp.
+/// This gives the same result as [crate::samplers::SampleRepetition] followed by
+/// [crate::samplers::SampleFreqPresence] but only scans the history once.
+///
+/// For each token that appears `count` times in the `last_n` tokens, the logit is first
+/// divided by `repeat_penalty` (or multiplied if it's not positive), then reduced by
+/// `count * freq_penalty + presence_penalty`.
+///
+/// **Properties**:
+/// - Modifies logits
+///
+/// **Parameters**:
+/// - `last_n`: Number of last tokens to consider. (default: `64`)
+/// - `repeat_penalty`: Penalty to apply to repeated tokens. `1.0` disables it. (default: `1.1`)
+/// - `freq_penalty`: Penalty to apply to tokens based on frequency. (default: `0.0`)
+/// - `presence_penalty`: Penalty to apply to tokens that are already present. (default: `0.0`)
+#[derive(Debug, Clone)]
+pub struct SamplePenalties {
+    pub(crate) repeat_penalty: L,
+    pub(crate) freq_penalty: L,
+    pub(crate) presence_penalty: L,
+    pub(crate) last_n: usize,
+}
+
+impl Default for SamplePenalties {
+    fn default() -> Self {
+        Self {
+            repeat_penalty: 1.1f32,
+            freq_penalty: 0f32,
+            presence_penalty: 0f32,
+            last_n: 64,
+        }
+    }
+}
+
+impl SamplePenalties {
+    pub fn new(repeat_penalty: L, freq_penalty: L, presence_penalty: L, last_n: usize) -> Self {
+        Self {
+            repeat_penalty,
+            freq_penalty,
+            presence_penalty,
+            last_n,
+        }
+    }
+
+    pub fn last_n(mut self, val: usize) -> Self {
+        self.last_n = val;
+        self
+    }
+
+    pub fn repeat_penalty(mut self, val: L) -> Self {
+        self.repeat_penalty = val;
+        self
+    }
+
+    pub fn freq_penalty(mut self, val: L) -> Self {
+        self.freq_penalty = val;
+        self
+    }
+
+    pub fn presence_penalty(mut self, val: L) -> Self {
+        self.presence_penalty = val;
+        self
+    }
+
+    /// Runs the sampler using `tokens` as the last tokens rather than
+    /// requiring a [HasSamplerResources] implementation.
+    pub fn sample_with_tokens<'a>(
+        &mut self,
+        tokens: &[TID],
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        self.sample(&mut TokenSliceSamplerResources(tokens), logits)
+    }
+}
+
+impl Sampler for SamplePenalties {
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        let Self {
+            repeat_penalty,
+            freq_penalty,
+            presence_penalty,
+            last_n,
+        } = *self;
+
+        if logits.is_empty()
+            || last_n == 0
+            || (repeat_penalty == 1f32 && freq_penalty == 0f32 && presence_penalty == 0f32)
+        {
+            return Ok(logits);
+        }
+
+        let mut counts = HashMap::<TID, usize>::default();
+        res.with_last_tokens(&mut |tokens| {
+            let tokens = if last_n > tokens.len() {
+                tokens
+            } else {
+                &tokens[tokens.len() - last_n..]
+            };
+            counts.reserve(tokens.len());
+            tokens
+                .iter()
+                .for_each(|tid| *counts.entry(*tid).or_insert(0) += 1);
+        })?;
+
+        let mut changed = 0;
+        logits.iter_mut().for_each(|l| {
+            let Some(&cnt) = counts.get(&l.token_id) else {
+                return;
+            };
+            if l.logit <= 0f32 {
+                l.logit *= repeat_penalty;
+            } else {
+                l.logit /= repeat_penalty;
+            }
+            l.logit -= cnt as L * freq_penalty + presence_penalty;
+            changed += 1;
+        });
+
+        if changed > 0 {
+            logits.set_sorted(false);
+            logits.set_softmax(false);
+        }
+        Ok(logits)
+    }
+
+    sampler_metadata_from_options!();
+}
+
+impl ConfigurableSampler<usize, L> for SamplePenalties {}
+
+impl HasSamplerMetadata<usize, L> for SamplePenalties {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "penalties",
+            description: Some(concat!(
+                "Applies repetition, frequency and presence penalties ",
+                "in a single pass over the previous last_n tokens."
+            )),
+            options: vec![
+                SamplerOptionMetadata {
+                    key: "repeat_penalty",
+                    description: Some(
+                        "Penalty to apply to tokens that meet the repetition criteria.",
+                    ),
+                    option_type: SamplerOptionType::Float,
+                },
+                SamplerOptionMetadata {
+                    key: "freq_penalty",
+                    description: Some(concat!(
+                        "Penalty to apply to tokens based on frequency. ",
+                        "For example, if a token has appeared 3 times within the last_n ",
+                        "range then it will have its logit decreased by ",
+                        "3 * freq_penalty."
+                    )),
+                    option_type: SamplerOptionType::Float,
+                },
+                SamplerOptionMetadata {
+                    key: "presence_penalty",
+                    description: Some(concat!(
+                        "Penalty to apply to tokens that are already present ",
+                        "within the last_n tokens."
+                    )),
+                    option_type: SamplerOptionType::Float,
+                },
+                SamplerOptionMetadata {
+                    key: "last_n",
+                    description: Some(
+                        "Number of previous tokens to consider when applying penalties.",
+                    ),
+                    option_type: SamplerOptionType::UInt,
+                },
+            ],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValueMut::Float(&mut self.repeat_penalty)),
+                    Some(SamplerOptionValueMut::Float(&mut self.freq_penalty)),
+                    Some(SamplerOptionValueMut::Float(&mut self.presence_penalty)),
+                    Some(SamplerOptionValueMut::UInt(&mut self.last_n)),
+                ],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValue::Float(self.repeat_penalty)),
+                    Some(SamplerOptionValue::Float(self.freq_penalty)),
+                    Some(SamplerOptionValue::Float(self.presence_penalty)),
+                    Some(SamplerOptionValue::UInt(self.last_n)),
+                ],
+            )
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{configure::*, types::*};
+
+/// # Top percentile sampling
+/// This sampler retains the top `pct` percent of the tokens by rank, so with
+/// `pct` of `1.0` and 32,000 tokens the 320 most probable tokens are kept. It works
+/// like top-K with `k = ceil(len * pct / 100)`, which is handy when vocabulary sizes vary.
+///
+/// **Properties**:
+/// - Filters logits
+///
+/// **Parameters**:
+/// - `min_keep`: Minimum number of entries to keep. (default: `1`)
+/// - `pct`: Percentage of the tokens to keep. Use `100.0` to keep everything. (default: `10.0`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplePercentile {
+    pub(crate) pct: L,
+    pub(crate) min_keep: usize,
+}
+
+impl Default for SamplePercentile {
+    fn default() -> Self {
+        Self {
+            pct: 10f32,
+            min_keep: 1,
+        }
+    }
+}
+
+impl SamplePercentile {
+    pub fn new(pct: L, min_keep: usize) -> Self {
+        Self { pct, min_keep }
+    }
+
+    pub fn min_keep(mut self, val: usize) -> Self {
+        self.min_keep = val;
+        self
+    }
+
+    pub fn pct(mut self, val: L) -> Self {
+        self.pct = val;
+        self
+    }
+}
+
+impl Sampler for SamplePercentile {
+    fn sample<'a>(
+        &mut self,
+        _res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        let Self { pct, min_keep } = *self;
+        if logits.is_empty() || pct >= 100f32 {
+            return Ok(logits);
+        }
+        let k = (logits.len() as L * pct.max(0f32) / 100f32).ceil() as usize;
+        logits.ensure_sorted()?.truncate_keeping(k, min_keep);
+        Ok(logits)
+    }
+
+    sampler_metadata_from_options!();
+}
+
+impl ConfigurableSampler<usize, L> for SamplePercentile {}
+
+impl HasSamplerMetadata<usize, L> for SamplePercentile {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "percentile",
+            description: Some(concat!(
+                "This sampler retains the top pct percent of the tokens by rank. ",
+                "The remaining tokens are eliminated."
+            )),
+            options: vec![
+                SamplerOptionMetadata {
+                    key: "pct",
+                    description: Some("Percentage of the tokens to keep."),
+                    option_type: SamplerOptionType::Float,
+                },
+                SamplerOptionMetadata {
+                    key: "min_keep",
+                    description: Some(concat!(
+                        "Minimum number of tokens to keep after sampling. ",
+                        "Setting this to 0 is not recommended."
+                    )),
+                    option_type: SamplerOptionType::UInt,
+                },
+            ],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValueMut::Float(&mut self.pct)),
+                    Some(SamplerOptionValueMut::UInt(&mut self.min_keep)),
+                ],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValue::Float(self.pct)),
+                    Some(SamplerOptionValue::UInt(self.min_keep)),
+                ],
+            )
+        }
+    }
+}
@@ -16,15 +16,58 @@ use crate::{configure::*, types::*};
 /// - Selects a token
 ///
 /// **Parameters**:
-/// - (none)
+/// - `deterministic`: Select the most probable token instead of sampling. This allows
+///   switching between greedy and random sampling without changing the chain. (default: `false`)
+///
+/// The sampler uses the RNG resource when available and otherwise falls back to
+/// [HasSamplerResources::with_rand_f64]. If only one token remains it's selected
+/// without using any randomness, so the RNG state is left untouched. The same applies in
+/// deterministic mode. Note that a zero temperature upstream (see
+/// [crate::samplers::SampleTemperature]) already leaves only one possible token.
+///
+/// In strict mode (see [SampleRandDistrib::strict]) sampling empty logits
+/// results in [SamplerError::EmptyLogits] rather than no token being selected.
 #[derive(Debug, Default, Clone)]
 pub struct SampleRandDistrib {
     token_id: Option<TID>,
+    logit: Option<Logit>,
+    strict: bool,
+    pub(crate) deterministic: bool,
 }
 
 impl SampleRandDistrib {
     pub fn new() -> Self {
-        Self { token_id: None }
+        Self {
+            token_id: None,
+            logit: None,
+            strict: false,
+            deterministic: false,
+        }
+    }
+
+    pub fn strict(mut self, val: bool) -> Self {
+        self.strict = val;
+        self
+    }
+
+    pub fn deterministic(mut self, val: bool) -> Self {
+        self.deterministic = val;
+        self
+    }
+
+    /// Sets deterministic mode in place. See [Self::deterministic].
+    pub fn set_deterministic(&mut self, val: bool) -> &mut Self {
+        self.deterministic = val;
+        self
+    }
+}
+
+/// Used by samplers that select tokens with an internal [SampleRandDistrib] so a
+/// [SamplerError::MissingRng] error names the outer sampler.
+pub(crate) fn rng_context(sampler: &'static str) -> impl Fn(anyhow::Error) -> anyhow::Error {
+    move |err| match err.downcast_ref::<SamplerError>() {
+        Some(SamplerError::MissingRng(_)) => SamplerError::MissingRng(sampler).into(),
+        _ => err,
     }
 }
 
@@ -35,21 +78,79 @@ impl Sampler for SampleRandDistrib {
         logits: &'a mut Logits,
     ) -> anyhow::Result<&'a mut Logits> {
         self.token_id = None;
+        self.logit = None;
         if logits.is_empty() {
+            if self.strict {
+                Err(SamplerError::EmptyLogits)?
+            }
             return Ok(logits);
         }
+        logits.validate()?;
         logits.ensure_softmax()?;
+        if logits.len() == 1 || self.deterministic {
+            // Softmax sorts the logits, so the first entry is the most probable. Either
+            // way there's nothing random to choose, so don't disturb the RNG.
+            self.logit = Some(logits[0]);
+            self.token_id = Some(logits[0].token_id);
+            return Ok(logits);
+        }
         let dist = WeightedIndex::new(logits.iter().map(|l| l.prob))
             .map_err(SamplerError::RandWeightedError)?;
-        res.with_rng_mut(&mut |r| {
-            self.token_id = Some(logits[dist.sample(r)].token_id);
-        })?;
+        match res.with_rng_mut(&mut |r| {
+            self.logit = Some(logits[dist.sample(r)]);
+        }) {
+            Err(SamplerError::MissingResource(_)) => res
+                .with_rand_f64(&mut |next| {
+                    let target = next() as L;
+                    let mut cum_sum = 0f32;
+                    self.logit = logits
+                        .iter()
+                        .find(|l| {
+                            cum_sum += l.prob;
+                            cum_sum > target
+                        })
+                        .or_else(|| logits.last())
+                        .copied();
+                })
+                .map_err(|err| match err {
+                    SamplerError::MissingResource(_) => {
+                        SamplerError::MissingRng("random distribution")
+                    }
+                    err => err,
+                })?,
+            result => result?,
+        }
+        self.token_id = self.logit.map(|l| l.token_id);
         Ok(logits)
     }
 
     fn sampled_token_id(&self) -> Option<TID> {
         self.token_id
     }
+
+    fn sampled_logit(&self) -> Option<Logit> {
+        self.logit
+    }
+
+    fn reset(&mut self) {
+        self.token_id = None;
+        self.logit = None;
+    }
+
+    fn save_state(&self) -> Option<SamplerState> {
+        Some(SamplerState::Selected(self.logit))
+    }
+
+    fn load_state(&mut self, state: &SamplerState) -> anyhow::Result<()> {
+        let SamplerState::Selected(logit) = state else {
+            Err(state.mismatch(self.name()))?
+        };
+        self.logit = *logit;
+        self.token_id = logit.map(|l| l.token_id);
+        Ok(())
+    }
+
+    sampler_metadata_from_options!();
 }
 
 impl<UI: ConfigurableNumValue, F: ConfigurableNumValue> ConfigurableSampler<UI, F>
@@ -64,7 +165,29 @@ impl<UI: ConfigurableNumValue, F: ConfigurableNumValue> HasSamplerMetadata<UI, F
         SamplerMetadata {
             name: "random distribution",
             description: Some("Randomly selects a token based on its probability."),
-            options: vec![],
+            options: vec![SamplerOptionMetadata {
+                key: "deterministic",
+                description: Some("Select the most probable token instead of sampling."),
+                option_type: SamplerOptionType::Bool,
+            }],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, UI, F>> {
+        unsafe {
+            SamplerOptions::build_options(
+                HasSamplerMetadata::<UI, F>::sampler_metadata(self).options,
+                [Some(SamplerOptionValueMut::Bool(&mut self.deterministic))],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, UI, F>> {
+        unsafe {
+            SamplerOptions::build_options(
+                HasSamplerMetadata::<UI, F>::sampler_metadata(self).options,
+                [Some(SamplerOptionValue::Bool(self.deterministic))],
+            )
         }
     }
 }
@@ -0,0 +1,109 @@
+use std::ops::Range;
+
+use anyhow::Result;
+
+use crate::{configure::SamplerMetadata, types::*};
+
+/// # Range scoped sampler
+/// Wraps another [Sampler] so it only affects tokens whose id is in `range`, for
+/// example to apply a penalty to regular tokens but not special tokens. Logits
+/// outside the range are set aside while the wrapped sampler runs and added back
+/// afterward, so the wrapped sampler only sees (and computes probabilities from)
+/// the tokens in the range.
+///
+/// The logits that were set aside are added back after the remaining ones, so the
+/// original order isn't preserved and the sorted and softmax flags are cleared.
+/// They are added back even if the wrapped sampler returns an error. When no token
+/// is in the range, the wrapped sampler isn't run at all.
+///
+/// **Properties**:
+/// - Same as the wrapped sampler, restricted to tokens in the range
+///
+/// **Parameters**:
+/// - `range`: Token ids the wrapped sampler applies to, as the half-open range `start..end`.
+#[derive(Debug, Clone)]
+pub struct RangeScoped<S> {
+    pub(crate) inner: S,
+    pub(crate) range: Range<TID>,
+}
+
+impl<S: Sampler> RangeScoped<S> {
+    pub fn new(inner: S, range: Range<TID>) -> Self {
+        Self { inner, range }
+    }
+
+    pub fn range(mut self, val: Range<TID>) -> Self {
+        self.range = val;
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Sampler> Sampler for RangeScoped<S> {
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> Result<&'a mut Logits> {
+        if logits.iter().all(|l| self.range.contains(&l.token_id)) {
+            return self.inner.sample(res, logits);
+        }
+        if !logits.iter().any(|l| self.range.contains(&l.token_id)) {
+            return Ok(logits);
+        }
+        let (inside, outside): (Vec<_>, Vec<_>) = logits
+            .drain(..)
+            .partition(|l| self.range.contains(&l.token_id));
+        // Removing entries keeps the sort order but the probabilities are stale.
+        logits.extend(inside);
+        logits.set_softmax(false);
+        let result = self.inner.sample(res, logits).map(|_| ());
+        logits.extend(outside);
+        logits.clear_flags();
+        result?;
+        Ok(logits)
+    }
+
+    fn sampled_token_id(&self) -> Option<TID> {
+        self.inner.sampled_token_id()
+    }
+
+    fn sampled_logit(&self) -> Option<Logit> {
+        self.inner.sampled_logit()
+    }
+
+    fn try_sampled_token_id(&self) -> Result<Option<TID>> {
+        self.inner.try_sampled_token_id()
+    }
+
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset()
+    }
+
+    fn save_state(&self) -> Option<SamplerState> {
+        self.inner.save_state()
+    }
+
+    fn load_state(&mut self, state: &SamplerState) -> Result<()> {
+        self.inner.load_state(state)
+    }
+
+    fn metadata(&self) -> SamplerMetadata {
+        self.inner.metadata()
+    }
+}
@@ -1,6 +1,98 @@
-use std::marker::PhantomData;
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fmt::Debug,
+    marker::PhantomData,
+};
 
-use crate::{configure::*, types::*};
+use crate::{configure::*, resource::TokenSliceSamplerResources, types::*};
+
+/// Returns `true` if there are fewer than `warmup` last tokens, meaning a
+/// penalty sampler with that warmup setting shouldn't apply its penalty yet.
+///
+/// Resources without last tokens (for example ones that only provide
+/// [HasSamplerResources::with_token_counts]) are never considered to be in warmup.
+pub(crate) fn in_warmup(res: &dyn HasSamplerResources, warmup: usize) -> anyhow::Result<bool> {
+    if warmup == 0 {
+        return Ok(false);
+    }
+    let mut len = 0;
+    match res.with_last_tokens(&mut |tokens| len = tokens.len()) {
+        Ok(()) => Ok(len < warmup),
+        Err(SamplerError::MissingResource(_)) => Ok(false),
+        Err(err) => Err(err)?,
+    }
+}
+
+/// Decoded token bytes cached by [SampleRepetition] in subword mode so the
+/// vocabulary only needs to be decoded once.
+#[derive(Clone, Default)]
+pub(crate) struct TokenBytesCache(HashMap<TID, Vec<u8>>);
+
+impl Debug for TokenBytesCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("TokenBytesCache")
+            .field(&self.0.len())
+            .finish()
+    }
+}
+
+impl TokenBytesCache {
+    /// Returns the bytes for `tid`, decoding them with
+    /// [HasSamplerResources::with_token_bytes] if they aren't cached yet.
+    fn get(&mut self, res: &dyn HasSamplerResources, tid: TID) -> Result<&[u8], SamplerError> {
+        Ok(match self.0.entry(tid) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let mut bytes = vec![];
+                res.with_token_bytes(tid, &mut |b| bytes.extend_from_slice(b))?;
+                e.insert(bytes)
+            }
+        })
+    }
+
+    /// Returns the ids in `logits` whose decoded bytes start with at least `min_len`
+    /// bytes that repeat the end of the text of the last `last_n` tokens.
+    fn subword_matches(
+        &mut self,
+        res: &dyn HasSamplerResources,
+        logits: &Logits,
+        last_n: usize,
+        min_len: usize,
+    ) -> anyhow::Result<HashSet<TID>> {
+        let min_len = min_len.max(1);
+        let mut recent_ids = vec![];
+        res.with_last_tokens(&mut |tokens| {
+            recent_ids.extend_from_slice(&tokens[tokens.len().saturating_sub(last_n)..])
+        })?;
+        let mut recent = vec![];
+        for tid in recent_ids {
+            recent.extend_from_slice(self.get(res, tid)?);
+        }
+
+        let mut matches = HashSet::new();
+        if recent.len() < min_len {
+            return Ok(matches);
+        }
+        for l in logits.iter() {
+            let bytes = self.get(res, l.token_id)?;
+            if (min_len..=bytes.len().min(recent.len())).any(|n| recent.ends_with(&bytes[..n])) {
+                matches.insert(l.token_id);
+            }
+        }
+        Ok(matches)
+    }
+}
+
+/// Where repetition style penalties get applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PenaltySpace {
+    /// Penalties are applied to the raw logits.
+    #[default]
+    Logit,
+    /// Softmax is applied first and penalties are applied to the probabilities, which are
+    /// clamped at zero. The logits are then replaced with `ln(prob)`.
+    Prob,
+}
 
 // FIXME: Complete documentation.
 /// # Repetition penalty sampling
@@ -13,10 +105,26 @@ use crate::{configure::*, types::*};
 /// **Parameters**:
 /// - `last_n`: Number of last tokens to consider. (default: `64`)
 /// - `repetition_penalty`: Penalty to apply to repeated tokens. (default: `1.1`)
+/// - `warmup`: Skip the penalty until there are at least this many last tokens, to avoid
+///   penalizing important early tokens. (default: `0`)
+/// - `space`: With [PenaltySpace::Prob] the probability of repeated tokens is divided by the
+///   penalty instead. Only settable with [SampleRepetition::space].
+///   (default: [PenaltySpace::Logit])
+/// - `subword`: When set, tokens whose decoded text overlaps recently generated text
+///   are penalized too, which catches loops that re-spell earlier text with different
+///   tokens. A token counts if its text starts with at least this many bytes repeating
+///   the end of the text of the last `last_n` tokens. Requires the
+///   [HasSamplerResources::with_token_bytes] resource. The decoded bytes are cached until
+///   [Sampler::reset], so the decoder must return the same bytes for a token id in between.
+///   Only settable with [SampleRepetition::subword]. (default: `None`)
 #[derive(Debug, Clone)]
 pub struct SampleRepetition<TID = u32, L = f32> {
     pub(crate) repetition_penalty: L,
     pub(crate) last_n: usize,
+    pub(crate) warmup: usize,
+    pub(crate) space: PenaltySpace,
+    pub(crate) subword: Option<usize>,
+    pub(crate) subword_cache: TokenBytesCache,
     marker: PhantomData<TID>,
 }
 
@@ -25,6 +133,10 @@ impl Default for SampleRepetition {
         Self {
             repetition_penalty: 1.1f32,
             last_n: 64,
+            warmup: 0,
+            space: PenaltySpace::Logit,
+            subword: None,
+            subword_cache: TokenBytesCache::default(),
             marker: PhantomData,
         }
     }
@@ -35,10 +147,15 @@ impl SampleRepetition {
         Self {
             repetition_penalty,
             last_n,
-            marker: PhantomData,
+            ..Self::default()
         }
     }
 
+    pub fn space(mut self, val: PenaltySpace) -> Self {
+        self.space = val;
+        self
+    }
+
     pub fn last_n(mut self, val: usize) -> Self {
         self.last_n = val;
         self
@@ -48,6 +165,37 @@ impl SampleRepetition {
         self.repetition_penalty = val;
         self
     }
+
+    pub fn warmup(mut self, val: usize) -> Self {
+        self.warmup = val;
+        self
+    }
+
+    pub fn subword(mut self, val: Option<usize>) -> Self {
+        self.subword = val;
+        self
+    }
+
+    /// Runs the sampler using `tokens` as the last tokens rather than
+    /// requiring a [HasSamplerResources] implementation.
+    ///
+    /// ```rust
+    /// use llm_samplers::prelude::*;
+    ///
+    /// # fn main() -> anyhow::Result<()> {
+    /// let mut logits = Logits::try_from_iter([1.0f32, 1.0, 1.0])?;
+    /// SampleRepetition::new(2.0, 64).sample_with_tokens(&[1], &mut logits)?;
+    /// assert_eq!(logits[1].logit, 0.5);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn sample_with_tokens<'a>(
+        &mut self,
+        tokens: &[TID],
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        self.sample(&mut TokenSliceSamplerResources(tokens), logits)
+    }
 }
 
 impl Sampler for SampleRepetition {
@@ -59,13 +207,26 @@ impl Sampler for SampleRepetition {
         let Self {
             repetition_penalty,
             last_n,
+            warmup,
+            space,
+            subword,
             ..
         } = *self;
 
-        if logits.is_empty() || last_n == 0 || repetition_penalty <= 1f32 {
+        if logits.is_empty() || last_n == 0 || repetition_penalty <= 1f32 || in_warmup(res, warmup)?
+        {
             return Ok(logits);
         }
 
+        let subword_ids = subword
+            .map(|min_len| {
+                self.subword_cache
+                    .subword_matches(res, logits, last_n, min_len)
+            })
+            .transpose()?;
+        if space == PenaltySpace::Prob {
+            logits.ensure_softmax()?;
+        }
         let mut changed = 0;
         res.with_last_tokens(&mut |tokens| {
             let tokens = if last_n > tokens.len() {
@@ -76,23 +237,37 @@ impl Sampler for SampleRepetition {
 
             logits
                 .iter_mut()
-                .filter(|l| tokens.contains(&l.token_id))
+                .filter(|l| {
+                    tokens.contains(&l.token_id)
+                        || subword_ids
+                            .as_ref()
+                            .is_some_and(|ids| ids.contains(&l.token_id))
+                })
                 .for_each(|l| {
-                    l.logit = if l.logit <= 0f32 {
-                        l.logit * repetition_penalty
-                    } else {
-                        l.logit / repetition_penalty
-                    };
+                    match space {
+                        PenaltySpace::Logit if l.logit <= 0f32 => l.logit *= repetition_penalty,
+                        PenaltySpace::Logit => l.logit /= repetition_penalty,
+                        PenaltySpace::Prob => l.prob /= repetition_penalty,
+                    }
                     changed += 1;
                 });
         })?;
 
         if changed > 0 {
+            if space == PenaltySpace::Prob {
+                logits.iter_mut().for_each(|l| l.logit = l.prob.ln());
+            }
             logits.set_sorted(false);
             logits.set_softmax(false);
         }
         Ok(logits)
     }
+
+    fn reset(&mut self) {
+        self.subword_cache = TokenBytesCache::default();
+    }
+
+    sampler_metadata_from_options!();
 }
 
 impl ConfigurableSampler<usize, L> for SampleRepetition {}
@@ -120,6 +295,13 @@ impl HasSamplerMetadata<usize, L> for SampleRepetition {
                     ),
                     option_type: SamplerOptionType::UInt,
                 },
+                SamplerOptionMetadata {
+                    key: "warmup",
+                    description: Some(
+                        "Skip the penalty until there are at least this many previous tokens.",
+                    ),
+                    option_type: SamplerOptionType::UInt,
+                },
             ],
         }
     }
@@ -131,6 +313,7 @@ impl HasSamplerMetadata<usize, L> for SampleRepetition {
                 [
                     Some(SamplerOptionValueMut::Float(&mut self.repetition_penalty)),
                     Some(SamplerOptionValueMut::UInt(&mut self.last_n)),
+                    Some(SamplerOptionValueMut::UInt(&mut self.warmup)),
                 ],
             )
         }
@@ -143,6 +326,7 @@ impl HasSamplerMetadata<usize, L> for SampleRepetition {
                 [
                     Some(SamplerOptionValue::Float(self.repetition_penalty)),
                     Some(SamplerOptionValue::UInt(self.last_n)),
+                    Some(SamplerOptionValue::UInt(self.warmup)),
                 ],
             )
         }
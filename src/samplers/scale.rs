@@ -0,0 +1,100 @@
+use crate::{configure::*, types::*};
+
+/// # Scale sampling
+/// Multiplies every logit by `scale`. A scale above `1.0` sharpens the distribution
+/// and a scale below `1.0` flattens it. This is the same as a temperature of
+/// `1.0 / scale` (see [crate::samplers::SampleTemperature]) but can be clearer
+/// when thinking in terms of scaling the logits. Logits that aren't finite, like
+/// tokens masked with negative infinity, are left unchanged.
+///
+/// **Properties**:
+/// - Modifies logits
+///
+/// **Parameters**:
+/// - `scale`: Factor to multiply the logits by. (default: `1.0`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleScale {
+    pub(crate) scale: L,
+}
+
+impl Default for SampleScale {
+    fn default() -> Self {
+        Self { scale: 1f32 }
+    }
+}
+
+impl SampleScale {
+    pub fn new(scale: L) -> Self {
+        Self { scale }
+    }
+
+    pub fn scale(mut self, val: L) -> Self {
+        self.scale = val;
+        self
+    }
+}
+
+impl Sampler for SampleScale {
+    fn sample<'a>(
+        &mut self,
+        _res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        let scale = self.scale;
+        if scale == 1f32 || logits.is_empty() {
+            return Ok(logits);
+        }
+
+        // Non-finite logits are left alone so masked tokens stay masked rather than
+        // becoming NaN (zero scale) or positive infinity (negative scale).
+        logits
+            .iter_mut()
+            .filter(|l| l.logit.is_finite())
+            .for_each(|l| l.logit *= scale);
+        if scale <= 0f32 {
+            // Negative scales reverse the order and zero makes every logit equal.
+            logits.set_sorted(false);
+        }
+        logits.set_softmax(false);
+        Ok(logits)
+    }
+
+    sampler_metadata_from_options!();
+}
+
+impl ConfigurableSampler<usize, L> for SampleScale {}
+
+impl HasSamplerMetadata<usize, L> for SampleScale {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "scale",
+            description: Some(concat!(
+                "Multiplies every logit by scale. ",
+                "Values above 1.0 sharpen the distribution and values below 1.0 flatten it."
+            )),
+            options: vec![SamplerOptionMetadata {
+                key: "scale",
+                description: Some("Factor to multiply the logits by."),
+                option_type: SamplerOptionType::Float,
+            }],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [Some(SamplerOptionValueMut::Float(&mut self.scale))],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [Some(SamplerOptionValue::Float(self.scale))],
+            )
+        }
+    }
+}
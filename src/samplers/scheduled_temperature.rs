@@ -0,0 +1,114 @@
+use std::{fmt::Debug, sync::Arc};
+
+use crate::{configure::*, samplers::SampleTemperature, types::*};
+
+/// Schedule used by [SampleScheduledTemperature] to determine the
+/// temperature for a given step.
+#[derive(Clone)]
+pub enum TemperatureSchedule {
+    /// Temperature values indexed by step. Once the step count exceeds the
+    /// length, the last value continues to be used.
+    Steps(Vec<L>),
+
+    /// A function that maps the step count to a temperature.
+    Fn(Arc<dyn Fn(usize) -> L + Send + Sync>),
+}
+
+impl Debug for TemperatureSchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Steps(steps) => f.debug_tuple("Steps").field(steps).finish(),
+            Self::Fn(_) => f.debug_tuple("Fn").finish_non_exhaustive(),
+        }
+    }
+}
+
+impl Default for TemperatureSchedule {
+    fn default() -> Self {
+        Self::Steps(vec![1f32])
+    }
+}
+
+impl TemperatureSchedule {
+    /// Returns the temperature for `step` or [None] if the schedule is empty.
+    pub fn temperature_at(&self, step: usize) -> Option<L> {
+        match self {
+            Self::Steps(steps) => steps.get(step).or_else(|| steps.last()).copied(),
+            Self::Fn(fun) => Some(fun(step)),
+        }
+    }
+}
+
+/// # Scheduled temperature sampling
+/// Like [SampleTemperature] but the temperature changes over the course of
+/// generation. The current step is read from the
+/// [HasSamplerResources::with_step_count] resource.
+///
+/// **Properties**:
+///
+/// - Modifies logits
+///
+/// **Parameters**:
+/// - `schedule`: The [TemperatureSchedule]. (default: constant `1.0`)
+#[derive(Debug, Clone, Default)]
+pub struct SampleScheduledTemperature {
+    pub(crate) schedule: TemperatureSchedule,
+}
+
+impl SampleScheduledTemperature {
+    pub fn new(schedule: TemperatureSchedule) -> Self {
+        Self { schedule }
+    }
+
+    /// Build the sampler from temperatures indexed by step.
+    pub fn from_steps(steps: impl IntoIterator<Item = L>) -> Self {
+        Self::new(TemperatureSchedule::Steps(Vec::from_iter(steps)))
+    }
+
+    /// Build the sampler from a function mapping the step to a temperature.
+    pub fn from_fn(fun: impl Fn(usize) -> L + Send + Sync + 'static) -> Self {
+        Self::new(TemperatureSchedule::Fn(Arc::new(fun)))
+    }
+
+    pub fn schedule(mut self, val: TemperatureSchedule) -> Self {
+        self.schedule = val;
+        self
+    }
+}
+
+impl Sampler for SampleScheduledTemperature {
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        let mut step = 0;
+        res.with_step_count(&mut |sc| step = sc)?;
+        let Some(temperature) = self.schedule.temperature_at(step) else {
+            return Ok(logits);
+        };
+        SampleTemperature::new(temperature).sample(res, logits)
+    }
+
+    sampler_metadata_from_options!();
+}
+
+impl<UI: ConfigurableNumValue, F: ConfigurableNumValue> ConfigurableSampler<UI, F>
+    for SampleScheduledTemperature
+{
+}
+
+impl<UI: ConfigurableNumValue, F: ConfigurableNumValue> HasSamplerMetadata<UI, F>
+    for SampleScheduledTemperature
+{
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "scheduled temperature",
+            description: Some(concat!(
+                "Temperature that changes based on the current step. ",
+                "The schedule can't be set via options."
+            )),
+            options: vec![],
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use crate::{configure::*, types::*};
+use crate::{configure::*, resource::TokenSliceSamplerResources, types::*};
 
 /// # Sequence Repetition
 /// This sampler penalizing repeating sequences of tokens that have already been seen within the
@@ -17,6 +17,10 @@ use crate::{configure::*, types::*};
 /// the sequence was `1, 7, 8, 3, 4, 1, 2, 3` it would _still_ be count as a match and `4` would
 /// be penalized.
 ///
+/// Since a match needs both an earlier occurrence of the sequence and the sequence at the
+/// end of the window, nothing can be penalized unless `last_n` (and the number of available
+/// last tokens) is at least `min_length * 2`.
+///
 /// **Warning**: Very alpha code, likely has significant bugs.
 ///
 /// **Properties**:
@@ -24,15 +28,19 @@ use crate::{configure::*, types::*};
 ///
 /// **Parameters**:
 /// - `last_n`: Number of last tokens to consider. (default: `64`)
-/// - `min_length`: The minimum length for a sequence to match. (default: `0`)
+/// - `min_length`: The minimum length for a sequence to match. Values below `2` disable
+///   the sampler. (default: `4`)
 /// - `flat_penalty`: Flat penalty to apply to the token that would continue the matched sequence. (default: `0.0`)
 /// - `stacking_penalty`: Stacking penalty to the token that would continue the matched sequence,
-///     it is multiplied by the sequence length. (default: `0.0`)
+///   it is multiplied by the sequence length. (default: `0.0`)
 /// - `tolerance`: Tolerance basically acts like a wildcard to allow fuzzy sequence matching.
-///     For example, if tolerance is set to `1`, then `1, 6, 3` could match with `1, 2, 3`. (default: `0`)
+///   For example, if tolerance is set to `1`, then `1, 6, 3` could match with `1, 2, 3`. (default: `0`)
 /// - `max_merge`: Controls the number of consecutive non-matching tokens that
-///     the tolerance wildcard can match. Setting this to `0` or `1` deactivates it.
-///     Setting it to 2 would allow `1, 6, 6, 3` to match with `1, 2, 3`. (default: `1`)
+///   the tolerance wildcard can match. Setting this to `0` or `1` deactivates it.
+///   Setting it to 2 would allow `1, 6, 6, 3` to match with `1, 2, 3`. (default: `1`)
+/// - `max_search_steps`: Limits the amount of work the sequence search can do. Once the
+///   limit is reached the search stops early and only the matches found so far are
+///   penalized. Use `0` for no limit. (default: `0`)
 
 #[derive(Debug, Clone)]
 pub struct SampleSeqRepetition {
@@ -42,6 +50,7 @@ pub struct SampleSeqRepetition {
     max_merge: usize,
     min_length: usize,
     last_n: usize,
+    max_search_steps: usize,
 }
 
 impl Default for SampleSeqRepetition {
@@ -53,6 +62,7 @@ impl Default for SampleSeqRepetition {
             max_merge: 1,
             last_n: 64,
             min_length: 4,
+            max_search_steps: 0,
         }
     }
 }
@@ -73,6 +83,7 @@ impl SampleSeqRepetition {
             tolerance,
             max_merge,
             last_n,
+            max_search_steps: 0,
         }
     }
 
@@ -96,6 +107,11 @@ impl SampleSeqRepetition {
         self
     }
 
+    pub fn max_search_steps(mut self, val: usize) -> Self {
+        self.max_search_steps = val;
+        self
+    }
+
     pub fn flat_penalty(mut self, val: L) -> Self {
         self.flat_penalty = val;
         self
@@ -105,6 +121,16 @@ impl SampleSeqRepetition {
         self.stacking_penalty = val;
         self
     }
+
+    /// Runs the sampler using `tokens` as the last tokens rather than
+    /// requiring a [HasSamplerResources] implementation.
+    pub fn sample_with_tokens<'a>(
+        &mut self,
+        tokens: &[TID],
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        self.sample(&mut TokenSliceSamplerResources(tokens), logits)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -115,12 +141,13 @@ struct SeqMatchResult<'a, T> {
     seq: &'a [T],
 }
 
-fn find_seqs<T: PartialEq + Copy + std::fmt::Debug>(
-    seq: &[T],
+fn find_seqs<'a, T: PartialEq + Copy + std::fmt::Debug>(
+    seq: &'a [T],
     min_len: usize,
     tolerance: usize,
     max_merge: usize,
-) -> Vec<SeqMatchResult<'_, T>> {
+    steps_left: &mut usize,
+) -> Vec<SeqMatchResult<'a, T>> {
     let seqlen = seq.len();
     if seqlen < min_len * 2 {
         return Vec::default();
@@ -134,8 +161,12 @@ fn find_seqs<T: PartialEq + Copy + std::fmt::Debug>(
         let mut needle = &seq[seqlen - nlen..];
 
         while seqlen >= nlen + min_len {
+            if *steps_left == 0 {
+                return result;
+            }
+            *steps_left -= 1;
             if hay[0] == needle[0] {
-                fuzzy_match(hay, needle, needle.len(), tolerance, max_merge)
+                fuzzy_match(hay, needle, needle.len(), tolerance, max_merge, steps_left)
                     .into_iter()
                     .filter(|(hidx, _)| hay.len() > needle.len() && hay.len() > hidx + 1)
                     .for_each(|(hidx, mlen)| {
@@ -166,6 +197,7 @@ fn fuzzy_match<T: PartialEq + std::fmt::Debug>(
     min_len: usize,
     mut tolerance: usize,
     merge_limit: usize,
+    steps_left: &mut usize,
 ) -> Vec<(usize, usize)> {
     let mut result = Vec::with_capacity(32);
     let mut window = 1;
@@ -174,6 +206,10 @@ fn fuzzy_match<T: PartialEq + std::fmt::Debug>(
     'outer: for (nidx, n) in needle.iter().enumerate() {
         while window > 0 {
             window -= 1;
+            if *steps_left == 0 {
+                break 'outer;
+            }
+            *steps_left -= 1;
             let Some((hidx, h)) = hi.next() else {
                 break 'outer;
             };
@@ -206,13 +242,14 @@ impl Sampler for SampleSeqRepetition {
             min_length,
             flat_penalty,
             stacking_penalty,
+            max_search_steps,
             ..
         } = *self;
 
         if logits.is_empty()
             || (flat_penalty == 0f32 && stacking_penalty == 0f32)
             || min_length < 2
-            || last_n < min_length
+            || last_n < min_length * 2
         {
             return Ok(logits);
         }
@@ -229,27 +266,37 @@ impl Sampler for SampleSeqRepetition {
                 &orig_tokens[orig_tokens.len() - last_n..]
             };
 
-            find_seqs(tokens, self.min_length, self.tolerance, self.max_merge)
-                .into_iter()
-                .filter(|mi| !mi.seq.is_empty())
-                .for_each(|mi| {
-                    let seqlen = mi.seq.len();
-                    penalize
-                        .entry(mi.seq[seqlen - 1])
-                        .and_modify(|prevseqlen| *prevseqlen = (*prevseqlen).max(seqlen))
-                        .or_insert(seqlen);
-                });
+            let mut steps_left = if max_search_steps == 0 {
+                usize::MAX
+            } else {
+                max_search_steps
+            };
+            find_seqs(
+                tokens,
+                self.min_length,
+                self.tolerance,
+                self.max_merge,
+                &mut steps_left,
+            )
+            .into_iter()
+            .filter(|mi| !mi.seq.is_empty())
+            .for_each(|mi| {
+                let seqlen = mi.seq.len();
+                penalize
+                    .entry(mi.seq[seqlen - 1])
+                    .and_modify(|prevseqlen| *prevseqlen = (*prevseqlen).max(seqlen))
+                    .or_insert(seqlen);
+            });
         })?;
 
         let mut changed = 0;
 
         for (tid, seqlen) in penalize.into_iter() {
-            let Some(idx) = logits
-                .iter()
-                .enumerate()
-                .find(|(_, l)| l.token_id == tid)
-                .map(|(idx, _)| idx)
-            else {
+            // Token ids are used as indexes, so they have to fit in a usize.
+            usize::try_from(tid).map_err(|_| SamplerError::TokenIdConversion)?;
+            // Token ids without an entry, for example ids past the end of a
+            // truncated vocabulary, can't be penalized.
+            let Some(idx) = logits.logit_index_for(tid) else {
                 continue;
             };
 
@@ -267,6 +314,8 @@ impl Sampler for SampleSeqRepetition {
         }
         Ok(logits)
     }
+
+    sampler_metadata_from_options!();
 }
 
 impl ConfigurableSampler<usize, L> for SampleSeqRepetition {}
@@ -327,6 +376,15 @@ impl HasSamplerMetadata<usize, L> for SampleSeqRepetition {
                     )),
                     option_type: SamplerOptionType::UInt,
                 },
+                SamplerOptionMetadata {
+                    key: "max_search_steps",
+                    description: Some(concat!(
+                        "Limits the amount of work the sequence search can do, ",
+                        "only matches found before reaching the limit are penalized. ",
+                        "Use 0 for no limit."
+                    )),
+                    option_type: SamplerOptionType::UInt,
+                },
             ],
         }
     }
@@ -342,6 +400,7 @@ impl HasSamplerMetadata<usize, L> for SampleSeqRepetition {
                     Some(SamplerOptionValueMut::UInt(&mut self.tolerance)),
                     Some(SamplerOptionValueMut::UInt(&mut self.max_merge)),
                     Some(SamplerOptionValueMut::UInt(&mut self.last_n)),
+                    Some(SamplerOptionValueMut::UInt(&mut self.max_search_steps)),
                 ],
             )
         }
@@ -358,6 +417,7 @@ impl HasSamplerMetadata<usize, L> for SampleSeqRepetition {
                     Some(SamplerOptionValue::UInt(self.tolerance)),
                     Some(SamplerOptionValue::UInt(self.max_merge)),
                     Some(SamplerOptionValue::UInt(self.last_n)),
+                    Some(SamplerOptionValue::UInt(self.max_search_steps)),
                 ],
             )
         }
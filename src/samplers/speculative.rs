@@ -0,0 +1,162 @@
+use crate::{configure::*, samplers::rand_distrib::*, types::*};
+
+/// # Speculative decoding acceptance sampling
+/// Decides whether to accept a token proposed by a draft model given the target
+/// model's distribution in the logits. The draft token is accepted with probability
+/// `min(1, p_target / p_draft)`. On rejection, a token is selected from the residual
+/// distribution `max(0, p_target - p_draft)`.
+///
+/// Since only the draft model's probability for the draft token is known, the draft
+/// probability of every other token is treated as `0`, so the residual distribution
+/// is the target distribution with the draft token's probability reduced.
+///
+/// Set the draft token for each step with [SampleSpeculative::set_draft]. A draft token
+/// is only used by the next run, so a stale draft is never applied to a later step. If
+/// no draft token is set, a token is selected from the target distribution.
+///
+/// **Properties**:
+/// - Modifies logits
+/// - Selects a token
+///
+/// **Parameters**:
+/// - `draft`: Draft token id and its probability under the draft model. (default: `None`)
+#[derive(Debug, Clone, Default)]
+pub struct SampleSpeculative {
+    pub(crate) draft: Option<(TID, L)>,
+    pub(crate) token: Option<TID>,
+    pub(crate) logit: Option<Logit>,
+    pub(crate) accepted: Option<bool>,
+    rd_sampler: SampleRandDistrib,
+}
+
+impl SampleSpeculative {
+    pub fn new(draft_token: TID, draft_prob: L) -> Self {
+        Self {
+            draft: Some((draft_token, draft_prob)),
+            ..Default::default()
+        }
+    }
+
+    pub fn draft(mut self, val: Option<(TID, L)>) -> Self {
+        self.draft = val;
+        self
+    }
+
+    /// Sets the draft token and its draft probability in place.
+    pub fn set_draft(&mut self, draft_token: TID, draft_prob: L) -> &mut Self {
+        self.draft = Some((draft_token, draft_prob));
+        self
+    }
+
+    /// Returns whether the draft token was accepted by the last sample call.
+    /// This is [None] if there was no draft token or no token was selected.
+    pub fn accepted(&self) -> Option<bool> {
+        self.accepted
+    }
+}
+
+impl Sampler for SampleSpeculative {
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        self.token = None;
+        self.logit = None;
+        self.accepted = None;
+        if logits.is_empty() {
+            return Ok(logits);
+        }
+        let Some((draft_tid, draft_prob)) = self.draft.take() else {
+            self.token = self
+                .rd_sampler
+                .sample_token(res, logits)
+                .map_err(rng_context("speculative"))?;
+            self.logit = self.rd_sampler.sampled_logit();
+            return Ok(logits);
+        };
+
+        logits.ensure_softmax()?;
+        let target = logits.iter().find(|l| l.token_id == draft_tid).copied();
+        let target_prob = target.map_or(0f32, |l| l.prob);
+        let accept_prob = if draft_prob > 0f32 {
+            (target_prob / draft_prob).min(1f32)
+        } else if target_prob > 0f32 {
+            1f32
+        } else {
+            0f32
+        };
+
+        let mut rval = 0f32;
+        res.with_rand_f64(&mut |next| rval = next() as L)
+            .map_err(|err| match err {
+                SamplerError::MissingResource(_) => SamplerError::MissingRng("speculative"),
+                err => err,
+            })?;
+        if rval < accept_prob {
+            self.token = Some(draft_tid);
+            self.logit = target;
+            self.accepted = Some(true);
+            return Ok(logits);
+        }
+
+        // Rebuild the logits from the residual distribution. The log of the
+        // probability differs from the original logit by a constant, so
+        // softmax gives the same distribution for the other tokens.
+        logits.iter_mut().for_each(|l| {
+            let prob = if l.token_id == draft_tid {
+                (l.prob - draft_prob).max(0f32)
+            } else {
+                l.prob
+            };
+            l.logit = prob.ln();
+        });
+        logits.clear_flags();
+        self.token = self
+            .rd_sampler
+            .sample_token(res, logits)
+            .map_err(rng_context("speculative"))?;
+        self.logit = self.rd_sampler.sampled_logit();
+        self.accepted = self.token.map(|_| false);
+        Ok(logits)
+    }
+
+    fn sampled_token_id(&self) -> Option<TID> {
+        self.token
+    }
+
+    fn sampled_logit(&self) -> Option<Logit> {
+        self.logit
+    }
+
+    /// Clears the result of the last run and any draft token that wasn't used yet.
+    fn reset(&mut self) {
+        self.draft = None;
+        self.token = None;
+        self.logit = None;
+        self.accepted = None;
+        self.rd_sampler.reset();
+    }
+
+    sampler_metadata_from_options!();
+}
+
+impl<UI: ConfigurableNumValue, F: ConfigurableNumValue> ConfigurableSampler<UI, F>
+    for SampleSpeculative
+{
+}
+
+impl<UI: ConfigurableNumValue, F: ConfigurableNumValue> HasSamplerMetadata<UI, F>
+    for SampleSpeculative
+{
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "speculative",
+            description: Some(concat!(
+                "Accepts or rejects a draft token based on the ratio of the target ",
+                "and draft probabilities. The draft token can't be set via options."
+            )),
+            options: vec![],
+        }
+    }
+}
@@ -13,10 +13,19 @@ use crate::{configure::*, types::*};
 /// - `min_keep`: Minimum number of entries to keep. Setting this to `0` is not recommended. (default: `1`)
 /// - `z`: The z parameter. It is not entirely clear what a reasonable value here is but 1.0 appears to be
 ///   the same as disabled which is similar to top-p sampling. (default: `1.0`)
+/// - `max_consider`: If set, only the top `max_consider` entries by logit are kept before
+///   calculating the derivatives, which bounds the cost on large vocabularies.
+///   Only settable with [SampleTailFree::max_consider]. (default: `None`)
+/// - `epsilon`: Values of `z` above `1.0 - epsilon` are treated as `1.0` and skip the
+///   derivative calculations since they would filter almost nothing. The default of
+///   `0.0` only skips them when `z` is at least `1.0`, so results are unchanged unless
+///   this is set. (default: `0.0`)
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct SampleTailFree {
     pub(crate) z: L,
     pub(crate) min_keep: usize,
+    pub(crate) max_consider: Option<usize>,
+    pub(crate) epsilon: L,
 }
 
 impl Default for SampleTailFree {
@@ -24,13 +33,19 @@ impl Default for SampleTailFree {
         Self {
             z: 1f32,
             min_keep: 1,
+            max_consider: None,
+            epsilon: 0f32,
         }
     }
 }
 
 impl SampleTailFree {
     pub fn new(z: L, min_keep: usize) -> Self {
-        Self { z, min_keep }
+        Self {
+            z,
+            min_keep,
+            ..Self::default()
+        }
     }
 
     pub fn min_keep(mut self, val: usize) -> Self {
@@ -42,6 +57,16 @@ impl SampleTailFree {
         self.z = val;
         self
     }
+
+    pub fn max_consider(mut self, val: Option<usize>) -> Self {
+        self.max_consider = val;
+        self
+    }
+
+    pub fn epsilon(mut self, val: L) -> Self {
+        self.epsilon = val;
+        self
+    }
 }
 
 impl Sampler for SampleTailFree {
@@ -50,64 +75,51 @@ impl Sampler for SampleTailFree {
         _res: &mut dyn HasSamplerResources,
         logits: &'a mut Logits,
     ) -> anyhow::Result<&'a mut Logits> {
-        use std::ops::ControlFlow::*;
-
-        let Self { z, min_keep } = *self;
-
-        if z >= 1f32 || logits.len() < 2 {
+        let Self {
+            z,
+            min_keep,
+            max_consider,
+            epsilon,
+        } = *self;
+
+        // The second derivative needs at least three entries.
+        if z >= 1f32 || z > 1f32 - epsilon || logits.len() < 3 {
             return Ok(logits);
         }
+        let max_consider = max_consider.map(|mc| mc.max(effective_min_keep(min_keep)));
+        if let Some(max_consider) = max_consider.filter(|mc| *mc < logits.len()) {
+            *logits = std::mem::take(logits).into_top_k(max_consider);
+            if logits.len() < 3 {
+                return Ok(logits);
+            }
+        }
 
         logits.ensure_softmax()?;
 
-        let mut fderivs = logits
-            .iter()
-            .take(logits.len() - 1)
-            .enumerate()
-            .map(|(idx, l)| l.prob - logits[idx + 1].prob)
-            .peekable();
-
-        let want_sderivs = logits.len() - 2;
-        let mut sderivs = Vec::with_capacity(want_sderivs);
-        let mut ssum = 0f32;
-
-        while let Some(prob) = fderivs.next() {
-            let sprob = (prob
-                - *fderivs.peek().ok_or_else(|| {
-                    SamplerError::InternalError(String::from(
-                        "Impossible: missing next deriv item?",
-                    ))
-                })?)
-            .abs();
-            ssum += sprob;
-            sderivs.push(sprob);
-            if sderivs.len() == want_sderivs {
-                break;
-            }
-        }
+        let fderivs = logits
+            .windows(2)
+            .map(|w| w[0].prob - w[1].prob)
+            .collect::<Vec<_>>();
+        let mut sderivs = fderivs
+            .windows(2)
+            .map(|w| (w[0] - w[1]).abs())
+            .collect::<Vec<_>>();
+        let ssum = sderivs.iter().sum::<L>();
         sderivs.iter_mut().for_each(|prob| *prob /= ssum);
 
         let mut cum_sum = 0f32;
-        let last_idx =
-            match sderivs
-                .into_iter()
-                .enumerate()
-                .try_fold(logits.len(), |last_idx, (idx, prob)| {
-                    cum_sum += prob;
-                    if cum_sum > z && idx >= min_keep {
-                        return Break(idx);
-                    }
-                    Continue(last_idx)
-                }) {
-                Continue(i) => i,
-                Break(i) => i,
-            };
-        if last_idx != logits.len() {
-            logits.truncate(last_idx);
-            logits.set_softmax(false);
-        }
+        let last_idx = sderivs
+            .into_iter()
+            .position(|prob| {
+                cum_sum += prob;
+                cum_sum > z
+            })
+            .unwrap_or_else(|| logits.len());
+        logits.truncate_keeping(last_idx, min_keep);
         Ok(logits)
     }
+
+    sampler_metadata_from_options!();
 }
 
 impl ConfigurableSampler<usize, L> for SampleTailFree {}
@@ -139,6 +151,13 @@ impl HasSamplerMetadata<usize, L> for SampleTailFree {
                     )),
                     option_type: SamplerOptionType::UInt,
                 },
+                SamplerOptionMetadata {
+                    key: "epsilon",
+                    description: Some(
+                        "Values of z above 1.0 - epsilon are treated as 1.0, skipping the filter.",
+                    ),
+                    option_type: SamplerOptionType::Float,
+                },
             ],
         }
     }
@@ -150,6 +169,7 @@ impl HasSamplerMetadata<usize, L> for SampleTailFree {
                 [
                     Some(SamplerOptionValueMut::Float(&mut self.z)),
                     Some(SamplerOptionValueMut::UInt(&mut self.min_keep)),
+                    Some(SamplerOptionValueMut::Float(&mut self.epsilon)),
                 ],
             )
         }
@@ -162,6 +182,7 @@ impl HasSamplerMetadata<usize, L> for SampleTailFree {
                 [
                     Some(SamplerOptionValue::Float(self.z)),
                     Some(SamplerOptionValue::UInt(self.min_keep)),
+                    Some(SamplerOptionValue::Float(self.epsilon)),
                 ],
             )
         }
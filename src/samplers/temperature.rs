@@ -4,32 +4,54 @@ use crate::{configure::*, types::*};
 /// **Temperature** controls how random the output is. Only relevant when using
 /// samplers that utilize RNG.
 ///
+/// A temperature of `0.0` collapses the distribution to the single most likely
+/// token (like [crate::samplers::SampleGreedy]) by setting every other logit to
+/// negative infinity.
+///
 /// **Properties**:
 ///
 /// - Modifies logits
 ///
 /// **Parameters**:
 /// - `temperature`: Temperature value. (default: `0.8`)
+/// - `prob_space`: When the logits already have softmax applied (for example
+///   when built with [Logits::from_probs]), apply the temperature as
+///   `prob^(1/temperature)` and renormalize. This is equivalent to dividing the
+///   logits but works directly on the probabilities. Only settable with
+///   [SampleTemperature::prob_space] so a bare value still configures the temperature.
+///   (default: `false`)
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct SampleTemperature {
     pub(crate) temperature: L,
+    pub(crate) prob_space: bool,
 }
 
 impl Default for SampleTemperature {
     fn default() -> Self {
-        Self { temperature: 1f32 }
+        Self {
+            temperature: 1f32,
+            prob_space: false,
+        }
     }
 }
 
 impl SampleTemperature {
     pub fn new(temperature: L) -> Self {
-        Self { temperature }
+        Self {
+            temperature,
+            prob_space: false,
+        }
     }
 
     pub fn temperature(mut self, val: L) -> Self {
         self.temperature = val;
         self
     }
+
+    pub fn prob_space(mut self, val: bool) -> Self {
+        self.prob_space = val;
+        self
+    }
 }
 
 impl Sampler for SampleTemperature {
@@ -39,12 +61,52 @@ impl Sampler for SampleTemperature {
         logits: &'a mut Logits,
     ) -> anyhow::Result<&'a mut Logits> {
         let temp = self.temperature;
+        if temp != 0f32 && self.prob_space && logits.get_softmax() {
+            let exponent = 1f32 / temp;
+            let sum = logits.iter_mut().fold(0f32, |sum, l| {
+                l.prob = l.prob.powf(exponent);
+                sum + l.prob
+            });
+            if sum.is_normal() {
+                // The transform is monotonic so the sort order is preserved.
+                logits.iter_mut().for_each(|l| {
+                    l.prob /= sum;
+                    l.logit = l.prob.ln();
+                });
+                return Ok(logits);
+            }
+            // Fall back to logit space if the probabilities underflowed.
+            logits.set_softmax(false);
+        }
         if temp != 0f32 {
             logits.iter_mut().for_each(|l| l.logit /= temp);
             logits.set_softmax(false);
+            return Ok(logits);
         }
+
+        let max_idx = if logits.get_sorted() {
+            0
+        } else {
+            let Some((max_idx, _)) = logits
+                .iter()
+                .enumerate()
+                .filter(|(_, l)| !l.logit.is_nan())
+                .max_by(|(_, x), (_, y)| x.logit.total_cmp(&y.logit))
+            else {
+                return Ok(logits);
+            };
+            max_idx
+        };
+        logits
+            .iter_mut()
+            .enumerate()
+            .filter(|(idx, _)| *idx != max_idx)
+            .for_each(|(_, l)| l.logit = L::NEG_INFINITY);
+        logits.set_softmax(false);
         Ok(logits)
     }
+
+    sampler_metadata_from_options!();
 }
 
 impl<UI: ConfigurableNumValue> ConfigurableSampler<UI, L> for SampleTemperature {}
@@ -0,0 +1,104 @@
+use anyhow::Result;
+
+use crate::{configure::SamplerMetadata, types::*};
+
+/// # Toggle sampler
+/// Wraps another [Sampler] so it can be switched on and off without removing it
+/// from a [crate::prelude::SamplerChain]. When disabled, the logits are passed
+/// through untouched and no token id is reported.
+///
+/// **Properties**:
+/// - Same as the wrapped sampler when enabled
+///
+/// **Parameters**:
+/// - `enabled`: Whether the wrapped sampler runs. (default: `true`)
+#[derive(Debug, Clone)]
+pub struct ToggleSampler<S> {
+    pub(crate) inner: S,
+    pub(crate) enabled: bool,
+}
+
+impl<S: Sampler> ToggleSampler<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            enabled: true,
+        }
+    }
+
+    pub fn enabled(mut self, val: bool) -> Self {
+        self.enabled = val;
+        self
+    }
+
+    pub fn set_enabled(&mut self, val: bool) -> &mut Self {
+        self.enabled = val;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Sampler> Sampler for ToggleSampler<S> {
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> Result<&'a mut Logits> {
+        if !self.enabled {
+            return Ok(logits);
+        }
+        self.inner.sample(res, logits)
+    }
+
+    fn sampled_token_id(&self) -> Option<TID> {
+        self.enabled
+            .then(|| self.inner.sampled_token_id())
+            .flatten()
+    }
+
+    fn sampled_logit(&self) -> Option<Logit> {
+        self.enabled.then(|| self.inner.sampled_logit()).flatten()
+    }
+
+    fn try_sampled_token_id(&self) -> Result<Option<TID>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        self.inner.try_sampled_token_id()
+    }
+
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset()
+    }
+
+    fn save_state(&self) -> Option<SamplerState> {
+        self.inner.save_state()
+    }
+
+    fn load_state(&mut self, state: &SamplerState) -> Result<()> {
+        self.inner.load_state(state)
+    }
+
+    fn metadata(&self) -> SamplerMetadata {
+        self.inner.metadata()
+    }
+}
@@ -6,7 +6,6 @@ use crate::{configure::*, types::*};
 ///
 /// Credit to @BlinkDL on GitHub for design. See this link for a more in-depth
 /// explanation: https://github.com/BlinkDL/RWKV-LM#the-top-a-sampling-method
-
 ///
 /// **Properties**:
 /// - Filters logits
@@ -15,11 +14,15 @@ use crate::{configure::*, types::*};
 /// - `min_keep`: Minimum number of entries to keep. (default: `1`)
 /// - `a1`: Threshold scale. Use `0.0` to disable. (default: `0.2`)
 /// - `a2`: Threshold power. Use `0.0` to disable. (default: `2.0`)
+/// - `log_space`: Compare log-probabilities against the log of the threshold
+///   rather than computing probabilities. This avoids underflow with very large
+///   vocabularies. (default: `false`)
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct SampleTopA {
     pub(crate) a1: L,
     pub(crate) a2: L,
     pub(crate) min_keep: usize,
+    pub(crate) log_space: bool,
 }
 
 impl Default for SampleTopA {
@@ -28,13 +31,24 @@ impl Default for SampleTopA {
             a1: 0.2,
             a2: 2.0,
             min_keep: 1,
+            log_space: false,
         }
     }
 }
 
 impl SampleTopA {
     pub fn new(a1: L, a2: L, min_keep: usize) -> Self {
-        Self { a1, a2, min_keep }
+        Self {
+            a1,
+            a2,
+            min_keep,
+            log_space: false,
+        }
+    }
+
+    pub fn log_space(mut self, val: bool) -> Self {
+        self.log_space = val;
+        self
     }
 
     pub fn min_keep(mut self, val: usize) -> Self {
@@ -59,31 +73,49 @@ impl Sampler for SampleTopA {
         _res: &mut dyn HasSamplerResources,
         logits: &'a mut Logits,
     ) -> anyhow::Result<&'a mut Logits> {
-        let Self { a1, a2, min_keep } = *self;
+        let Self {
+            a1,
+            a2,
+            min_keep,
+            log_space,
+        } = *self;
         if logits.is_empty() || a1 == 0.0 || a2 == 0.0 {
             return Ok(logits);
         }
 
-        logits.ensure_softmax()?;
-
-        if logits.len() <= min_keep {
-            return Ok(logits);
-        }
-
-        let threshold = logits[0].prob.powf(a2) * a1;
-        let last_idx = logits
-            .iter()
-            .enumerate()
-            .skip(1.max(min_keep))
-            .find(|(_, l)| l.prob < threshold)
-            .map(|(idx, _)| idx)
-            .unwrap_or_else(|| logits.len());
-        if last_idx != logits.len() {
-            logits.truncate(last_idx);
-            logits.set_softmax(false);
+        let last_idx = if log_space {
+            logits.ensure_sorted()?;
+            let max_logit = logits[0].logit;
+            // ln(max_prob) = -ln(sum(exp(logit - max_logit)))
+            let ln_max_prob = -logits
+                .iter()
+                .map(|l| (l.logit - max_logit).exp())
+                .sum::<L>()
+                .ln();
+            // ln(prob) < ln(a1) + a2 * ln(max_prob), with ln(prob) = logit - max_logit + ln(max_prob)
+            let threshold = a1.ln() + (a2 - 1.0) * ln_max_prob;
+            logits
+                .iter()
+                .enumerate()
+                .skip(1)
+                .find(|(_, l)| l.logit - max_logit < threshold)
+                .map(|(idx, _)| idx)
+        } else {
+            logits.ensure_softmax()?;
+            let threshold = logits[0].prob.powf(a2) * a1;
+            logits
+                .iter()
+                .enumerate()
+                .skip(1)
+                .find(|(_, l)| l.prob < threshold)
+                .map(|(idx, _)| idx)
         }
+        .unwrap_or_else(|| logits.len());
+        logits.truncate_keeping(last_idx, min_keep);
         Ok(logits)
     }
+
+    sampler_metadata_from_options!();
 }
 
 impl ConfigurableSampler<usize, L> for SampleTopA {}
@@ -115,6 +147,11 @@ impl HasSamplerMetadata<usize, L> for SampleTopA {
                     )),
                     option_type: SamplerOptionType::UInt,
                 },
+                SamplerOptionMetadata {
+                    key: "log_space",
+                    description: Some("Compare against the threshold using log-probabilities."),
+                    option_type: SamplerOptionType::Bool,
+                },
             ],
         }
     }
@@ -127,6 +164,7 @@ impl HasSamplerMetadata<usize, L> for SampleTopA {
                     Some(SamplerOptionValueMut::Float(&mut self.a1)),
                     Some(SamplerOptionValueMut::Float(&mut self.a2)),
                     Some(SamplerOptionValueMut::UInt(&mut self.min_keep)),
+                    Some(SamplerOptionValueMut::Bool(&mut self.log_space)),
                 ],
             )
         }
@@ -140,6 +178,7 @@ impl HasSamplerMetadata<usize, L> for SampleTopA {
                     Some(SamplerOptionValue::Float(self.a1)),
                     Some(SamplerOptionValue::Float(self.a2)),
                     Some(SamplerOptionValue::UInt(self.min_keep)),
+                    Some(SamplerOptionValue::Bool(self.log_space)),
                 ],
             )
         }
@@ -10,21 +10,55 @@ use crate::{configure::*, types::*};
 /// **Parameters**:
 /// - `min_keep`: Minimum number of entries to keep. (default: `1`)
 /// - `k`: Number of entries to keep. (default: `40`)
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// - `max_p`: If set, stop keeping tokens once the kept tokens account for this much
+///   probability mass. Only settable with [SampleTopK::max_p]. (default: `None`)
+/// - `renormalize`: Recompute the probabilities of the remaining tokens so they
+///   sum to `1.0`. (default: `false`)
+/// - `include_ties`: Also keep tokens past the cutoff whose logit is equal to the
+///   last kept token, so equally likely tokens aren't dropped arbitrarily. (default: `false`)
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct SampleTopK {
     pub(crate) k: usize,
     pub(crate) min_keep: usize,
+    pub(crate) max_p: Option<L>,
+    pub(crate) renormalize: bool,
+    pub(crate) include_ties: bool,
 }
 
 impl Default for SampleTopK {
     fn default() -> Self {
-        Self { min_keep: 1, k: 40 }
+        Self {
+            min_keep: 1,
+            k: 40,
+            max_p: None,
+            renormalize: false,
+            include_ties: false,
+        }
     }
 }
 
 impl SampleTopK {
     pub fn new(k: usize, min_keep: usize) -> Self {
-        Self { k, min_keep }
+        Self {
+            k,
+            min_keep,
+            ..Self::default()
+        }
+    }
+
+    pub fn max_p(mut self, val: Option<L>) -> Self {
+        self.max_p = val;
+        self
+    }
+
+    pub fn include_ties(mut self, val: bool) -> Self {
+        self.include_ties = val;
+        self
+    }
+
+    pub fn renormalize(mut self, val: bool) -> Self {
+        self.renormalize = val;
+        self
     }
 
     pub fn min_keep(mut self, val: usize) -> Self {
@@ -44,14 +78,46 @@ impl Sampler for SampleTopK {
         _res: &mut dyn HasSamplerResources,
         logits: &'a mut Logits,
     ) -> anyhow::Result<&'a mut Logits> {
-        let k = self.k.max(self.min_keep).min(logits.len());
-        logits.ensure_sorted()?;
-        if k != logits.len() {
-            logits.truncate(k);
-            logits.set_softmax(false);
+        let Self {
+            k,
+            min_keep,
+            max_p,
+            renormalize,
+            include_ties,
+        } = *self;
+        let mut last_idx = if let Some(max_p) = max_p {
+            logits.ensure_softmax()?;
+            let mut cum_sum = 0f32;
+            logits
+                .iter()
+                .take(k)
+                .position(|l| {
+                    cum_sum += l.prob;
+                    cum_sum >= max_p
+                })
+                .map_or(k, |idx| idx + 1)
+        } else {
+            logits.ensure_sorted()?;
+            k
+        };
+        if include_ties {
+            last_idx = last_idx.max(effective_min_keep(min_keep));
+            if let Some(boundary) = last_idx.checked_sub(1).and_then(|idx| logits.get(idx)) {
+                let boundary = boundary.logit;
+                last_idx += logits[last_idx..]
+                    .iter()
+                    .take_while(|l| l.logit == boundary)
+                    .count();
+            }
+        }
+        logits.truncate_keeping(last_idx, min_keep);
+        if renormalize {
+            logits.ensure_softmax()?;
         }
         Ok(logits)
     }
+
+    sampler_metadata_from_options!();
 }
 
 impl<L: ConfigurableNumValue> ConfigurableSampler<usize, L> for SampleTopK {}
@@ -79,6 +145,20 @@ impl<L: ConfigurableNumValue> HasSamplerMetadata<usize, L> for SampleTopK {
                     )),
                     option_type: SamplerOptionType::UInt,
                 },
+                SamplerOptionMetadata {
+                    key: "renormalize",
+                    description: Some(
+                        "Recompute the probabilities of the remaining tokens after filtering.",
+                    ),
+                    option_type: SamplerOptionType::Bool,
+                },
+                SamplerOptionMetadata {
+                    key: "include_ties",
+                    description: Some(
+                        "Keep tokens past the cutoff that are tied with the last kept token.",
+                    ),
+                    option_type: SamplerOptionType::Bool,
+                },
             ],
         }
     }
@@ -90,6 +170,8 @@ impl<L: ConfigurableNumValue> HasSamplerMetadata<usize, L> for SampleTopK {
                 [
                     Some(SamplerOptionValueMut::UInt(&mut self.k)),
                     Some(SamplerOptionValueMut::UInt(&mut self.min_keep)),
+                    Some(SamplerOptionValueMut::Bool(&mut self.renormalize)),
+                    Some(SamplerOptionValueMut::Bool(&mut self.include_ties)),
                 ],
             )
         }
@@ -102,6 +184,8 @@ impl<L: ConfigurableNumValue> HasSamplerMetadata<usize, L> for SampleTopK {
                 [
                     Some(SamplerOptionValue::UInt(self.k)),
                     Some(SamplerOptionValue::UInt(self.min_keep)),
+                    Some(SamplerOptionValue::Bool(self.renormalize)),
+                    Some(SamplerOptionValue::Bool(self.include_ties)),
                 ],
             )
         }
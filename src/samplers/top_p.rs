@@ -11,10 +11,19 @@ use crate::{configure::*, types::*};
 /// **Parameters**:
 /// - `min_keep`: Minimum number of entries to keep. (default: `1`)
 /// - `p`: Target value. (default: `0.9`)
+/// - `inclusive`: Keep the token that makes the cumulative probability reach `p`.
+///   When `false`, only tokens while the cumulative probability is still below `p`
+///   are kept, which matches some other implementations. (default: `true`)
+/// - `min_p_mass`: If set, the kept tokens cover at least this much cumulative probability
+///   even when `p` is lower, so the effective target is `max(p, min_p_mass)`. Unlike
+///   `min_keep` this is a probability mass rather than a token count. Only settable with
+///   [SampleTopP::min_p_mass]. (default: `None`)
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct SampleTopP {
     pub(crate) p: L,
     pub(crate) min_keep: usize,
+    pub(crate) inclusive: bool,
+    pub(crate) min_p_mass: Option<L>,
 }
 
 impl Default for SampleTopP {
@@ -22,13 +31,30 @@ impl Default for SampleTopP {
         Self {
             p: 0.9f32,
             min_keep: 1,
+            inclusive: true,
+            min_p_mass: None,
         }
     }
 }
 
 impl SampleTopP {
     pub fn new(p: L, min_keep: usize) -> Self {
-        Self { p, min_keep }
+        Self {
+            p,
+            min_keep,
+            inclusive: true,
+            min_p_mass: None,
+        }
+    }
+
+    pub fn inclusive(mut self, val: bool) -> Self {
+        self.inclusive = val;
+        self
+    }
+
+    pub fn min_p_mass(mut self, val: Option<L>) -> Self {
+        self.min_p_mass = val;
+        self
     }
 
     pub fn min_keep(mut self, val: usize) -> Self {
@@ -48,32 +74,28 @@ impl Sampler for SampleTopP {
         _res: &mut dyn HasSamplerResources,
         logits: &'a mut Logits,
     ) -> anyhow::Result<&'a mut Logits> {
-        use std::ops::ControlFlow::*;
-
-        let Self { p, min_keep } = *self;
+        let Self {
+            p,
+            min_keep,
+            inclusive,
+            min_p_mass,
+        } = *self;
+        let p = min_p_mass.map_or(p, |mass| p.max(mass));
         logits.ensure_softmax()?;
 
         let mut cum_sum = 0f32;
-        let last_idx =
-            match logits
-                .iter()
-                .enumerate()
-                .try_fold(logits.len(), |last_idx, (idx, logit)| {
-                    cum_sum += logit.prob;
-                    if cum_sum >= p && idx + 1 >= min_keep {
-                        return Break(idx + 1);
-                    }
-                    Continue(last_idx)
-                }) {
-                Continue(i) => i,
-                Break(i) => i,
-            };
-        if last_idx != logits.len() {
-            logits.truncate(last_idx);
-            logits.set_softmax(false);
-        }
+        let last_idx = logits
+            .iter()
+            .position(|l| {
+                cum_sum += l.prob;
+                cum_sum >= p
+            })
+            .map_or_else(|| logits.len(), |idx| if inclusive { idx + 1 } else { idx });
+        logits.truncate_keeping(last_idx, min_keep);
         Ok(logits)
     }
+
+    sampler_metadata_from_options!();
 }
 
 impl ConfigurableSampler<usize, L> for SampleTopP {}
@@ -101,6 +123,13 @@ impl HasSamplerMetadata<usize, L> for SampleTopP {
                     )),
                     option_type: SamplerOptionType::UInt,
                 },
+                SamplerOptionMetadata {
+                    key: "inclusive",
+                    description: Some(
+                        "Keep the token that makes the cumulative probability reach p.",
+                    ),
+                    option_type: SamplerOptionType::Bool,
+                },
             ],
         }
     }
@@ -112,6 +141,7 @@ impl HasSamplerMetadata<usize, L> for SampleTopP {
                 [
                     Some(SamplerOptionValueMut::Float(&mut self.p)),
                     Some(SamplerOptionValueMut::UInt(&mut self.min_keep)),
+                    Some(SamplerOptionValueMut::Bool(&mut self.inclusive)),
                 ],
             )
         }
@@ -124,6 +154,7 @@ impl HasSamplerMetadata<usize, L> for SampleTopP {
                 [
                     Some(SamplerOptionValue::Float(self.p)),
                     Some(SamplerOptionValue::UInt(self.min_keep)),
+                    Some(SamplerOptionValue::Bool(self.inclusive)),
                 ],
             )
         }
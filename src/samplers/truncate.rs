@@ -0,0 +1,148 @@
+use crate::{configure::*, types::*};
+
+/// # Truncate sampling
+/// This sampler removes tokens with an absolute probability below `floor`.
+/// Unlike min-P, the threshold doesn't depend on the most probable token.
+/// This can be useful for trimming the long tail before random sampling.
+///
+/// **Properties**:
+/// - Filters logits
+///
+/// **Parameters**:
+/// - `min_keep`: Minimum number of entries to keep. (default: `1`)
+/// - `floor`: Probability threshold. Use `0.0` to disable. (default: `0.0001`)
+/// - `renormalize`: Recompute the probabilities of the remaining tokens. (default: `false`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleTruncate {
+    pub(crate) floor: L,
+    pub(crate) min_keep: usize,
+    pub(crate) renormalize: bool,
+}
+
+impl Default for SampleTruncate {
+    fn default() -> Self {
+        Self {
+            floor: 0.0001f32,
+            min_keep: 1,
+            renormalize: false,
+        }
+    }
+}
+
+impl SampleTruncate {
+    pub fn new(floor: L, min_keep: usize) -> Self {
+        Self {
+            floor,
+            min_keep,
+            ..Self::default()
+        }
+    }
+
+    pub fn min_keep(mut self, val: usize) -> Self {
+        self.min_keep = val;
+        self
+    }
+
+    pub fn floor(mut self, val: L) -> Self {
+        self.floor = val;
+        self
+    }
+
+    pub fn renormalize(mut self, val: bool) -> Self {
+        self.renormalize = val;
+        self
+    }
+}
+
+impl Sampler for SampleTruncate {
+    fn sample<'a>(
+        &mut self,
+        _res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        let Self {
+            floor,
+            min_keep,
+            renormalize,
+        } = *self;
+        if floor <= 0f32 || logits.is_empty() {
+            return Ok(logits);
+        }
+
+        logits.ensure_softmax()?;
+
+        let last_idx = logits
+            .iter()
+            .position(|l| l.prob < floor)
+            .unwrap_or_else(|| logits.len());
+        logits.truncate_keeping(last_idx, min_keep);
+        if renormalize {
+            logits.ensure_softmax()?;
+        }
+        Ok(logits)
+    }
+
+    sampler_metadata_from_options!();
+}
+
+impl ConfigurableSampler<usize, L> for SampleTruncate {}
+
+impl HasSamplerMetadata<usize, L> for SampleTruncate {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "truncate",
+            description: Some(concat!(
+                "This sampler removes tokens with an absolute probability below floor.",
+                " Unlike min-P, the threshold doesn't depend on the most probable token."
+            )),
+            options: vec![
+                SamplerOptionMetadata {
+                    key: "floor",
+                    description: Some("Probability threshold."),
+                    option_type: SamplerOptionType::Float,
+                },
+                SamplerOptionMetadata {
+                    key: "min_keep",
+                    description: Some(concat!(
+                        "Minimum number of tokens to keep after sampling. ",
+                        "Setting this to 0 is not recommended."
+                    )),
+                    option_type: SamplerOptionType::UInt,
+                },
+                SamplerOptionMetadata {
+                    key: "renormalize",
+                    description: Some(
+                        "Recompute the probabilities of the remaining tokens after filtering.",
+                    ),
+                    option_type: SamplerOptionType::Bool,
+                },
+            ],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValueMut::Float(&mut self.floor)),
+                    Some(SamplerOptionValueMut::UInt(&mut self.min_keep)),
+                    Some(SamplerOptionValueMut::Bool(&mut self.renormalize)),
+                ],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValue::Float(self.floor)),
+                    Some(SamplerOptionValue::UInt(self.min_keep)),
+                    Some(SamplerOptionValue::Bool(self.renormalize)),
+                ],
+            )
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+
+use crate::{configure::*, resource::TokenSliceSamplerResources, types::*};
+
+/// Weighting function used by [SampleWindowedRepetition]. `distance` is how far
+/// an occurrence is from the end of the window: the most recent token has a distance
+/// of `0` and the oldest considered token has a distance of `last_n - 1`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RepetitionWindow {
+    /// Every occurrence within the window has a weight of `1.0`.
+    #[default]
+    Step,
+    /// Weight falls off linearly: `1.0 - distance / last_n`.
+    Linear,
+    /// Weight falls off exponentially: `rate^distance`.
+    Exponential(L),
+}
+
+impl RepetitionWindow {
+    /// Returns the weight for an occurrence at `distance` in a window of `last_n` tokens.
+    pub fn weight(&self, distance: usize, last_n: usize) -> L {
+        match *self {
+            Self::Step => 1f32,
+            Self::Linear => 1f32 - distance as L / last_n as L,
+            Self::Exponential(rate) => rate.powf(distance as L),
+        }
+    }
+}
+
+/// # Windowed repetition penalty sampling
+/// A more general version of [crate::samplers::SampleRepetition]. Each occurrence of a token
+/// in the `last_n` tokens gets a weight based on how recently it appeared (see
+/// [RepetitionWindow]) and the weights are summed per token. A token with total weight
+/// `w` is penalized by `repetition_penalty^w`, so the penalty reflects both how
+/// recently and how often the token occurred.
+///
+/// **Properties**:
+/// - Modifies logits
+///
+/// **Parameters**:
+/// - `last_n`: Number of last tokens to consider. (default: `64`)
+/// - `repetition_penalty`: Penalty to apply to repeated tokens. (default: `1.1`)
+/// - `window`: Weighting function, not configurable via options. (default: [RepetitionWindow::Step])
+#[derive(Debug, Clone)]
+pub struct SampleWindowedRepetition {
+    pub(crate) repetition_penalty: L,
+    pub(crate) last_n: usize,
+    pub(crate) window: RepetitionWindow,
+}
+
+impl Default for SampleWindowedRepetition {
+    fn default() -> Self {
+        Self {
+            repetition_penalty: 1.1f32,
+            last_n: 64,
+            window: RepetitionWindow::default(),
+        }
+    }
+}
+
+impl SampleWindowedRepetition {
+    pub fn new(repetition_penalty: L, last_n: usize, window: RepetitionWindow) -> Self {
+        Self {
+            repetition_penalty,
+            last_n,
+            window,
+        }
+    }
+
+    pub fn last_n(mut self, val: usize) -> Self {
+        self.last_n = val;
+        self
+    }
+
+    pub fn penalty(mut self, val: L) -> Self {
+        self.repetition_penalty = val;
+        self
+    }
+
+    pub fn window(mut self, val: RepetitionWindow) -> Self {
+        self.window = val;
+        self
+    }
+
+    /// Runs the sampler using `tokens` as the last tokens rather than
+    /// requiring a [HasSamplerResources] implementation.
+    pub fn sample_with_tokens<'a>(
+        &mut self,
+        tokens: &[TID],
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        self.sample(&mut TokenSliceSamplerResources(tokens), logits)
+    }
+}
+
+impl Sampler for SampleWindowedRepetition {
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        let Self {
+            repetition_penalty,
+            last_n,
+            window,
+        } = *self;
+
+        if logits.is_empty() || last_n == 0 || repetition_penalty <= 1f32 {
+            return Ok(logits);
+        }
+
+        let mut weights = HashMap::with_capacity(last_n);
+        res.with_last_tokens(&mut |tokens| {
+            tokens
+                .iter()
+                .rev()
+                .take(last_n)
+                .enumerate()
+                .for_each(|(distance, tid)| {
+                    *weights.entry(*tid).or_insert(0f32) += window.weight(distance, last_n);
+                });
+        })?;
+
+        let mut changed = 0;
+        logits.iter_mut().for_each(|l| {
+            let Some(weight) = weights.get(&l.token_id).copied() else {
+                return;
+            };
+            if weight <= 0f32 {
+                return;
+            }
+            let penalty = repetition_penalty.powf(weight);
+            l.logit = if l.logit <= 0f32 {
+                l.logit * penalty
+            } else {
+                l.logit / penalty
+            };
+            changed += 1;
+        });
+
+        if changed > 0 {
+            logits.set_sorted(false);
+            logits.set_softmax(false);
+        }
+        Ok(logits)
+    }
+
+    sampler_metadata_from_options!();
+}
+
+impl ConfigurableSampler<usize, L> for SampleWindowedRepetition {}
+
+impl HasSamplerMetadata<usize, L> for SampleWindowedRepetition {
+    fn sampler_metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "windowed repetition",
+            description: Some(concat!(
+                "Applies a penalty to tokens that have appeared within the previous last_n tokens, ",
+                "weighting each occurrence by how recently it appeared."
+            )),
+            options: vec![
+                SamplerOptionMetadata {
+                    key: "penalty",
+                    description: Some(
+                        "Penalty to apply to tokens that meet the repetition criteria.",
+                    ),
+                    option_type: SamplerOptionType::Float,
+                },
+                SamplerOptionMetadata {
+                    key: "last_n",
+                    description: Some(
+                        "Number of previous tokens to consider when determining repetition.",
+                    ),
+                    option_type: SamplerOptionType::UInt,
+                },
+            ],
+        }
+    }
+
+    fn sampler_options_mut(&mut self) -> SamplerOptions<SamplerOptionValueMut<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValueMut::Float(&mut self.repetition_penalty)),
+                    Some(SamplerOptionValueMut::UInt(&mut self.last_n)),
+                ],
+            )
+        }
+    }
+
+    fn sampler_options(&self) -> SamplerOptions<SamplerOptionValue<'_, usize, L>> {
+        unsafe {
+            SamplerOptions::build_options(
+                self.sampler_metadata().options,
+                [
+                    Some(SamplerOptionValue::Float(self.repetition_penalty)),
+                    Some(SamplerOptionValue::UInt(self.last_n)),
+                ],
+            )
+        }
+    }
+}
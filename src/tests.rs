@@ -116,6 +116,402 @@ fn test_logits_with_top_k() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_logits_into_top_k() -> anyhow::Result<()> {
+    use rand::{seq::SliceRandom, SeedableRng};
+
+    let mut v = Vec::from_iter(std::iter::successors(Some(5f32), |n| Some(n - 0.5)).take(200));
+    v.extend_from_slice(&[3.0, 3.0, -1.0]);
+    v.shuffle(&mut rand::rngs::StdRng::seed_from_u64(123));
+    let logits = Logits::try_from_iter(v)?;
+
+    for k in [0, 1, 7, 20, 500] {
+        let mut expected = logits.clone();
+        expected.ensure_sorted()?.truncate(k);
+        let borrowed = logits.top_k(k);
+        let owned = logits.clone().into_top_k(k);
+        assert!(borrowed.get_sorted() && owned.get_sorted());
+        assert_eq!(*borrowed, *expected);
+        assert_eq!(*owned, *expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_logits_cumulative_probability() -> Result<()> {
+    let mut logits = Logits::try_from_iter(T1.iter().map(|p| p.ln()))?;
+    let close = |v: Option<f32>, e: f32| (v.expect("missing value") - e).abs() < 0.00001;
+    assert!(close(logits.cumulative_probability(3)?, 0.4));
+    assert!(close(logits.cumulative_probability(2)?, 0.7));
+    assert!(close(logits.cumulative_probability(0)?, 1.0));
+    assert_eq!(logits.cumulative_probability(4)?, None);
+
+    assert_eq!(logits.nucleus_size(0.0)?, 1);
+    assert_eq!(logits.nucleus_size(0.5)?, 2);
+    assert_eq!(logits.nucleus_size(0.75)?, 3);
+    assert_eq!(logits.nucleus_size(1.5)?, 4);
+
+    for p in [0.0, 0.5, 0.75, 1.0] {
+        let mut filtered = Logits::try_from_iter(T1.iter().map(|p| p.ln()))?;
+        filtered.sample(&mut (), &mut SampleTopP::new(p, 1))?;
+        assert_eq!(logits.nucleus_size(p)?, filtered.len());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_softmax_degenerate() -> Result<()> {
+    let probs = |it: &[f32]| -> Result<Vec<f32>> {
+        Ok(Logits::try_from_iter(it.iter().copied())?
+            .ensure_softmax()?
+            .iter()
+            .map(|l| l.prob)
+            .collect())
+    };
+    let ninf = f32::NEG_INFINITY;
+
+    assert_eq!(probs(&[ninf, ninf, ninf, ninf])?, vec![0.25; 4]);
+    assert_eq!(probs(&[-1e38, -1e38, -1e38, -1e38])?, vec![0.25; 4]);
+    assert_eq!(probs(&[f32::MIN, ninf])?, vec![1.0, 0.0]);
+    assert_eq!(
+        probs(&[1.0, f32::INFINITY, f32::INFINITY])?,
+        vec![0.5, 0.5, 0.0]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_logits_blend() -> Result<()> {
+    let mut logits = Logits::try_from_iter([1.0f32, 2.0, 3.0, 4.0])?;
+    let mut other = Logits::try_from_iter([3.0f32, 0.0, 3.0, -4.0])?;
+    // Blending should match by token id even if the order differs.
+    other.ensure_sorted()?;
+    logits.ensure_softmax()?;
+
+    logits.blend(&other, 0.5)?;
+    assert_eq!(
+        logits.to_logit_pairs(),
+        vec![(3, 0.0), (2, 3.0), (1, 1.0), (0, 2.0)]
+    );
+    assert!(!logits.get_sorted() && !logits.get_softmax());
+
+    let short = Logits::try_from_iter([1.0f32, 2.0])?;
+    assert!(logits.blend(&short, 0.5).is_err());
+
+    let mut shifted = Logits::try_from_iter([0.0f32; 5])?;
+    shifted.remove(0);
+    let err = logits.blend(&shifted, 0.5).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<LogitsError>(),
+        Some(LogitsError::MissingTokenId(0))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_logits_approx_eq() -> Result<()> {
+    let mut logits = Logits::try_from_iter([0.1f32, 0.2, 0.3, f32::NEG_INFINITY])?;
+    let mut other = Logits::try_from_iter([0.1f32, 0.2000001, 0.2999999, f32::NEG_INFINITY])?;
+    assert!(logits.approx_eq(&other, 1e-5));
+    assert!(!logits.approx_eq(&other, 0.0));
+    logits.ensure_softmax()?;
+    other.ensure_softmax()?;
+    assert!(logits.probs_approx_eq(&other, 1e-5));
+
+    let shifted = Logits::try_from_iter([0.1f32, 0.25, 0.3, f32::NEG_INFINITY])?;
+    assert!(
+        !Logits::try_from_iter([0.1f32, 0.2, 0.3, f32::NEG_INFINITY])?.approx_eq(&shifted, 1e-5)
+    );
+    // Every value is within the tolerance, but softmax sorted `logits` so the
+    // token order differs.
+    assert!(!logits.approx_eq(&shifted, 1.0));
+    Ok(())
+}
+
+#[test]
+fn test_logits_from_probs() -> Result<()> {
+    const PROBS: &[f32] = &[0.1, 0.4, 0.2, 0.3];
+    let mut from_probs = Logits::from_probs(PROBS)?;
+    assert!(from_probs.get_sorted() && from_probs.get_softmax());
+    assert_eq!(
+        from_probs.iter().map(|l| l.token_id).collect::<Vec<_>>(),
+        vec![1, 3, 2, 0]
+    );
+
+    let mut from_logits = Logits::try_from_iter(PROBS.iter().map(|p| p.ln()))?;
+    let mut res = NilSamplerResources;
+    from_probs.sample(&mut res, &mut SampleTopP::new(0.6, 1))?;
+    from_logits.sample(&mut res, &mut SampleTopP::new(0.6, 1))?;
+    assert!(from_probs.probs_approx_eq(&from_logits, 1e-6));
+    assert_eq!(from_probs.len(), 2);
+
+    // Unnormalized input is normalized.
+    let logits = Logits::from_probs(&[2.0, 2.0])?;
+    assert_eq!(
+        logits.iter().map(|l| l.prob).collect::<Vec<_>>(),
+        vec![0.5, 0.5]
+    );
+
+    assert!(Logits::from_probs(&[0.5, -0.1]).is_err());
+    assert!(Logits::from_probs(&[0.5, f32::NAN]).is_err());
+    assert!(Logits::from_probs(&[0.0, 0.0]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_shared_sampler_poisoned() -> Result<()> {
+    use std::sync::{Arc, Mutex};
+
+    let mut shared: Arc<Mutex<dyn Sampler>> = Arc::new(Mutex::new(SampleGreedy::new()));
+    let mut logits = Logits::try_from_iter([0.1f32, 0.2, 0.3])?;
+    assert_eq!(shared.sample_token(&mut (), &mut logits)?, Some(2));
+    assert_eq!(shared.try_sampled_token_id()?, Some(2));
+
+    let poisoner = shared.clone();
+    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _guard = poisoner.lock().unwrap();
+        panic!("poisoning the lock");
+    }));
+    assert_eq!(shared.sampled_token_id(), None);
+    assert!(shared.try_sampled_token_id().is_err());
+    assert!(shared.sample(&mut (), &mut logits).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_logits_apply_mask() -> Result<()> {
+    let ninf = f32::NEG_INFINITY;
+    let mut logits = Logits::try_from_iter([0.1f32, 0.2, 0.3, 0.4, 0.5])?;
+    logits.ensure_softmax()?;
+    // Token 4 is past the end of the mask so it's disallowed too.
+    logits.apply_mask(&[true, false, true, false]);
+    assert!(!logits.get_sorted() && !logits.get_softmax());
+    let mut masked = logits.to_logit_pairs();
+    masked.sort_by_key(|(tid, _)| *tid);
+    assert_eq!(
+        masked,
+        vec![(0, 0.1), (1, ninf), (2, 0.3), (3, ninf), (4, ninf)]
+    );
+
+    let mut logits = Logits::try_from_iter([0.1f32, 0.2, 0.3, 0.4, 0.5])?;
+    logits.apply_mask_fn(|tid| tid % 2 == 0);
+    assert_eq!(
+        logits.iter().map(|l| l.logit).collect::<Vec<_>>(),
+        vec![0.1, ninf, 0.3, ninf, 0.5]
+    );
+    assert_eq!(
+        logits.sample_token(&mut (), &mut SampleGreedy::new())?,
+        Some(4)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_logits_validate() -> Result<()> {
+    let mut logits = Logits::try_from_iter([0.1f32, 0.2, 0.3])?;
+    logits.validate()?;
+    logits.validate_token_ids()?;
+
+    logits[1].logit = f32::NAN;
+    assert!(matches!(
+        logits.validate(),
+        Err(LogitsError::InvalidLogit(1))
+    ));
+    let err = logits
+        .sample_token(&mut (), &mut SampleRandDistrib::new())
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<LogitsError>(),
+        Some(LogitsError::InvalidLogit(1))
+    ));
+
+    let mut logits = Logits::try_from_iter([0.1f32, 0.2, 0.3])?;
+    logits[2].token_id = 0;
+    logits.validate()?;
+    assert!(matches!(
+        logits.validate_token_ids(),
+        Err(LogitsError::DuplicateTokenId(2))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_softmax_temperature() -> Result<()> {
+    const T: &[f32] = &[0.1, 2.5, -1.0, 0.7, 1.3];
+    for temp in [0.3, 0.8, 1.0, 2.0] {
+        let mut expected = Logits::try_from_iter(T.iter().copied())?;
+        SampleTemperature::new(temp).sample(&mut (), &mut expected)?;
+        expected.ensure_softmax()?;
+
+        let mut logits = Logits::try_from_iter(T.iter().copied())?;
+        logits.softmax_temperature(temp)?;
+        assert!(logits.get_sorted() && logits.get_softmax());
+        assert!(logits.approx_eq(&expected, 1e-6));
+        assert!(logits.probs_approx_eq(&expected, 1e-6));
+    }
+
+    let mut logits = Logits::try_from_iter(T.iter().copied())?;
+    logits.softmax_temperature(0.0)?;
+    assert_eq!(logits[0].token_id, 1);
+    assert_eq!(
+        logits.iter().map(|l| l.prob).collect::<Vec<_>>(),
+        [1.0, 0.0, 0.0, 0.0, 0.0]
+    );
+    assert!(logits.iter().skip(1).all(|l| l.logit == f32::NEG_INFINITY));
+    Ok(())
+}
+
+#[test]
+fn test_softmax_with_policy() -> Result<()> {
+    let make = || -> Result<Logits> {
+        let mut logits = Logits::try_from_iter([0.0f32, f32::NEG_INFINITY, 0.0, 0.0])?;
+        // Logits can't be constructed with NaN values.
+        logits[3].logit = f32::NAN;
+        Ok(logits)
+    };
+    let probs = |logits: &Logits| {
+        let mut probs = logits
+            .iter()
+            .map(|l| (l.token_id, l.prob))
+            .collect::<Vec<_>>();
+        probs.sort_by_key(|(tid, _)| *tid);
+        probs
+    };
+
+    let mut logits = make()?;
+    logits.softmax_with_policy(NanPolicy::Skip)?;
+    assert_eq!(probs(&logits), [(0, 0.5), (2, 0.5)]);
+
+    let mut logits = make()?;
+    assert!(matches!(
+        logits
+            .softmax_with_policy(NanPolicy::Error)
+            .unwrap_err()
+            .downcast_ref::<LogitsError>(),
+        Some(LogitsError::InvalidLogit(1))
+    ));
+    let mut logits = Logits::try_from_iter([0.0f32, 0.0])?;
+    logits.softmax_with_policy(NanPolicy::Error)?;
+    assert_eq!(probs(&logits), [(0, 0.5), (1, 0.5)]);
+
+    let mut logits = make()?;
+    logits.softmax_with_policy(NanPolicy::ZeroProb)?;
+    assert_eq!(probs(&logits), [(0, 0.5), (1, 0.0), (2, 0.5), (3, 0.0)]);
+    assert_eq!(
+        logits.iter().find(|l| l.token_id == 3).map(|l| l.logit),
+        Some(f32::NEG_INFINITY)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_logits_stats() -> Result<()> {
+    let logits = Logits::try_from_iter([0.5f32, 0.25, 0.25].map(f32::ln))?;
+    let stats = logits.stats();
+    let (l1, l2) = (0.5f32.ln(), 0.25f32.ln());
+    let mean = (l1 + 2.0 * l2) / 3.0;
+    let variance = ((l1 - mean).powi(2) + 2.0 * (l2 - mean).powi(2)) / 3.0;
+    let expected = [
+        (stats.max_logit, l1),
+        (stats.min_logit, l2),
+        (stats.mean, mean),
+        (stats.variance, variance),
+        (stats.std_dev, variance.sqrt()),
+        (stats.entropy, 1.5 * 2f32.ln()),
+        (stats.top_prob, 0.5),
+    ];
+    expected.into_iter().for_each(|(actual, expected)| {
+        assert!(
+            (actual - expected).abs() < 0.00001,
+            "{actual} != {expected}"
+        )
+    });
+    assert_eq!(Logits::default().stats(), LogitsStats::default());
+
+    let masked = Logits::try_from_iter([f32::NEG_INFINITY; 4])?.stats();
+    assert_eq!((masked.entropy, masked.top_prob), (4f32.ln(), 0.25));
+    let infinite = Logits::try_from_iter([f32::INFINITY, 0.0, f32::INFINITY])?.stats();
+    assert_eq!((infinite.entropy, infinite.top_prob), (2f32.ln(), 0.5));
+    Ok(())
+}
+
+#[test]
+fn test_logits_pairs() -> Result<()> {
+    let mut logits = Logits::try_from_iter([0.1f32, 0.4, 0.2, 0.3])?;
+    assert_eq!(
+        logits.to_logit_pairs(),
+        vec![(0, 0.1), (1, 0.4), (2, 0.2), (3, 0.3)]
+    );
+    let pairs = logits.to_prob_pairs()?;
+    assert_eq!(
+        pairs.iter().map(|(tid, _)| *tid).collect::<Vec<_>>(),
+        vec![1, 3, 2, 0]
+    );
+    let sum = pairs.iter().map(|(_, prob)| prob).sum::<f32>();
+    assert!((sum - 1.0).abs() < 0.00001, "{sum} != 1.0");
+    Ok(())
+}
+
+#[test]
+fn test_logits_try_from_contiguous() -> Result<()> {
+    let logits = Logits::try_from_contiguous(T1)?;
+    let expected = Logits::try_from_iter(T1.iter().copied())?;
+    assert_eq!(logits.to_logit_pairs(), expected.to_logit_pairs());
+    assert!(!logits.get_sorted() && !logits.get_softmax());
+    assert!(matches!(
+        Logits::try_from_contiguous(&[0.1, f32::NAN]),
+        Err(LogitsError::InvalidLogit(1))
+    ));
+    Ok(())
+}
+
+#[cfg(feature = "ndarray")]
+#[test]
+fn test_logits_try_from_ndarray() -> Result<()> {
+    use ndarray::{s, Array1};
+
+    let arr = Array1::from_vec(T1.to_vec());
+    let logits = Logits::try_from_ndarray(&arr.view())?;
+    let expected = Logits::try_from_iter(T1.iter().copied())?;
+    assert_eq!(logits.to_logit_pairs(), expected.to_logit_pairs());
+    assert!(Logits::try_from_ndarray(&arr.slice(s![..;-1])).is_err());
+    Ok(())
+}
+
+#[cfg(feature = "candle")]
+#[test]
+fn test_logits_try_from_candle() -> Result<()> {
+    use candle_core::{Device, Tensor};
+
+    let tensor = Tensor::new(T1, &Device::Cpu)?;
+    let logits = Logits::try_from_candle(&tensor)?;
+    let expected = Logits::try_from_iter(T1.iter().copied())?;
+    assert_eq!(logits.to_logit_pairs(), expected.to_logit_pairs());
+    assert!(Logits::try_from_candle(&tensor.reshape((2, 2))?).is_err());
+    assert!(Logits::try_from_candle(&tensor.to_dtype(candle_core::DType::F64)?).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_logits_with_capacity() -> Result<()> {
+    let mut logits = Logits::with_capacity(4);
+    assert!(logits.is_empty() && logits.capacity() >= 4);
+    T1.iter().zip(0..).for_each(|(&prob, token_id)| {
+        logits.push(Logit {
+            token_id,
+            logit: prob.ln(),
+            prob: 0.0,
+        })
+    });
+    validate(&mut SampleGreedy::new(), logits.ensure_softmax()?, TE1);
+
+    logits.iter_mut().for_each(|l| l.logit = -l.logit);
+    logits.clear_flags();
+    assert!(!logits.get_sorted() && !logits.get_softmax());
+    assert_eq!(logits.ensure_softmax()?[0].token_id, 0);
+    Ok(())
+}
+
 #[test]
 fn test_chain1() -> anyhow::Result<()> {
     let mut res = NilSamplerResources;
@@ -130,6 +526,360 @@ fn test_chain1() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_logits_sample_top_candidates() -> Result<()> {
+    let mut logits = Logits::try_from_iter([0.1f32, 0.4, 0.2, 0.3].map(f32::ln))?;
+    let candidates =
+        logits.sample_top_candidates(&mut NilSamplerResources, 3, &mut SampleTopK::new(3, 1))?;
+    let expected = [(1, 0.4 / 0.9), (3, 0.3 / 0.9), (2, 0.2 / 0.9)];
+    assert_eq!(candidates.len(), expected.len());
+    candidates
+        .iter()
+        .zip(expected.iter())
+        .for_each(|((tid, prob), (etid, eprob))| {
+            assert_eq!(tid, etid);
+            assert!((prob - eprob).abs() < 0.00001, "{prob} != {eprob}");
+        });
+    Ok(())
+}
+
+#[test]
+fn test_chain_sampled_probability() -> Result<()> {
+    let mut sc = SamplerChain::new() + SampleGreedy::new();
+    assert_eq!(sc.sampled_probability(), None);
+
+    let mut logits = Logits::try_from_iter(T1.iter().map(|p| p.ln()))?;
+    assert_eq!(sc.sample_token(&mut (), &mut logits)?, Some(3));
+    assert!((sc.sampled_probability().expect("missing probability") - 0.4).abs() < 0.00001);
+
+    let mut logits = Logits::try_from_iter(T1.iter().map(|p| p.ln()))?;
+    logits.ensure_softmax()?;
+    let mut sc = SamplerChain::new() + SampleTopK::new(2, 1) + SampleGreedy::new();
+    assert_eq!(sc.sample_token(&mut (), &mut logits)?, Some(3));
+    assert!((sc.sampled_probability().expect("missing probability") - 0.4 / 0.7).abs() < 0.00001);
+
+    let mut logits = Logits::default();
+    assert_eq!(sc.sample_token(&mut (), &mut logits)?, None);
+    assert_eq!(sc.sampled_probability(), None);
+    Ok(())
+}
+
+#[test]
+fn test_chain_non_destructive() -> Result<()> {
+    let ninf = f32::NEG_INFINITY;
+    let mut logits = Logits::try_from_iter([0.3f32, 0.1, 0.4, 0.2, 0.5])?;
+    let mut sc =
+        (SamplerChain::new() + SampleTopK::new(2, 1) + SampleGreedy::new()).non_destructive(true);
+    assert_eq!(sc.sample_token(&mut (), &mut logits)?, Some(4));
+    assert_eq!(logits.len(), 5);
+    assert!(logits
+        .iter()
+        .enumerate()
+        .all(|(idx, l)| l.token_id as usize == idx));
+    assert_eq!(
+        logits.iter().map(|l| l.logit).collect::<Vec<_>>(),
+        vec![ninf, ninf, 0.4, ninf, 0.5]
+    );
+    assert!(!logits.get_sorted() && !logits.get_softmax());
+
+    // The order is restored when a sampler fails too.
+    let mut logits = Logits::try_from_iter([0.3f32, 0.1, 0.4, 0.2, 0.5])?;
+    let mut sc = (SamplerChain::new()
+        + SampleTopK::new(2, 1)
+        + FnSampler::new(|_: &mut dyn HasSamplerResources, _: &mut Logits| {
+            anyhow::bail!("failed")
+        }))
+    .non_destructive(true);
+    assert!(sc.sample_token(&mut (), &mut logits).is_err());
+    assert_eq!(
+        logits.iter().map(|l| l.logit).collect::<Vec<_>>(),
+        vec![ninf, ninf, 0.4, ninf, 0.5]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_fn_sampler() -> Result<()> {
+    let mut logits = Logits::try_from_iter([0.1f32, 0.2, 0.3, 0.4])?;
+    let mut sc = SamplerChain::new()
+        + FnSampler::new(|_res: &mut dyn HasSamplerResources, logits: &mut Logits| {
+            logits
+                .iter_mut()
+                .filter(|l| l.token_id == 3)
+                .for_each(|l| l.logit = 0.0);
+            logits.clear_flags();
+            Ok(())
+        })
+        + SampleGreedy::new();
+    assert_eq!(sc.sample_token(&mut (), &mut logits)?, Some(2));
+    assert_eq!(
+        FnSampler::new(|_: &mut dyn HasSamplerResources, _: &mut Logits| Ok(())).name(),
+        "FnSampler"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_chain_sampled_logit() -> Result<()> {
+    let mut logits = Logits::try_from_iter([0.1f32, 0.4, 0.3, 0.2])?;
+    let mut sc = SamplerChain::new() + SampleGreedy::new();
+    assert_eq!(sc.sampled_logit(), None);
+    assert_eq!(sc.sample_token(&mut (), &mut logits)?, Some(1));
+    assert_eq!(
+        sc.sampled_logit(),
+        Some(Logit {
+            token_id: 1,
+            logit: 0.4,
+            prob: 0.0
+        })
+    );
+
+    // Modifying the logits afterward doesn't change the recorded entry.
+    logits.iter_mut().for_each(|l| l.logit = 0.0);
+    assert_eq!(sc.sampled_logit().map(|l| l.logit), Some(0.4));
+
+    let mut greedy = SampleGreedy::new();
+    logits.sample_token(&mut (), &mut greedy)?;
+    assert_eq!(greedy, greedy.clone());
+    assert_ne!(greedy, SampleGreedy::new());
+
+    let mut logits = Logits::try_from_iter([0.1f32, 0.4, 0.3, 0.2])?;
+    let mut sc = SamplerChain::new() + SampleTopK::new(1, 1) + SampleRandDistrib::new();
+    assert_eq!(sc.sample_token(&mut (), &mut logits)?, Some(1));
+    assert_eq!(
+        sc.sampled_logit(),
+        Some(Logit {
+            token_id: 1,
+            logit: 0.4,
+            prob: 1.0
+        })
+    );
+    Ok(())
+}
+
+#[test]
+fn test_chain_iter_metadata() {
+    let sc = SamplerChain::new()
+        + SampleRepetition::new(1.1, 64)
+        + SampleTopK::new(40, 1)
+        + SampleTemperature::new(0.8)
+        + FnSampler::new(|_: &mut dyn HasSamplerResources, _: &mut Logits| Ok(()))
+        + SampleGreedy::new();
+    assert_eq!(
+        sc.iter_metadata().map(|md| md.name).collect::<Vec<_>>(),
+        ["repetition", "top-k", "temperature", "unknown", "greedy"]
+    );
+    assert_eq!(
+        sc.iter_metadata().nth(1),
+        Some(SampleTopK::default().metadata())
+    );
+}
+
+#[test]
+fn test_chain_collect() -> Result<()> {
+    use std::sync::{Arc, Mutex};
+
+    let collect = Arc::new(Mutex::new(SampleCollect::new()));
+    let shared: Arc<Mutex<dyn Sampler>> = collect.clone();
+    let mut sc = SamplerChain::new() + SampleTopK::new(2, 1) + shared;
+    let mut logits = Logits::try_from_iter([0.1f32, 0.4, 0.3, 0.2])?;
+
+    assert_eq!(collect.lock().unwrap().collected(), None);
+    assert_eq!(sc.sample_token(&mut (), &mut logits)?, None);
+    let collected = collect.lock().unwrap().collected().unwrap();
+    assert_eq!(
+        collected.iter().map(|(tid, _)| *tid).collect::<Vec<_>>(),
+        [1, 2]
+    );
+    let expected = 1.0 / (1.0 + (-0.1f32).exp());
+    assert!((collected[0].1 - expected).abs() < 1e-6);
+    assert_eq!(logits.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_chain_sample_n() -> Result<()> {
+    use rand::SeedableRng;
+
+    let mut res =
+        SimpleSamplerResources::new(Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))), None);
+    let logits = Logits::try_from_iter([0.0f32; 8])?;
+    let mut sc = SamplerChain::new() + SampleRandDistrib::new();
+    let samples = sc.sample_n(&mut res, &logits, 32)?;
+    assert_eq!(samples.len(), 32);
+    assert!(samples.iter().all(|tid| tid.is_some_and(|tid| tid < 8)));
+    let distinct = samples.iter().collect::<std::collections::HashSet<_>>();
+    assert!(distinct.len() > 4, "{samples:?}");
+    assert_eq!(sc.sampled_token_id(), samples[31]);
+    assert_eq!(logits.len(), 8);
+
+    sc.reset();
+    assert_eq!(sc.sampled_token_id(), None);
+    Ok(())
+}
+
+#[test]
+fn test_chain_sample_from_probs() -> Result<()> {
+    use rand::SeedableRng;
+
+    let mut res =
+        SimpleSamplerResources::new(Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))), None);
+    let mut sc = SamplerChain::new() + SampleTopP::new(0.9, 1) + SampleRandDistrib::new();
+    for _ in 0..10 {
+        assert_eq!(
+            sc.sample_from_probs(&mut res, &[0.01, 0.02, 0.95, 0.02])?,
+            Some(2)
+        );
+    }
+    assert!(sc.sample_from_probs(&mut res, &[0.0, 0.0]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_chain_sample_timed() -> Result<()> {
+    let mut logits = Logits::try_from_iter([0.1f32, 0.4, 0.3, 0.2])?;
+    let delay = std::time::Duration::from_millis(5);
+    let mut sc = SamplerChain::new()
+        + SampleTopK::new(2, 1)
+        + SampleTemperature::new(0.8)
+        + FnSampler::new(move |_: &mut dyn HasSamplerResources, _: &mut Logits| {
+            std::thread::sleep(delay);
+            Ok(())
+        })
+        + SampleGreedy::new();
+    let (tid, timings) = sc.sample_timed(&mut (), &mut logits)?;
+    assert_eq!(tid, Some(1));
+    assert_eq!(
+        timings.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+        ["top-k", "temperature", "unknown", "greedy"]
+    );
+    assert!(timings[2].1 >= delay);
+    Ok(())
+}
+
+#[test]
+fn test_chain_keeps_first_token() -> Result<()> {
+    use rand::SeedableRng;
+
+    let mut res =
+        SimpleSamplerResources::new(Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))), None);
+    let mut sc = SamplerChain::new() + SampleGreedy::new() + SampleTemperature::new(0.8);
+    let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+    assert_eq!(sc.sample_token(&mut res, &mut logits)?, Some(3));
+
+    let mut sc = SamplerChain::new()
+        + SampleTopK::new(3, 1)
+        + SampleGreedy::new()
+        + SampleTemperature::new(5.0)
+        + SampleRandDistrib::new();
+    for _ in 0..10 {
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        assert_eq!(sc.sample_token(&mut res, &mut logits)?, Some(3));
+    }
+
+    let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+    let err = sc
+        .set_strict(true)
+        .sample_token(&mut res, &mut logits)
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<SamplerError>(),
+        Some(SamplerError::ConflictingSelection(3))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_chain_collecting_removed() -> Result<()> {
+    let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+    let mut sc = SamplerChain::new()
+        + SampleTemperature::new(0.8)
+        + SampleTopK::new(2, 1)
+        + SampleGreedy::new();
+
+    assert_eq!(
+        sc.sample_collecting_removed(&mut (), &mut logits)?,
+        [(1, vec![0, 1])]
+    );
+    assert_eq!(sc.sampled_token_id(), Some(3));
+    Ok(())
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn test_chain_tracing() -> Result<()> {
+    use std::sync::{Arc, Mutex};
+
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Metadata, Subscriber,
+    };
+
+    struct SamplerNames(Arc<Mutex<Vec<String>>>);
+
+    impl Visit for &SamplerNames {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            if field.name() == "sampler" {
+                self.0.lock().unwrap().push(value.to_string());
+            }
+        }
+
+        fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+    }
+
+    impl Subscriber for SamplerNames {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _span: &span::Attributes<'_>) -> span::Id {
+            span::Id::from_u64(1)
+        }
+
+        fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            event.record(&mut &*self);
+        }
+
+        fn enter(&self, _span: &span::Id) {}
+
+        fn exit(&self, _span: &span::Id) {}
+    }
+
+    let names = Arc::new(Mutex::new(vec![]));
+    let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+    let mut sc = SamplerChain::new() + SampleTopK::new(2, 1) + SampleGreedy::new();
+    tracing::subscriber::with_default(SamplerNames(names.clone()), || {
+        sc.sample_token(&mut NilSamplerResources, &mut logits)
+    })?;
+    assert_eq!(*names.lock().unwrap(), vec!["SampleTopK", "SampleGreedy"]);
+    Ok(())
+}
+
+#[test]
+fn test_generate() -> Result<()> {
+    // A "model" that always favors the token after the last one.
+    let mut next_logits = |tokens: &[u32]| {
+        let next = tokens.last().map_or(0, |tid| tid + 1) as usize;
+        Logits::try_from_iter((0..8).map(|tid| if tid == next { 1.0 } else { 0.0 }))
+            .map_err(anyhow::Error::from)
+    };
+    let mut sc = SamplerChain::new() + SampleGreedy::new();
+
+    let mut res = SimpleSamplerResources::new(None, Some(vec![1]));
+    let generated = sc.generate(&mut res, &mut next_logits, 10, |tid| tid == 5)?;
+    assert_eq!(generated, vec![2, 3, 4, 5]);
+    res.with_last_tokens(&mut |tokens| assert_eq!(tokens, &[1, 2, 3, 4, 5]))?;
+
+    let mut res = SimpleSamplerResources::new(None, Some(vec![]));
+    let generated = sc.generate(&mut res, &mut next_logits, 3, |tid| tid == 5)?;
+    assert_eq!(generated, vec![0, 1, 2]);
+    Ok(())
+}
+
 #[test]
 fn test_chain2() -> Result<()> {
     use rand::SeedableRng;
@@ -173,30 +923,364 @@ fn test_resources() -> Result<()> {
     Ok(())
 }
 
-mod sampler {
-    use super::*;
+#[test]
+fn test_seedable_resources() -> Result<()> {
+    let mut res = SeedableSamplerResources::new(1, None);
+    let sample = |res: &mut SeedableSamplerResources| -> Result<Vec<Option<u32>>> {
+        (0..8)
+            .map(|_| {
+                Logits::try_from_iter(T1.iter().copied())?
+                    .sample_token(res, &mut SampleRandDistrib::new())
+            })
+            .collect()
+    };
+
+    res.reseed(42);
+    assert_eq!(res.current_seed(), 42);
+    let first = sample(&mut res)?;
+    res.reseed(42);
+    assert_eq!(sample(&mut res)?, first);
+    res.reseed(43);
+    assert_eq!(res.current_seed(), 43);
+    assert_ne!(sample(&mut res)?, first);
+    Ok(())
+}
+
+#[test]
+fn test_dry_run() -> Result<()> {
+    let logits = Logits::try_from_iter(T1.iter().copied())?;
+    let mut sc = SamplerChain::new() + SampleTopK::new(2, 1) + SampleGreedy::new();
+    let (tid, survivors) = sc.dry_run(&mut NilSamplerResources, &logits)?;
+    assert_eq!(tid, Some(3));
+    assert_eq!(
+        survivors.iter().map(|l| l.token_id).collect::<Vec<_>>(),
+        [3, 2]
+    );
+    assert!(logits.approx_eq(&Logits::try_from_iter(T1.iter().copied())?, 0.0));
+    assert!(!logits.get_sorted());
+    Ok(())
+}
+
+#[test]
+fn test_composite_resources() -> Result<()> {
+    let sample = |res: &mut dyn HasSamplerResources| -> Result<Vec<Option<u32>>> {
+        let mut sc =
+            SamplerChain::new() + SampleRepetition::new(4.0, 64) + SampleRandDistrib::new();
+        (0..8)
+            .map(|_| sc.sample_token(res, &mut Logits::try_from_iter(T1.iter().copied())?))
+            .collect()
+    };
+    let expected = sample(&mut SeedableSamplerResources::new(5, Some(vec![3])))?;
+
+    let mut rng = SeedableSamplerResources::new(5, None);
+    let mut tokens = SimpleSamplerResources::new(None, Some(vec![3]));
+    let mut res = CompositeSamplerResources::new(&mut rng, &mut tokens);
+    assert_eq!(sample(&mut res)?, expected);
+    res.with_last_tokens_mut(&mut |tokens| tokens.push(2))?;
+    assert!(res.with_token_bytes(0, &mut |_| ()).is_err());
+
+    let mut last_tokens = vec![];
+    tokens.with_last_tokens(&mut |tokens| last_tokens = tokens.to_vec())?;
+    assert_eq!(last_tokens, [3, 2]);
+    assert!(
+        CompositeSamplerResources::new(&mut tokens, &mut NilSamplerResources)
+            .with_rng_mut(&mut |_| ())
+            .is_err()
+    );
+    Ok(())
+}
+
+#[test]
+fn test_chain_snapshot() -> Result<()> {
+    let logits = || Logits::try_from_iter((1..=8).map(|i| -(i as f32).ln()));
+    let mut res = SeedableSamplerResources::new(1, None);
+    let mut sc = SamplerChain::new()
+        + SampleTopK::new(6, 1)
+        + ToggleSampler::new(SampleMirostat2::new(1.5, 0.1));
+    for _ in 0..3 {
+        sc.sample_token(&mut res, &mut logits()?)?;
+    }
+    let snapshot = sc.snapshot();
+    assert_eq!(snapshot.0.len(), 2);
+    assert!(snapshot.0[0].is_none());
+    assert!(matches!(snapshot.0[1], Some(SamplerState::Mirostat(_))));
+
+    let mut run = |sc: &mut SamplerChain| -> Result<Vec<Option<u32>>> {
+        res.reseed(7);
+        (0..5)
+            .map(|_| sc.sample_token(&mut res, &mut logits()?))
+            .collect()
+    };
+    let continued = run(&mut sc)?;
+    assert_ne!(sc.snapshot(), snapshot);
+    sc.restore(&snapshot)?;
+    assert_eq!(sc.snapshot(), snapshot);
+    assert_eq!(run(&mut sc)?, continued);
+
+    let mut nested = SamplerChain::new() + SampleGreedy::new();
+    nested.sample_token(&mut res, &mut logits()?)?;
+    let nested_snapshot = nested.save_state();
+    assert_eq!(
+        nested_snapshot,
+        Some(SamplerState::Chain(ChainSnapshot(vec![Some(
+            SamplerState::Selected(logits()?.first().copied())
+        )])))
+    );
+    assert!(sc.restore(&ChainSnapshot(vec![None])).is_err());
+    assert!(sc.load_state(&SamplerState::Selected(None)).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_shared_token_resources() -> Result<()> {
+    use rand::SeedableRng;
+    let mut res = SharedTokenResources::from_parts(
+        Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+        vec![],
+    );
+    let handle = res.last_tokens_handle();
+    std::thread::spawn(move || handle.write().unwrap().push(1))
+        .join()
+        .unwrap();
+
+    let mut logits = Logits::try_from_iter([1.0f32, 1.0, 1.0])?;
+    SampleRepetition::new(2.0, 64).sample(&mut res, &mut logits)?;
+    assert_eq!(logits[1].logit, 0.5);
+
+    res.with_last_tokens_mut(&mut |tokens| tokens.push(2))?;
+    assert_eq!(*res.clone().last_tokens_handle().read().unwrap(), [1, 2]);
+    assert!(res.rng_handle().is_some());
+    res.with_rng_mut(&mut |rng| {
+        rng.next_u32();
+    })?;
+    Ok(())
+}
+
+#[test]
+fn test_generated_tokens() -> Result<()> {
+    let mut res = SimpleSamplerResources::new(None, Some(vec![1u32, 2, 3])).prompt_len(2);
+    let mut generated = vec![];
+    res.with_generated_tokens(&mut |tokens| generated = tokens.to_vec())?;
+    assert_eq!(generated, [3]);
+
+    res.with_last_tokens_mut(&mut |tokens| tokens.push(4))?;
+    res.with_generated_tokens(&mut |tokens| generated = tokens.to_vec())?;
+    assert_eq!(generated, [3, 4]);
+
+    res.set_prompt_len(10);
+    res.with_generated_tokens(&mut |tokens| generated = tokens.to_vec())?;
+    assert!(generated.is_empty());
+    assert!(NilSamplerResources
+        .with_generated_tokens(&mut |_| ())
+        .is_err());
+    Ok(())
+}
+
+mod sampler {
+    use super::*;
+
+    #[test]
+    fn test_greedy() -> Result<()> {
+        do_test_greedy(T1.iter().copied(), Some(3))?;
+        do_test_greedy(T1.iter().rev().copied(), Some(0))
+    }
+
+    #[test]
+    fn test_greedy_runners_up() -> Result<()> {
+        let mut logits = Logits::try_from_iter([0.3f32, 0.9, 0.1, 0.7, 0.5])?;
+        let mut greedy = SampleGreedy::new().debug_k(Some(3));
+        assert_eq!(logits.sample_token(&mut (), &mut greedy)?, Some(1));
+        assert_eq!(greedy.runners_up(), [1, 3, 4]);
+
+        let mut greedy = SampleGreedy::new();
+        logits.sample_token(&mut (), &mut greedy)?;
+        assert!(greedy.runners_up().is_empty());
+
+        let mut logits = Logits::try_from_iter((0..20).map(|i| i as f32))?;
+        let mut greedy = SampleGreedy::new().debug_k(Some(100));
+        logits.sample_token(&mut (), &mut greedy)?;
+        assert_eq!(greedy.runners_up().len(), SampleGreedy::MAX_DEBUG_K);
+        assert_eq!(greedy.runners_up()[..3], [19, 18, 17]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_strict_selectors() -> Result<()> {
+        use rand::SeedableRng;
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+            None,
+        );
+
+        let mut logits = Logits::default();
+        assert_eq!(
+            logits.sample_token(&mut res, &mut SampleGreedy::new())?,
+            None
+        );
+        let err = logits
+            .sample_token(&mut res, &mut SampleGreedy::new().strict(true))
+            .expect_err("strict greedy should fail");
+        assert!(matches!(
+            err.downcast_ref::<SamplerError>(),
+            Some(SamplerError::EmptyLogits)
+        ));
+
+        assert_eq!(
+            logits.sample_token(&mut res, &mut SampleRandDistrib::new())?,
+            None
+        );
+        assert!(logits
+            .sample_token(&mut res, &mut SampleRandDistrib::new().strict(true))
+            .is_err());
+        assert!(logits
+            .sample_token(
+                &mut res,
+                &mut SampleMirostat1::new(3, 5.0, 0.1).strict(true)
+            )
+            .is_err());
+        assert!(logits
+            .sample_token(&mut res, &mut SampleMirostat2::new(5.0, 0.1).strict(true))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_k() {
+        let mut res = NilSamplerResources;
+        test_sampler(
+            &mut res,
+            &mut SampleTopK::new(1, 0),
+            T1,
+            &TE1[0..1],
+            validate,
+        );
+        test_sampler(
+            &mut res,
+            &mut SampleTopK::new(3, 0),
+            T1,
+            &TE1[0..3],
+            validate,
+        );
+    }
+
+    #[test]
+    fn test_top_k_renormalize() -> Result<()> {
+        let mut res = NilSamplerResources;
+        let mut logits = Logits::try_from_iter([0.1f32, 0.2, 0.3, 0.4])?;
+        logits.ensure_softmax()?;
+        logits.sample(&mut res, &mut SampleTopK::new(2, 1))?;
+        assert!(!logits.get_softmax());
+
+        let mut logits = Logits::try_from_iter([0.1f32, 0.2, 0.3, 0.4])?;
+        logits.ensure_softmax()?;
+        logits.sample(&mut res, &mut SampleTopK::new(2, 1).renormalize(true))?;
+        assert!(logits.get_softmax());
+        assert_eq!(logits.len(), 2);
+        assert!((logits.iter().map(|l| l.prob).sum::<f32>() - 1.0).abs() < 0.00001);
+        Ok(())
+    }
+
+    #[test]
+    fn test_eos_bias() -> Result<()> {
+        const T: &[f32] = &[0.1, 2.0, 1.0, 0.5];
+        let mut res = SimpleSamplerResources::new(None, None);
+        let mut sampler = SampleEosBias::new(0, 3, 0.0, 0.0);
+
+        for step in 0..5 {
+            let mut logits = Logits::try_from_iter(T.iter().copied())?;
+            let tid = logits.sample_token(
+                &mut res,
+                &mut (SamplerChain::new() + sampler + SampleGreedy::new()),
+            )?;
+            assert_eq!(tid, Some(if step < 3 { 1 } else { 0 }));
+            res.increment_step();
+        }
+
+        sampler = SampleEosBias::new(0, 0, 0.05, 5.0);
+        test_sampler_raw(
+            &mut res,
+            &mut sampler,
+            T,
+            &[5.1, 2.0, 1.0, 0.5],
+            validate_eq,
+        );
+        test_sampler_raw(&mut res, &mut sampler.threshold(0.5), T, T, validate_eq);
+        test_sampler_raw(&mut res, &mut sampler.eos_id(9), T, T, validate_eq);
+
+        // The step count is 5, so EOS has to be forced but token 9 has no entry.
+        let mut logits = Logits::try_from_iter(T.iter().copied())?;
+        let err = logits
+            .sample(&mut res, &mut SampleEosBias::new(9, 3, 0.0, 0.0))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<SamplerError>(),
+            Some(SamplerError::TokenIdOutOfRange(9))
+        ));
+        Ok(())
+    }
 
     #[test]
-    fn test_greedy() -> Result<()> {
-        do_test_greedy(T1.iter().copied(), Some(3))?;
-        do_test_greedy(T1.iter().rev().copied(), Some(0))
+    fn test_top_k_include_ties() -> Result<()> {
+        const T: &[f32] = &[0.4, 0.2, 0.2, 0.2, 0.1];
+        let mut res = NilSamplerResources;
+        let kept = |res: &mut NilSamplerResources, sampler: &mut SampleTopK| -> Result<usize> {
+            let mut logits = Logits::try_from_iter(T.iter().copied())?;
+            Ok(logits.sample(res, sampler)?.len())
+        };
+        assert_eq!(kept(&mut res, &mut SampleTopK::new(2, 1))?, 2);
+        assert_eq!(
+            kept(&mut res, &mut SampleTopK::new(2, 1).include_ties(true))?,
+            4
+        );
+        assert_eq!(
+            kept(&mut res, &mut SampleTopK::new(1, 1).include_ties(true))?,
+            1
+        );
+        assert_eq!(
+            kept(&mut res, &mut SampleTopK::new(0, 2).include_ties(true))?,
+            4
+        );
+        assert_eq!(
+            kept(&mut res, &mut SampleTopK::new(10, 1).include_ties(true))?,
+            5
+        );
+
+        let mut sampler = SampleTopK::default();
+        ConfigurableSampler::<usize, f32>::configure(&mut sampler, "k=3:include_ties=true")?;
+        assert_eq!(kept(&mut res, &mut sampler)?, 4);
+        Ok(())
     }
 
     #[test]
-    fn test_top_k() {
+    fn test_top_k_max_p() {
+        const T: &[f32] = &[0.7, 0.2, 0.05, 0.03, 0.02];
         let mut res = NilSamplerResources;
         test_sampler(
             &mut res,
-            &mut SampleTopK::new(1, 0),
-            T1,
-            &TE1[0..1],
+            &mut SampleTopK::new(4, 1).max_p(Some(0.5)),
+            T,
+            &T[0..1],
             validate,
         );
         test_sampler(
             &mut res,
-            &mut SampleTopK::new(3, 0),
-            T1,
-            &TE1[0..3],
+            &mut SampleTopK::new(4, 1).max_p(Some(0.92)),
+            T,
+            &T[0..3],
+            validate,
+        );
+        test_sampler(
+            &mut res,
+            &mut SampleTopK::new(2, 1).max_p(Some(0.99)),
+            T,
+            &T[0..2],
+            validate,
+        );
+        test_sampler(
+            &mut res,
+            &mut SampleTopK::new(4, 2).max_p(Some(0.5)),
+            T,
+            &T[0..2],
             validate,
         );
     }
@@ -222,32 +1306,105 @@ mod sampler {
     }
 
     #[test]
-    fn test_min_p() {
+    fn test_top_p_inclusive() -> Result<()> {
+        let mut res = NilSamplerResources;
+        let kept = |samp: &mut SampleTopP, res: &mut NilSamplerResources| -> Result<usize> {
+            let mut logits = Logits::from_probs(&[0.4, 0.3, 0.2, 0.1])?;
+            Ok(samp.sample(res, &mut logits)?.len())
+        };
+        // The cumulative probability reaches 0.8 at the third token.
+        assert_eq!(kept(&mut SampleTopP::new(0.8, 1), &mut res)?, 3);
+        assert_eq!(
+            kept(&mut SampleTopP::new(0.8, 1).inclusive(false), &mut res)?,
+            2
+        );
+        // min_keep still applies when the first token already crosses p.
+        assert_eq!(
+            kept(&mut SampleTopP::new(0.1, 1).inclusive(false), &mut res)?,
+            1
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_p_min_p_mass() -> Result<()> {
+        let kept = |samp: &mut SampleTopP| -> Result<usize> {
+            let mut logits = Logits::from_probs(&[0.4, 0.3, 0.2, 0.1])?;
+            Ok(samp.sample(&mut NilSamplerResources, &mut logits)?.len())
+        };
+        assert_eq!(kept(&mut SampleTopP::new(0.3, 1))?, 1);
+        // A cumulative probability of 0.85 isn't reached until the third token.
+        assert_eq!(
+            kept(&mut SampleTopP::new(0.3, 1).min_p_mass(Some(0.85)))?,
+            3
+        );
+        // A lower mass doesn't reduce what p keeps.
+        assert_eq!(kept(&mut SampleTopP::new(0.8, 1).min_p_mass(Some(0.1)))?, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_percentile() -> Result<()> {
+        let survivors = |samp: &mut SamplePercentile| -> Result<Vec<u32>> {
+            let mut logits = Logits::try_from_iter((0..1000).map(|i| i as f32))?;
+            samp.sample(&mut NilSamplerResources, &mut logits)?;
+            Ok(logits.iter().map(|l| l.token_id).collect())
+        };
+        assert_eq!(
+            survivors(&mut SamplePercentile::new(1.0, 1))?,
+            (990..1000).rev().collect::<Vec<_>>()
+        );
+        // ceil(1000 * 0.25 / 100) = 3
+        assert_eq!(survivors(&mut SamplePercentile::new(0.25, 1))?.len(), 3);
+        assert_eq!(survivors(&mut SamplePercentile::new(0.0, 5))?.len(), 5);
+        assert_eq!(survivors(&mut SamplePercentile::new(100.0, 1))?.len(), 1000);
+
+        let mut samp = SamplePercentile::default();
+        ConfigurableSampler::<usize, f32>::configure(&mut samp, "pct=2.5:min_keep=1")?;
+        assert_eq!(survivors(&mut samp)?.len(), 25);
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_p() -> Result<()> {
         const TINP: &[f32] = &[2.0, 1.0, 0.5, 0.25, 0.1];
         const TEXP: &[f32] = &[0.5194805, 0.25974026, 0.12987013, 0.064935066, 0.025974026];
 
         let mut res = NilSamplerResources;
         test_sampler(
             &mut res,
-            &mut SampleMinP::new(2.0, 1),
+            &mut SampleMinP::new(2.0, 1).renormalize(false),
             TINP,
             &TEXP[0..1],
             validate,
         );
         test_sampler(
             &mut res,
-            &mut SampleMinP::new(0.2, 1),
+            &mut SampleMinP::new(0.2, 1).renormalize(false),
             TINP,
             &TEXP[0..3],
             validate,
         );
         test_sampler(
             &mut res,
-            &mut SampleMinP::new(0.0001, 1),
+            &mut SampleMinP::new(0.0001, 1).renormalize(false),
             TINP,
             TEXP,
             validate,
         );
+        test_sampler(
+            &mut res,
+            &mut SampleMinP::new(0.2, 1),
+            TINP,
+            &[0.5714286, 0.2857143, 0.1428571],
+            validate,
+        );
+
+        let mut logits = Logits::try_from_iter(TINP.iter().map(|p| p.ln()))?;
+        logits.sample(&mut res, &mut SampleMinP::new(0.2, 1))?;
+        assert!(logits.get_softmax());
+        assert!((logits.iter().map(|l| l.prob).sum::<f32>() - 1.0).abs() < 0.00001);
+        Ok(())
     }
 
     #[test]
@@ -279,6 +1436,184 @@ mod sampler {
         );
     }
 
+    #[test]
+    fn test_min_p_slope() -> Result<()> {
+        const TINP: &[f32] = &[2.0, 1.0, 0.5, 0.25, 0.1];
+        let mut sampler = SampleMinP::new(0.6, 1).slope(-0.2).cap(Some(0.1));
+        assert_eq!(sampler.p_at(0), 0.6);
+        assert!((sampler.p_at(1) - 0.4).abs() < 1e-6);
+        assert_eq!(sampler.p_at(10), 0.1);
+
+        let mut res = SimpleSamplerResources::new(None, None);
+        let mut kept = vec![];
+        for _ in 0..3 {
+            let mut logits = Logits::try_from_iter(TINP.iter().map(|p| p.ln()))?;
+            sampler.sample(&mut res, &mut logits)?;
+            kept.push(logits.len());
+            res.increment_step();
+        }
+        assert_eq!(kept, [1, 2, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_p_top_a_log_space() -> Result<()> {
+        const TINP: &[f32] = &[2.0, 1.0, 0.5, 0.25, 0.1, 0.05, 0.01];
+        let mut res = NilSamplerResources;
+        let survivors = |samp: &mut dyn Sampler| -> Result<Vec<u32>> {
+            let mut logits = Logits::try_from_iter(TINP.iter().map(|p| p.ln()))?;
+            samp.sample(&mut NilSamplerResources, &mut logits)?;
+            Ok(logits.iter().map(|l| l.token_id).collect())
+        };
+
+        for p in [0.0001, 0.03, 0.2, 0.45, 2.0] {
+            let linear = survivors(&mut SampleMinP::new(p, 1))?;
+            assert_eq!(
+                linear,
+                survivors(&mut SampleMinP::new(p, 1).log_space(true))?
+            );
+        }
+        for (a1, a2) in [
+            (0.0001, 2.0),
+            (0.1, 1.0),
+            (0.45, 2.0),
+            (1.0, 0.5),
+            (8.0, 2.0),
+        ] {
+            let linear = survivors(&mut SampleTopA::new(a1, a2, 1))?;
+            assert_eq!(
+                linear,
+                survivors(&mut SampleTopA::new(a1, a2, 1).log_space(true))?
+            );
+        }
+
+        let mut logits = Logits::try_from_iter(TINP.iter().map(|p| p.ln()))?;
+        logits.sample(&mut res, &mut SampleMinP::new(0.2, 1).log_space(true))?;
+        assert_eq!(logits.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_clamp() -> Result<()> {
+        const T: &[f32] = &[-50.0, -2.0, 0.5, 3.0, 80.0, f32::INFINITY];
+        let mut res = NilSamplerResources;
+
+        test_sampler_raw(
+            &mut res,
+            &mut SampleClamp::new(-10.0, 10.0),
+            T,
+            &[-10.0, -2.0, 0.5, 3.0, 10.0, 10.0],
+            validate_eq,
+        );
+        test_sampler_raw(&mut res, &mut SampleClamp::default(), T, T, validate_eq);
+
+        let mut sampler = SampleClamp::default();
+        ConfigurableSampler::<usize, f32>::configure(&mut sampler, "max=1")?;
+        let mut logits = Logits::try_from_iter(T.iter().copied())?;
+        sampler.sample(&mut res, &mut logits)?;
+        assert_eq!(
+            logits.iter().map(|l| l.logit).collect::<Vec<_>>(),
+            [-50.0, -2.0, 0.5, 1.0, 1.0, 1.0]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_scale() -> Result<()> {
+        const T: &[f32] = &[4.0, 2.0, 1.0, -1.0];
+        let mut res = NilSamplerResources;
+        test_sampler_raw(
+            &mut res,
+            &mut SampleScale::new(2.0),
+            T,
+            &[8.0, 4.0, 2.0, -2.0],
+            validate_eq,
+        );
+        test_sampler_raw(&mut res, &mut SampleScale::default(), T, T, validate_eq);
+
+        let mut sampler = SampleScale::default();
+        ConfigurableSampler::<usize, f32>::configure(&mut sampler, "0.5")?;
+        let mut logits = Logits::try_from_iter(T.iter().copied())?;
+        logits.ensure_softmax()?;
+        sampler.sample(&mut res, &mut logits)?;
+        assert!(!logits.get_softmax());
+        assert!(logits.get_sorted());
+        assert_eq!(
+            logits.iter().map(|l| l.logit).collect::<Vec<_>>(),
+            [2.0, 1.0, 0.5, -0.5]
+        );
+
+        SampleScale::new(-1.0).sample(&mut res, &mut logits)?;
+        assert!(!logits.get_sorted());
+
+        const TM: &[f32] = &[2.0, f32::NEG_INFINITY, -1.0];
+        for (scale, expected) in [
+            (0.0, [0.0, f32::NEG_INFINITY, 0.0]),
+            (-2.0, [-4.0, f32::NEG_INFINITY, 2.0]),
+        ] {
+            let mut logits = Logits::try_from_iter(TM.iter().copied())?;
+            SampleScale::new(scale).sample(&mut res, &mut logits)?;
+            assert_eq!(logits.iter().map(|l| l.logit).collect::<Vec<_>>(), expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncate() -> Result<()> {
+        const T: &[f32] = &[0.5, 0.3, 0.19, 0.00999, 0.00001];
+        let mut res = NilSamplerResources;
+
+        test_sampler(
+            &mut res,
+            &mut SampleTruncate::new(0.01, 1),
+            T,
+            &T[0..3],
+            validate,
+        );
+        test_sampler(
+            &mut res,
+            &mut SampleTruncate::new(0.6, 2),
+            T,
+            &T[0..2],
+            validate,
+        );
+        test_sampler(
+            &mut res,
+            &mut SampleTruncate::new(0.01, 1).renormalize(true),
+            T,
+            &[0.5 / 0.99, 0.3 / 0.99, 0.19 / 0.99],
+            validate,
+        );
+
+        let mut logits = Logits::try_from_iter(T.iter().map(|p| p.ln()))?;
+        logits.sample(&mut res, &mut SampleTruncate::default().renormalize(true))?;
+        assert_eq!(logits.len(), 4);
+        assert!(logits.get_softmax());
+        assert!((logits.iter().map(|l| l.prob).sum::<f32>() - 1.0).abs() < 0.00001);
+        Ok(())
+    }
+
+    #[test]
+    fn test_penalty_warmup() -> Result<()> {
+        const T: &[f32] = &[1.0, 1.0, 1.0];
+        let run = |sampler: &mut dyn Sampler, tokens: &[TID]| -> Result<Vec<f32>> {
+            let mut logits = Logits::try_from_iter(T.iter().copied())?;
+            let mut res = SimpleSamplerResources::new(None, Some(tokens.to_vec()));
+            sampler.sample(&mut res, &mut logits)?;
+            Ok(logits.iter().map(|l| l.logit).collect())
+        };
+
+        let mut rep = SampleRepetition::new(2.0, 64).warmup(3);
+        assert_eq!(run(&mut rep, &[1, 1])?, T);
+        assert_eq!(run(&mut rep, &[1, 1, 2])?, [1.0, 0.5, 0.5]);
+
+        let mut fp = SampleFreqPresence::new(0.0, 0.5, 64);
+        ConfigurableSampler::<usize, f32>::configure(&mut fp, "warmup=3")?;
+        assert_eq!(run(&mut fp, &[1, 1])?, T);
+        assert_eq!(run(&mut fp, &[1, 1, 2])?, [1.0, 0.5, 0.5]);
+        Ok(())
+    }
+
     #[test]
     fn test_repetition() -> Result<()> {
         const T: &[f32] = &[0.2, 0.2, 0.2, 0.2, 0.2];
@@ -317,6 +1652,44 @@ mod sampler {
         Ok(())
     }
 
+    #[test]
+    fn test_repetition_subword() -> Result<()> {
+        const VOCAB: &[&str] = &["hel", "lo", " wor", "orld", " world", "xyz", "llo"];
+        let mut res = SimpleSamplerResources::new(None, Some(vec![0, 1, 2]))
+            .token_decoder(|tid| VOCAB[tid as usize].as_bytes().to_vec());
+        let run = |res: &mut SimpleSamplerResources, subword| -> Result<Vec<f32>> {
+            let mut logits = Logits::try_from_iter([1.0f32; 7])?;
+            SampleRepetition::new(2.0, 64)
+                .subword(subword)
+                .sample(res, &mut logits)?;
+            Ok(logits.iter().map(|l| l.logit).collect())
+        };
+
+        assert_eq!(run(&mut res, None)?, [0.5, 0.5, 0.5, 1.0, 1.0, 1.0, 1.0]);
+        // "llo" appears in the recent text but doesn't continue from the end of it.
+        assert_eq!(run(&mut res, Some(2))?, [0.5, 0.5, 0.5, 0.5, 0.5, 1.0, 1.0]);
+
+        let mut res = SimpleSamplerResources::new(None, Some(vec![0]));
+        assert!(run(&mut res, Some(2)).is_err());
+
+        // Decoded bytes are cached until the sampler is reset.
+        let mut sampler = SampleRepetition::new(2.0, 64).subword(Some(2));
+        let mut res = SimpleSamplerResources::new(None, Some(vec![0]))
+            .token_decoder(|tid| VOCAB[tid as usize].as_bytes().to_vec());
+        sampler.sample(&mut res, &mut Logits::try_from_iter([1.0f32; 7])?)?;
+        let mut res = SimpleSamplerResources::new(None, Some(vec![0]))
+            .token_decoder(|tid| VOCAB[6 - tid as usize].as_bytes().to_vec());
+        let mut run = |sampler: &mut SampleRepetition| -> Result<Vec<f32>> {
+            let mut logits = Logits::try_from_iter([1.0f32; 7])?;
+            sampler.sample(&mut res, &mut logits)?;
+            Ok(logits.iter().map(|l| l.logit).collect())
+        };
+        assert_eq!(run(&mut sampler)?, [0.5, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0]);
+        sampler.reset();
+        assert_eq!(run(&mut sampler)?, [0.5, 1.0, 1.0, 1.0, 1.0, 0.5, 1.0]);
+        Ok(())
+    }
+
     #[test]
     fn test_freq_presence() -> Result<()> {
         const T: &[f32] = &[0.2, 0.2, 0.2, 0.2, 0.2];
@@ -354,6 +1727,209 @@ mod sampler {
         Ok(())
     }
 
+    #[test]
+    fn test_penalties() -> Result<()> {
+        const T: &[f32] = &[1.0, -1.0, 2.0, 0.5, -0.5];
+        let tokens = [0, 1, 1, 2, 0, 0];
+        let mut res = SimpleSamplerResources::new(None, Some(tokens.to_vec()));
+
+        let mut combined = Logits::try_from_iter(T.iter().copied())?;
+        SamplePenalties::new(1.3, 0.2, 0.4, 64).sample(&mut res, &mut combined)?;
+
+        let mut chained = Logits::try_from_iter(T.iter().copied())?;
+        let mut sc = SamplerChain::new()
+            + SampleRepetition::new(1.3, 64)
+            + SampleFreqPresence::new(0.2, 0.4, 64);
+        sc.sample(&mut res, &mut chained)?;
+
+        assert_eq!(combined.to_logit_pairs(), chained.to_logit_pairs());
+        let expected = [
+            1.0 / 1.3 - 0.6 - 0.4,
+            -1.3 - 0.4 - 0.4,
+            2.0 / 1.3 - 0.2 - 0.4,
+            0.5,
+            -0.5,
+        ];
+        test_sampler_raw(
+            &mut res,
+            &mut SamplePenalties::new(1.3, 0.2, 0.4, 64),
+            T,
+            &expected,
+            |_, logits, expected| {
+                logits.iter().zip(expected).for_each(|(l, e)| {
+                    assert!((l.logit - e).abs() < 0.00001, "{} != {e}", l.logit);
+                })
+            },
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_freq_presence_global() {
+        const T: &[f32] = &[0.0, 0.0, 0.0, 0.0, 0.0];
+        // Token 0 is outside of the last_n window.
+        let mut res = SimpleSamplerResources::new(None, Some(vec![0, 1, 1, 2]));
+
+        test_sampler_raw(
+            &mut res,
+            &mut SampleFreqPresence::new(1.0, 0.5, 3),
+            T,
+            &[0.0, -2.5, -1.5, 0.0, 0.0],
+            validate_eq,
+        );
+        test_sampler_raw(
+            &mut res,
+            &mut SampleFreqPresence::new(1.0, 0.5, 3).global_presence(true),
+            T,
+            &[-0.5, -2.5, -1.5, 0.0, 0.0],
+            validate_eq,
+        );
+        test_sampler_raw(
+            &mut res,
+            &mut SampleFreqPresence::new(1.0, 0.5, 0).global_presence(true),
+            T,
+            &[-0.5, -0.5, -0.5, 0.0, 0.0],
+            validate_eq,
+        );
+    }
+
+    #[test]
+    fn test_penalty_space() -> Result<()> {
+        const T: &[f32] = &[0.0, 0.0, 0.0, 0.0];
+        let tokens = [0, 0, 1];
+        let run = |samp: &mut dyn Sampler| -> Result<Vec<f32>> {
+            let mut logits = Logits::try_from_iter(T.iter().copied())?;
+            samp.sample(
+                &mut SimpleSamplerResources::new(None, Some(tokens.to_vec())),
+                &mut logits,
+            )?;
+            let mut pairs = logits.to_prob_pairs()?;
+            pairs.sort_by_key(|(tid, _)| *tid);
+            Ok(pairs.into_iter().map(|(_, prob)| prob).collect())
+        };
+        let close = |a: &[f32], b: &[f32]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-6);
+
+        // In logit space, a presence penalty of 0.1 barely changes the uniform distribution.
+        let logit = run(&mut SampleFreqPresence::new(0.0, 0.1, 64))?;
+        let (p, e) = (1.0 / (2.0 + 2.0 * (-0.1f32).exp()), (-0.1f32).exp());
+        assert!(close(&logit, &[p * e, p * e, p, p]), "{logit:?}");
+        // In probability space it removes 0.1 of the 0.25 probability before renormalizing.
+        let prob = run(&mut SampleFreqPresence::new(0.0, 0.1, 64).space(PenaltySpace::Prob))?;
+        assert!(close(&prob, &[0.1875, 0.1875, 0.3125, 0.3125]), "{prob:?}");
+        // Frequency penalties clamp at zero probability.
+        let prob = run(&mut SampleFreqPresence::new(0.2, 0.0, 64).space(PenaltySpace::Prob))?;
+        assert!(
+            close(&prob, &[0.0, 0.05 / 0.55, 0.25 / 0.55, 0.25 / 0.55]),
+            "{prob:?}"
+        );
+
+        // Repetition divides the probability rather than the logit, which does
+        // nothing for a logit of 0.
+        assert_eq!(run(&mut SampleRepetition::new(2.0, 64))?, [0.25; 4]);
+        let prob = run(&mut SampleRepetition::new(2.0, 64).space(PenaltySpace::Prob))?;
+        assert!(
+            close(&prob, &[1.0 / 6.0, 1.0 / 6.0, 1.0 / 3.0, 1.0 / 3.0]),
+            "{prob:?}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_freq_presence_decay() {
+        const T: &[f32] = &[0.0, 0.0, 0.0, 0.0, 0.0];
+        let mut res = SimpleSamplerResources::new(None, Some(vec![0, 1, 1, 2]));
+
+        test_sampler_raw(
+            &mut res,
+            &mut SampleFreqPresence::new(1.0, 0.0, 100),
+            T,
+            &[-1.0, -2.0, -1.0, 0.0, 0.0],
+            validate_eq,
+        );
+        test_sampler_raw(
+            &mut res,
+            &mut SampleFreqPresence::new(1.0, 0.0, 100).decay(0.5),
+            T,
+            &[-0.125, -0.75, -1.0, 0.0, 0.0],
+            validate_eq,
+        );
+    }
+
+    #[test]
+    fn test_freq_presence_token_counts() -> Result<()> {
+        use std::collections::HashMap;
+
+        #[derive(Debug)]
+        struct CountsOnly(HashMap<TID, usize>);
+
+        impl HasSamplerResources for CountsOnly {
+            fn with_token_counts(
+                &self,
+                last_n: usize,
+                fun: &mut dyn FnMut(&HashMap<TID, usize>),
+            ) -> Result<(), SamplerError> {
+                assert_eq!(last_n, 3);
+                fun(&self.0);
+                Ok(())
+            }
+        }
+
+        const T: &[f32] = &[0.0, 0.0, 0.0, 0.0, 0.0];
+        let tokens = vec![3, 0, 1, 1, 2];
+        let mut sampler = SampleFreqPresence::new(1.0, 0.5, 3);
+
+        let mut res = SimpleSamplerResources::new(None, Some(tokens.clone()));
+        let mut expected = Logits::try_from_iter(T.iter().copied())?;
+        sampler.sample(&mut res, &mut expected)?;
+
+        res.precompute_token_counts(3);
+        let mut logits = Logits::try_from_iter(T.iter().copied())?;
+        sampler.sample(&mut res, &mut logits)?;
+        assert!(logits.approx_eq(&expected, 1e-6));
+
+        // The counts are used without needing the last tokens.
+        let mut res = CountsOnly(HashMap::from([(1, 2), (2, 1)]));
+        let mut logits = Logits::try_from_iter(T.iter().copied())?;
+        sampler.sample(&mut res, &mut logits)?;
+        assert!(logits.approx_eq(&expected, 1e-6));
+
+        // Without last tokens the warmup can't be checked, so it doesn't apply.
+        let mut logits = Logits::try_from_iter(T.iter().copied())?;
+        sampler.clone().warmup(10).sample(&mut res, &mut logits)?;
+        assert!(logits.approx_eq(&expected, 1e-6));
+        Ok(())
+    }
+
+    #[test]
+    fn test_windowed_repetition() -> Result<()> {
+        let tokens = [0, 1, 1, 2];
+        let run = |window| -> Result<Logits> {
+            let mut logits = Logits::try_from_iter([1.0f32, 1.0, 1.0, 1.0, -1.0])?;
+            SampleWindowedRepetition::new(2.0, 4, window)
+                .sample_with_tokens(&tokens, &mut logits)?;
+            Ok(logits)
+        };
+
+        // Distances from the end: token 2 -> 0, token 1 -> 1 and 2, token 0 -> 3.
+        // Linear weights are 1.0, 0.75 + 0.5 and 0.25 respectively.
+        let expected =
+            Logits::try_from_iter([1.0 / 2f32.powf(0.25), 1.0 / 2f32.powf(1.25), 0.5, 1.0, -1.0])?;
+        assert!(run(RepetitionWindow::Linear)?.approx_eq(&expected, 1e-6));
+
+        // Exponential weights with rate 0.5 are 1.0, 0.5 + 0.25 and 0.125.
+        let expected = Logits::try_from_iter([0.917004, 0.5946036, 0.5, 1.0, -1.0])?;
+        assert!(run(RepetitionWindow::Exponential(0.5))?.approx_eq(&expected, 1e-6));
+
+        // Step weights every occurrence equally, so token 1 is penalized twice.
+        let expected = Logits::try_from_iter([0.5f32, 0.25, 0.5, 1.0, -1.0])?;
+        assert!(run(RepetitionWindow::Step)?.approx_eq(&expected, 1e-6));
+
+        // Distances too large for an i32 still decay rather than wrapping around.
+        let far = 1usize << 40;
+        assert_eq!(RepetitionWindow::Exponential(0.5).weight(far, far + 1), 0.0);
+        Ok(())
+    }
+
     #[test]
     fn test_sequence_repetition() -> Result<()> {
         const T: &[f32] = &[0.2, 0.2, 0.2, 0.2, 0.2];
@@ -373,7 +1949,7 @@ mod sampler {
                 .min_length(3)
                 .flat_penalty(5.0),
             T,
-            &[0.249579, 0.249579, 0.249579, 0.001681, 0.249579],
+            &[0.249579, 0.249579, 0.249579, 0.249579, 0.001681],
             validate_sm,
         );
 
@@ -383,7 +1959,7 @@ mod sampler {
                 .min_length(3)
                 .stacking_penalty(1.25),
             T,
-            &[0.249579, 0.249579, 0.249579, 0.001681, 0.249579],
+            &[0.249579, 0.249579, 0.249579, 0.249579, 0.001681],
             validate_sm,
         );
 
@@ -396,13 +1972,212 @@ mod sampler {
                 .tolerance(1)
                 .stacking_penalty(1.25),
             T,
-            &[0.249579, 0.249579, 0.249579, 0.001681, 0.249579],
+            &[0.249579, 0.249579, 0.249579, 0.249579, 0.001681],
             validate_sm,
         );
 
         Ok(())
     }
 
+    #[test]
+    fn test_sequence_repetition_guards() -> Result<()> {
+        let samp = SampleSeqRepetition::default();
+        assert_eq!(
+            ConfigurableSampler::<usize, f32>::get_option(&samp, "min_length")?,
+            crate::configure::SamplerOptionValue::UInt(4)
+        );
+        let penalized = |samp: SampleSeqRepetition, tokens: &[u32]| -> Result<Vec<u32>> {
+            let mut logits = Logits::try_from_iter([0.0f32; 5])?;
+            samp.flat_penalty(1.0)
+                .sample_with_tokens(tokens, &mut logits)?;
+            Ok(logits
+                .iter()
+                .filter(|l| l.logit != 0.0)
+                .map(|l| l.token_id)
+                .collect())
+        };
+
+        // The default min_length of 4 works with the default last_n.
+        assert_eq!(
+            penalized(SampleSeqRepetition::default(), &[0, 1, 2, 3, 4, 0, 1, 2, 3])?,
+            [4]
+        );
+        // min_length below 2 disables the sampler.
+        assert!(penalized(SampleSeqRepetition::default().min_length(1), &[0, 1, 0])?.is_empty());
+        let tokens = [0, 1, 2, 0, 1];
+        assert_eq!(
+            penalized(SampleSeqRepetition::default().min_length(2), &tokens)?,
+            [2]
+        );
+        // The window needs to hold at least min_length * 2 tokens.
+        assert!(penalized(
+            SampleSeqRepetition::default().min_length(2).last_n(3),
+            &tokens
+        )?
+        .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sequence_repetition_max_search_steps() -> Result<()> {
+        // Searching this exhaustively would take a very long time.
+        let mut tokens = [0u32, 1, 2, 3].repeat(1023);
+        tokens.extend([0, 1, 2]);
+        let mut logits = Logits::try_from_iter([0.0f32, 0.0, 0.0, 0.0])?;
+        SampleSeqRepetition::default()
+            .min_length(3)
+            .last_n(tokens.len())
+            .flat_penalty(1.0)
+            .max_search_steps(10_000)
+            .sample_with_tokens(&tokens, &mut logits)?;
+        assert_eq!(
+            logits.iter().map(|l| l.logit).collect::<Vec<_>>(),
+            [0.0, 0.0, 0.0, -1.0]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sequence_repetition_out_of_range() -> Result<()> {
+        // Token id 3 continues the sequence but there are only 3 logits, so it's skipped.
+        let mut logits = Logits::try_from_iter([0.2f32, 0.2, 0.2])?;
+        SampleSeqRepetition::default()
+            .min_length(3)
+            .flat_penalty(5.0)
+            .sample_with_tokens(&[0, 1, 2, 3, 0, 1, 2], &mut logits)?;
+        assert_eq!(
+            logits.iter().map(|l| l.logit).collect::<Vec<_>>(),
+            [0.2, 0.2, 0.2]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_penalties_large_token_ids() -> Result<()> {
+        const T: &[f32] = &[0.2, 0.2, 0.2];
+        let tokens = [0, 1, 1000, 0, 1, 1000, 0, 1];
+        let run = |sampler: &mut dyn Sampler| -> Result<Vec<f32>> {
+            let mut logits = Logits::try_from_iter(T.iter().copied())?;
+            let mut res = SimpleSamplerResources::new(None, Some(tokens.to_vec()));
+            sampler.sample(&mut res, &mut logits)?;
+            Ok(logits.iter().map(|l| l.logit).collect())
+        };
+
+        assert_eq!(run(&mut SampleRepetition::new(2.0, 64))?, [0.1, 0.1, 0.2]);
+        assert_eq!(
+            run(&mut SampleFreqPresence::new(0.0, 0.1, 64))?,
+            [0.1, 0.1, 0.2]
+        );
+        // The sequence 0, 1 would continue with 1000.
+        assert_eq!(
+            run(&mut SampleSeqRepetition::default()
+                .min_length(2)
+                .flat_penalty(1.0))?,
+            T
+        );
+
+        let logits = Logits::try_from_iter(T.iter().copied())?;
+        assert_eq!(logits.logit_index_for(2), Some(2));
+        assert_eq!(logits.logit_index_for(1000), None);
+        assert_eq!(logits.logit_index_for(TID::MAX), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_keep() -> Result<()> {
+        const T: &[f32] = &[0.9, 0.04, 0.03, 0.02, 0.008, 0.002];
+        let mut res = NilSamplerResources;
+
+        for min_keep in 0..=T.len() + 1 {
+            let mut samplers: Vec<Box<dyn Sampler>> = vec![
+                Box::new(SampleTopK::new(1, min_keep)),
+                Box::new(SampleTopP::new(0.1, min_keep)),
+                Box::new(SampleMinP::new(0.9, min_keep)),
+                Box::new(SampleTopA::new(1.0, 1.0, min_keep)),
+                Box::new(SampleTailFree::new(0.01, min_keep)),
+                Box::new(SampleLocallyTypical::new(0.01, min_keep)),
+                Box::new(SampleTruncate::new(0.95, min_keep)),
+            ];
+            for sampler in samplers.iter_mut() {
+                let mut logits = Logits::try_from_iter(T.iter().map(|p| p.ln()))?;
+                sampler.sample(&mut res, &mut logits)?;
+                assert!(
+                    logits.len() >= effective_min_keep(min_keep).min(T.len()),
+                    "{sampler:?} kept {} with min_keep={min_keep}",
+                    logits.len()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_keep_zero() -> Result<()> {
+        assert_eq!((effective_min_keep(0), effective_min_keep(3)), (1, 3));
+        let mut res = NilSamplerResources;
+        let inputs: [&[f32]; 3] = [
+            &[0.9, 0.04, 0.03, 0.02, 0.008, 0.002],
+            &[0.25, 0.25, 0.25, 0.25],
+            &[0.5, 0.3, 0.1, 0.1],
+        ];
+
+        for input in inputs {
+            let mut samplers: Vec<Box<dyn Sampler>> = vec![
+                Box::new(SampleTopK::new(0, 0)),
+                Box::new(SampleTopP::new(0.0, 0)),
+                Box::new(SampleMinP::new(1.0, 0)),
+                Box::new(SampleTopA::new(1.0, 1.0, 0)),
+                Box::new(SampleTailFree::new(0.0, 0)),
+                Box::new(SampleLocallyTypical::new(0.0, 0)),
+                Box::new(SampleTruncate::new(1.0, 0)),
+            ];
+            for sampler in samplers.iter_mut() {
+                let mut logits = Logits::try_from_iter(input.iter().map(|p| p.ln()))?;
+                sampler.sample(&mut res, &mut logits)?;
+                assert!(!logits.is_empty(), "{sampler:?} emptied {input:?}");
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sample_with_tokens() -> Result<()> {
+        const T: &[f32] = &[0.5, 0.2, 0.2, 0.2, 0.2, 0.1];
+        let tokens = [0u32, 1, 2, 3, 0, 1, 2];
+        let mut res = SimpleSamplerResources::new(None, Some(tokens.to_vec()));
+
+        let check = |via_res: &mut Logits, via_slice: &mut Logits| {
+            assert_eq!(
+                via_res.iter().map(|l| l.logit).collect::<Vec<_>>(),
+                via_slice.iter().map(|l| l.logit).collect::<Vec<_>>()
+            );
+        };
+
+        let (mut l1, mut l2) = (Logits::try_from(T.to_vec())?, Logits::try_from(T.to_vec())?);
+        let mut sampler = SampleRepetition::new(1.5, 4);
+        check(
+            sampler.sample(&mut res, &mut l1)?,
+            sampler.sample_with_tokens(&tokens, &mut l2)?,
+        );
+
+        let (mut l1, mut l2) = (Logits::try_from(T.to_vec())?, Logits::try_from(T.to_vec())?);
+        let mut sampler = SampleFreqPresence::new(0.5, 0.25, 6);
+        check(
+            sampler.sample(&mut res, &mut l1)?,
+            sampler.sample_with_tokens(&tokens, &mut l2)?,
+        );
+
+        let (mut l1, mut l2) = (Logits::try_from(T.to_vec())?, Logits::try_from(T.to_vec())?);
+        let mut sampler = SampleSeqRepetition::default()
+            .min_length(3)
+            .flat_penalty(2.0);
+        check(
+            sampler.sample(&mut res, &mut l1)?,
+            sampler.sample_with_tokens(&tokens, &mut l2)?,
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_locally_typical() {
         let mut res = NilSamplerResources;
@@ -422,6 +2197,69 @@ mod sampler {
         );
     }
 
+    #[test]
+    fn test_locally_typical_reorders() -> Result<()> {
+        let mut logits = Logits::try_from_iter([0.5f32, 0.3, 0.1, 0.1].map(f32::ln))?;
+        SampleLocallyTypical::new(0.85, 1).sample(&mut NilSamplerResources, &mut logits)?;
+        assert_eq!(
+            logits.iter().map(|l| l.token_id).collect::<Vec<_>>(),
+            vec![1, 0, 2]
+        );
+        assert!(!logits.get_sorted());
+        assert_eq!(
+            logits.sample_token(&mut NilSamplerResources, &mut SampleGreedy::new())?,
+            Some(0)
+        );
+        assert_eq!(logits.ensure_sorted()?[0].token_id, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_locally_typical_min_keep() -> Result<()> {
+        let survivors = |p, min_keep| -> Result<usize> {
+            let mut logits = Logits::try_from_iter([0.5f32, 0.3, 0.1, 0.1].map(f32::ln))?;
+            SampleLocallyTypical::new(p, min_keep).sample(&mut NilSamplerResources, &mut logits)?;
+            Ok(logits.len())
+        };
+        // Ordered by typicality the probabilities are 0.3, 0.5, 0.1, 0.1.
+        assert_eq!(survivors(0.2, 1)?, 1);
+        assert_eq!(survivors(0.5, 1)?, 2);
+        assert_eq!(survivors(0.85, 1)?, 3);
+        assert_eq!(survivors(0.2, 3)?, 3);
+        assert_eq!(survivors(0.5, 3)?, 3);
+        assert_eq!(survivors(0.85, 3)?, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_consider() -> Result<()> {
+        let run = |sampler: &mut dyn Sampler| -> Result<Vec<TID>> {
+            let mut logits = Logits::try_from_iter((0..100).map(|i| (i as f32 / 10.0).sin()))?;
+            sampler.sample(&mut NilSamplerResources, &mut logits)?;
+            assert!(!logits.is_empty());
+            logits.ensure_softmax()?;
+            assert!((logits.iter().map(|l| l.prob).sum::<f32>() - 1.0).abs() < 1e-5);
+            Ok(logits.iter().map(|l| l.token_id).collect())
+        };
+        let top5 = Logits::try_from_iter((0..100).map(|i| (i as f32 / 10.0).sin()))?
+            .top_k(5)
+            .iter()
+            .map(|l| l.token_id)
+            .collect::<Vec<_>>();
+
+        let kept = run(&mut SampleLocallyTypical::new(0.9, 1).max_consider(Some(5)))?;
+        assert!(kept.len() <= 5 && kept.iter().all(|tid| top5.contains(tid)));
+        assert!(run(&mut SampleLocallyTypical::new(0.9, 1))?.len() > 5);
+
+        let kept = run(&mut SampleTailFree::new(0.95, 1).max_consider(Some(5)))?;
+        assert!(kept.len() <= 5 && kept.iter().all(|tid| top5.contains(tid)));
+
+        // min_keep still applies.
+        let kept = run(&mut SampleLocallyTypical::new(1.0, 3).max_consider(Some(0)))?;
+        assert_eq!(kept.len(), 3);
+        Ok(())
+    }
+
     #[test]
     fn test_tail_free() {
         const T: &[f32] = &[0.1, 0.15, 0.2, 0.25, 0.3];
@@ -448,6 +2286,173 @@ mod sampler {
             &[0.3, 0.25],
             validate,
         );
+        let kept = |sampler: &mut SampleTailFree, input: &[f32]| -> usize {
+            let mut logits = Logits::try_from_iter(input.iter().map(|p| p.ln())).unwrap();
+            sampler
+                .sample(&mut NilSamplerResources, &mut logits)
+                .unwrap()
+                .len()
+        };
+        assert_eq!(kept(&mut SampleTailFree::new(0.9999, 1), T), 2);
+        assert_eq!(
+            kept(&mut SampleTailFree::new(0.9999, 1).epsilon(0.001), T),
+            5
+        );
+        for z in [0.0, 0.25, 0.5] {
+            assert_eq!(kept(&mut SampleTailFree::new(z, 1), &T[3..]), 2);
+        }
+    }
+
+    #[test]
+    fn test_temperature_prob_space() -> Result<()> {
+        const P: &[f32] = &[0.1, 0.2, 0.3, 0.4];
+        let by_tid = |logits: &Logits| {
+            let mut probs = logits
+                .iter()
+                .map(|l| (l.token_id, l.prob))
+                .collect::<Vec<_>>();
+            probs.sort_by_key(|(tid, _)| *tid);
+            probs.into_iter().map(|(_, p)| p).collect::<Vec<_>>()
+        };
+
+        for temp in [0.5, 0.8, 1.5] {
+            let mut expected = Logits::try_from_iter(P.iter().map(|p| p.ln()))?;
+            SampleTemperature::new(temp).sample(&mut (), &mut expected)?;
+            expected.ensure_softmax()?;
+
+            let mut logits = Logits::from_probs(P)?;
+            SampleTemperature::new(temp)
+                .prob_space(true)
+                .sample(&mut (), &mut logits)?;
+            assert!(logits.get_softmax());
+            let (probs, expected) = (by_tid(&logits), by_tid(&expected));
+            assert!(
+                probs
+                    .iter()
+                    .zip(&expected)
+                    .all(|(a, b)| (a - b).abs() < 1e-6),
+                "{probs:?} != {expected:?}"
+            );
+            assert!((logits.iter().map(|l| l.logit.exp()).sum::<f32>() - 1.0).abs() < 1e-6);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_toggle() -> Result<()> {
+        const T: &[f32] = &[0.1, 0.4, 0.2];
+        let mut res = NilSamplerResources;
+        let mut sampler = ToggleSampler::new(SampleTemperature::new(0.5)).enabled(false);
+
+        test_sampler_raw(&mut res, &mut sampler, T, T, validate_eq);
+        assert!(!sampler.is_enabled());
+
+        sampler.set_enabled(true);
+        test_sampler_raw(&mut res, &mut sampler, T, &[0.2, 0.8, 0.4], validate_eq);
+
+        let mut sc = SamplerChain::new() + ToggleSampler::new(SampleGreedy::new()).enabled(false);
+        let mut logits = Logits::try_from_iter(T.iter().copied())?;
+        assert_eq!(sc.sample_token(&mut res, &mut logits)?, None);
+        assert_eq!(sampler.name(), "SampleTemperature");
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_scoped() -> Result<()> {
+        const T: &[f32] = &[1.0, 2.0, 3.0, 4.0];
+        let mut res = NilSamplerResources;
+        let by_id = |logits: &Logits| {
+            let mut result = logits
+                .iter()
+                .map(|l| (l.token_id, l.logit))
+                .collect::<Vec<_>>();
+            result.sort_by_key(|(tid, _)| *tid);
+            result
+                .into_iter()
+                .map(|(_, logit)| logit)
+                .collect::<Vec<_>>()
+        };
+
+        let mut logits = Logits::try_from_iter(T.iter().copied())?;
+        logits.sample(
+            &mut res,
+            &mut RangeScoped::new(SampleTemperature::new(0.5), 1..3),
+        )?;
+        assert_eq!(by_id(&logits), [1.0, 4.0, 6.0, 4.0]);
+        assert!(!logits.get_sorted() && !logits.get_softmax());
+
+        let mut logits = Logits::try_from_iter(T.iter().copied())?;
+        let mut sampler = RangeScoped::new(SampleGreedy::new(), 0..2);
+        assert_eq!(logits.sample_token(&mut res, &mut sampler)?, Some(1));
+        assert_eq!(by_id(&logits), T);
+
+        let mut logits = Logits::try_from_iter(T.iter().copied())?;
+        logits.sample(&mut res, &mut RangeScoped::new(SampleTopK::new(1, 1), 0..3))?;
+        assert_eq!(by_id(&logits), [3.0, 4.0]);
+        assert_eq!(sampler.name(), "SampleGreedy");
+
+        // The logits outside the range are added back when the wrapped sampler fails.
+        let mut logits = Logits::try_from_iter(T.iter().copied())?;
+        let mut sampler = RangeScoped::new(
+            FnSampler::new(|_: &mut dyn HasSamplerResources, _: &mut Logits| {
+                anyhow::bail!("failed")
+            }),
+            1..3,
+        );
+        assert!(logits.sample(&mut res, &mut sampler).is_err());
+        assert_eq!(by_id(&logits), T);
+
+        // The wrapped sampler doesn't run when no token is in the range.
+        let mut logits = Logits::try_from_iter(T.iter().copied())?;
+        logits.sample(&mut res, &mut sampler.range(10..20))?;
+        assert_eq!(by_id(&logits), T);
+        Ok(())
+    }
+
+    #[test]
+    fn test_temperature_zero() -> Result<()> {
+        use rand::SeedableRng;
+
+        const T: &[f32] = &[0.1, 0.4, 0.2];
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+            None,
+        );
+
+        test_sampler_raw(
+            &mut res,
+            &mut SampleTemperature::new(0.0),
+            T,
+            &[f32::NEG_INFINITY, 0.4, f32::NEG_INFINITY],
+            validate_eq,
+        );
+
+        let mut sc = SamplerChain::new() + SampleTemperature::new(0.0) + SampleRandDistrib::new();
+        for _ in 0..10 {
+            let mut logits = Logits::try_from_iter(T.iter().copied())?;
+            assert_eq!(sc.sample_token(&mut res, &mut logits)?, Some(1));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_scheduled_temperature() {
+        const T: &[f32] = &[1.0, 2.0];
+        let mut res = SimpleSamplerResources::new(None, None);
+        let mut sampler = SampleScheduledTemperature::from_steps([1.0, 0.5, 0.25]);
+
+        for expected in [[1.0, 2.0], [2.0, 4.0], [4.0, 8.0], [4.0, 8.0]] {
+            test_sampler_raw(&mut res, &mut sampler, T, &expected, validate_eq);
+            res.increment_step();
+        }
+
+        test_sampler_raw(
+            res.set_step_count(2),
+            &mut SampleScheduledTemperature::from_fn(|step| 1.0 / (step + 1) as f32),
+            T,
+            &[3.0, 6.0],
+            validate_eq,
+        );
     }
 
     #[test]
@@ -471,6 +2476,77 @@ mod sampler {
         );
     }
 
+    #[test]
+    fn test_flat_bias_mode() -> Result<()> {
+        let mut logits = Logits::try_from_iter([0.1, 0.15, 0.2, 0.25, 0.3])?;
+        let mut add = SampleFlatBias::new([(2, 1.0)]);
+        add.sample(&mut (), &mut logits)?;
+        add.sample(&mut (), &mut logits)?;
+        assert_eq!(logits[2].logit, 2.2);
+
+        let mut set = SampleFlatBias::new([(2, 5.0), (4, f32::NEG_INFINITY)]).mode(BiasMode::Set);
+        set.sample(&mut (), &mut logits)?;
+        set.sample(&mut (), &mut logits)?;
+        assert_eq!(
+            logits.iter().map(|l| l.logit).collect::<Vec<_>>(),
+            [0.1, 0.15, 5.0, 0.25, f32::NEG_INFINITY]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_flat_bias_from_strings() -> Result<()> {
+        use std::collections::HashMap;
+
+        let encoder = |s: &str| match s {
+            "zero" => vec![0],
+            "two" => vec![2],
+            "both" => vec![0, 4],
+            _ => vec![],
+        };
+        let mut sampler = SampleFlatBias::from_strings(
+            HashMap::from([
+                ("two".to_string(), f32::NEG_INFINITY),
+                ("both".to_string(), 1.0),
+            ]),
+            encoder,
+        )?;
+        let mut logits = Logits::try_from_iter([0.1, 0.15, 0.2, 0.25, 0.3])?;
+        sampler.sample(&mut (), &mut logits)?;
+        assert_eq!(
+            logits.iter().map(|l| l.logit).collect::<Vec<_>>(),
+            [1.1, 0.15, f32::NEG_INFINITY, 0.25, 1.3]
+        );
+        assert!(SampleFlatBias::from_strings([("nope", 1.0)], encoder).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_rng() -> Result<()> {
+        let mut samplers: Vec<(Box<dyn Sampler>, &str)> = vec![
+            (Box::new(SampleRandDistrib::new()), "random distribution"),
+            (Box::new(SampleMirostat1::new(8, 5.0, 0.1)), "mirostat 1"),
+            (Box::new(SampleMirostat2::new(1.5, 0.1)), "mirostat 2"),
+            (Box::new(SampleSpeculative::new(1, 0.9)), "speculative"),
+        ];
+        for (sampler, name) in samplers.iter_mut() {
+            let mut logits = Logits::try_from_iter((1..=8).map(|i| -(i as f32).ln()))?;
+            let err = sampler.sample(&mut (), &mut logits).expect_err(name);
+            assert!(
+                matches!(
+                    err.downcast_ref::<SamplerError>(),
+                    Some(SamplerError::MissingRng(n)) if n == name
+                ),
+                "{err:?}"
+            );
+            assert_eq!(
+                err.to_string(),
+                format!("{name} sampler requires an RNG resource")
+            );
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_rand_distrib() -> Result<()> {
         use rand::SeedableRng;
@@ -492,6 +2568,87 @@ mod sampler {
         Ok(())
     }
 
+    #[test]
+    fn test_rand_distrib_deterministic() -> Result<()> {
+        use rand::SeedableRng;
+        use std::collections::HashSet;
+
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+            None,
+        );
+        let mut draw = |sampler: &mut SampleRandDistrib| -> Result<HashSet<u32>> {
+            (0..50)
+                .map(|_| {
+                    Logits::try_from_iter([1.0f32, 1.1, 1.0, 1.0])?
+                        .sample_token(&mut res, sampler)?
+                        .ok_or_else(|| anyhow::anyhow!("no token selected"))
+                })
+                .collect()
+        };
+
+        let mut sampler = SampleRandDistrib::new();
+        assert!(draw(&mut sampler)?.len() > 1);
+        sampler.set_deterministic(true);
+        assert_eq!(draw(&mut sampler)?, HashSet::from([1]));
+        ConfigurableSampler::<usize, f32>::configure(&mut sampler, "deterministic=false")?;
+        assert!(draw(&mut sampler)?.len() > 1);
+        assert_eq!(
+            draw(&mut SampleRandDistrib::new().deterministic(true))?,
+            HashSet::from([1])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rand_distrib_single_token() -> Result<()> {
+        use rand::{RngCore, SeedableRng};
+
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+            None,
+        );
+        let mut logits = Logits::try_from_iter([0.1f32, 0.4, 0.2])?;
+        let mut sc = SamplerChain::new() + SampleTopK::new(1, 1) + SampleRandDistrib::new();
+        assert_eq!(sc.sample_token(&mut res, &mut logits)?, Some(1));
+
+        let mut next = 0;
+        res.with_rng_mut(&mut |rng| next = rng.next_u64())?;
+        assert_eq!(next, rand::rngs::StdRng::seed_from_u64(123).next_u64());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rand_distrib_f64_source() -> Result<()> {
+        #[derive(Debug)]
+        struct F64Resources(Vec<f64>);
+
+        impl HasSamplerResources for F64Resources {
+            fn with_rand_f64(
+                &mut self,
+                fun: &mut dyn FnMut(&mut dyn FnMut() -> f64),
+            ) -> Result<(), SamplerError> {
+                fun(&mut || self.0.pop().unwrap_or_default());
+                Ok(())
+            }
+        }
+
+        let mut res = F64Resources(vec![0.99, 0.5, 0.05]);
+        let mut sampler = SampleRandDistrib::new();
+        let mut logits = Logits::try_from_iter([0.1f32, 0.3, 0.6].map(|i| i.ln()))?;
+        assert_eq!(logits.sample_token(&mut res, &mut sampler)?, Some(2));
+        assert_eq!(logits.sample_token(&mut res, &mut sampler)?, Some(2));
+        assert_eq!(logits.sample_token(&mut res, &mut sampler)?, Some(0));
+        assert!(logits.sample_token(&mut (), &mut sampler).is_err());
+
+        let mut res =
+            SimpleSamplerResources::new(Some(Box::new(rand::rngs::mock::StepRng::new(0, 0))), None);
+        let mut vals = vec![];
+        res.with_rand_f64(&mut |next| vals.extend([next(), next()]))?;
+        assert_eq!(vals, [0.0, 0.0]);
+        Ok(())
+    }
+
     #[test]
     fn test_mirostat1() -> Result<()> {
         use rand::SeedableRng;
@@ -514,6 +2671,38 @@ mod sampler {
         Ok(())
     }
 
+    #[test]
+    fn test_mirostat1_k_range() -> Result<()> {
+        use rand::SeedableRng;
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+            None,
+        );
+        let inputs: [&[f32]; 4] = [
+            // Zero probabilities after the top token make the estimate infinite/NaN.
+            &[1000.0, 0.0, f32::NEG_INFINITY, f32::NEG_INFINITY],
+            &[0.0, 0.0, 0.0, 0.0],
+            &[50.0, -50.0, -50.0, -50.0],
+            &[1.0, 0.5, 0.25, 0.125],
+        ];
+        for input in inputs {
+            for mu in [0.0, 10.0, 1e30] {
+                let sampler = SampleMirostat1::new(4, 5.0, 0.1).mu(mu);
+                let mut logits = Logits::try_from_iter(input.iter().copied())?;
+                logits.ensure_softmax()?;
+                let k = sampler.estimate_k(&logits);
+                assert!(
+                    (1..=4).contains(&k),
+                    "k {k} out of range for {input:?}, mu {mu}"
+                );
+                assert!(logits
+                    .sample_token(&mut res, &mut sampler.clone())?
+                    .is_some());
+            }
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_mirostat2() -> Result<()> {
         use rand::SeedableRng;
@@ -535,6 +2724,122 @@ mod sampler {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_mirostat_reset() -> Result<()> {
+        use rand::SeedableRng;
+
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+            None,
+        );
+        let mut m1 = SampleMirostat1::new(4, 5.0, 0.1).mu(3.0);
+        let mut m2 = SampleMirostat2::new(5.0, 0.1).mu(3.0);
+        m1.sample(&mut res, &mut Logits::try_from_iter(T1.iter().copied())?)?;
+        m2.sample(&mut res, &mut Logits::try_from_iter(T1.iter().copied())?)?;
+        assert!(m1.mu != 3.0 && m2.mu != 3.0);
+        m1.reset();
+        m2.reset();
+        assert_eq!((m1.mu, m2.mu), (3.0, 3.0));
+
+        ConfigurableSampler::<usize, f32>::configure(&mut m2, "mu=4")?;
+        m2.sample(&mut res, &mut Logits::try_from_iter(T1.iter().copied())?)?;
+        m2.reset();
+        assert_eq!(m2.mu, 4.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mirostat_state() -> Result<()> {
+        use rand::SeedableRng;
+
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+            None,
+        );
+        let mut m1 = SampleMirostat1::new(4, 5.0, 0.1);
+        let mut m2 = SampleMirostat2::new(5.0, 0.1);
+        let mut snapshots = None;
+
+        for step in 0..3 {
+            m1.sample(&mut res, &mut Logits::try_from_iter(T1.iter().copied())?)?;
+            m2.sample(&mut res, &mut Logits::try_from_iter(T1.iter().copied())?)?;
+            if step == 0 {
+                snapshots = Some((m1.snapshot_state(), m2.snapshot_state()));
+            }
+        }
+        let (snap1, snap2) = snapshots.expect("Missing snapshots");
+        assert!(snap1.token.is_some() && snap2.token.is_some());
+        assert_ne!(m1.snapshot_state().mu, snap1.mu);
+        assert_ne!(m2.snapshot_state().mu, snap2.mu);
+
+        m1.restore_state(snap1);
+        m2.restore_state(snap2);
+        assert_eq!(m1.snapshot_state(), snap1);
+        assert_eq!(m2.snapshot_state(), snap2);
+        assert_eq!((m1.mu, m2.mu), (snap1.mu, snap2.mu));
+        Ok(())
+    }
+
+    #[test]
+    fn test_speculative() -> Result<()> {
+        use rand::SeedableRng;
+
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+            None,
+        );
+        let logits = || Logits::try_from_iter(T1.iter().copied().map(f32::ln));
+
+        // The target is at least as confident as the draft: always accepted.
+        let mut sampler = SampleSpeculative::default();
+        for _ in 0..20 {
+            sampler.set_draft(3, 0.4);
+            assert_eq!(sampler.sample_token(&mut res, &mut logits()?)?, Some(3));
+            assert_eq!(sampler.accepted(), Some(true));
+        }
+
+        // The target gives the draft no probability: always rejected and the
+        // draft token can't be selected from the residual distribution.
+        let mut logits0 = Logits::try_from_iter([f32::NEG_INFINITY, 0.0, 0.0, 0.0])?;
+        let mut sampler = SampleSpeculative::new(0, 0.9);
+        assert!(matches!(
+            sampler.sample_token(&mut res, &mut logits0)?,
+            Some(1..=3)
+        ));
+        assert_eq!(sampler.accepted(), Some(false));
+
+        // The draft was used up, so the next run samples the target distribution.
+        let mut logits0 = Logits::try_from_iter([f32::NEG_INFINITY, 0.0, 0.0, 0.0])?;
+        assert!(matches!(
+            sampler.sample_token(&mut res, &mut logits0)?,
+            Some(1..=3)
+        ));
+        assert_eq!(sampler.accepted(), None);
+
+        // Accepted with probability 0.1 / 0.8. When rejected, the residual
+        // probability of token 0 is 0 so it must be another token.
+        let (mut accepted, mut rejected) = (0, 0);
+        for _ in 0..200 {
+            sampler.set_draft(0, 0.8);
+            let mut logits = logits()?;
+            let tid = sampler.sample_token(&mut res, &mut logits)?;
+            match sampler.accepted() {
+                Some(true) => {
+                    assert_eq!(tid, Some(0));
+                    accepted += 1;
+                }
+                Some(false) => {
+                    assert!(matches!(tid, Some(1..=3)));
+                    assert_eq!(logits.iter().find(|l| l.token_id == 0).unwrap().prob, 0.0);
+                    rejected += 1;
+                }
+                None => panic!("No token selected"),
+            }
+        }
+        assert!(accepted > 0 && rejected > accepted);
+        Ok(())
+    }
 }
 
 mod configure {
@@ -583,6 +2888,31 @@ mod configure {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_float_percent() -> Result<()> {
+        assert_eq!(
+            SamplerOptionValue::parse_value(SamplerOptionType::Float, "90%")?,
+            SamplerOptionValue::Float(0.9)
+        );
+        assert_eq!(
+            SamplerOptionValue::parse_value(SamplerOptionType::Float, "0.9")?,
+            SamplerOptionValue::Float(0.9)
+        );
+        assert_eq!(
+            SamplerOptionValue::parse_value(SamplerOptionType::Float, "inf%")?,
+            SamplerOptionValue::Float(f64::INFINITY)
+        );
+        assert!(SamplerOptionValue::parse_value(SamplerOptionType::Float, "90%%").is_err());
+
+        let mut samp = SampleTopP::default();
+        ConfigurableSampler::<usize, f32>::configure(&mut samp, "p=90%")?;
+        assert_eq!(
+            ConfigurableSampler::<usize, f32>::get_option(&samp, "p")?,
+            SamplerOptionValue::Float(0.9f32 as f64)
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_set_get_options() -> Result<()> {
         let mut samp = SampleTemperature::new(5.0);
@@ -608,6 +2938,78 @@ mod configure {
         Ok(())
     }
 
+    #[test]
+    fn test_help() {
+        let help = ConfigurableSampler::<usize, f32>::help(&SampleTopP::default());
+        let mut lines = help.lines();
+        assert!(lines.next().unwrap().starts_with("top-p: "));
+        assert!(lines.next().unwrap().starts_with("  p (float): "));
+        assert!(lines.next().unwrap().starts_with("  min_keep (uint): "));
+        assert!(lines.next().unwrap().starts_with("  inclusive (bool): "));
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn test_to_config_string() -> Result<()> {
+        let samp = SampleTemperature::new(0.8);
+        let cfg = ConfigurableSampler::<usize, f32>::to_config_string(&samp);
+        assert_eq!(cfg, "temperature=0.8");
+        let mut parsed = SampleTemperature::new(5.0);
+        ConfigurableSampler::<usize, f32>::configure(&mut parsed, &cfg)?;
+        assert_eq!(parsed, samp);
+
+        let samp = SampleTemperature::new(f32::NEG_INFINITY);
+        assert_eq!(
+            ConfigurableSampler::<usize, f32>::to_config_string(&samp),
+            "temperature=-inf"
+        );
+        assert_eq!(
+            ConfigurableSampler::<usize, f32>::to_config_string(&SampleTopP::new(0.95, 2)),
+            "p=0.95:min_keep=2:inclusive=true"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_config() -> Result<()> {
+        let mut samp = SampleTopK::new(5, 3);
+        ConfigurableSampler::<usize, f32>::configure(&mut samp, "renormalize=true")?;
+        assert_eq!(
+            ConfigurableSampler::<usize, f32>::default_config(&samp),
+            [
+                ("k", SamplerOptionValue::UInt(40)),
+                ("min_keep", SamplerOptionValue::UInt(1)),
+                ("renormalize", SamplerOptionValue::Bool(false)),
+                ("include_ties", SamplerOptionValue::Bool(false)),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_configure_strict() -> Result<()> {
+        let mut samp = SampleTemperature::new(5.0);
+        let cs: &mut dyn ConfigurableSampler<usize, f32> = &mut samp;
+        assert!(cs.configure_strict("temp=0.8").is_err());
+        assert!(cs.configure_strict("0.8").is_err());
+        assert_eq!(
+            cs.get_option("temperature")?,
+            SamplerOptionValue::Float(5.0)
+        );
+
+        cs.configure("temp=0.8")?;
+        assert_eq!(
+            cs.get_option("temperature")?,
+            SamplerOptionValue::Float(0.8f32 as f64)
+        );
+        cs.configure_strict("temperature=0.5")?;
+        assert_eq!(
+            cs.get_option("temperature")?,
+            SamplerOptionValue::Float(0.5)
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_config_from_str1() -> Result<()> {
         let mut samp = SampleTemperature::new(5.0);
@@ -687,10 +3089,201 @@ mod build {
         let mut sc = ss.into_chain();
 
         let mut res = SimpleSamplerResources::new(None, Some(vec![0, 1, 2, 3, 3, 0, 0]));
-        let mut logits = Logits::try_from_iter([0.2, 0.2, 0.19, 0.2].into_iter())?;
+        let mut logits = Logits::try_from_iter([0.2, 0.2, 0.19, 0.2])?;
         let tok = sc.sample_token(&mut res, &mut logits)?;
         assert_eq!(tok, Some(1));
 
         Ok(())
     }
+
+    #[test]
+    fn test_into_chain_with_defaults() -> Result<()> {
+        let builder = || -> SamplerChainBuilder<usize, f32> {
+            SamplerChainBuilder::from([
+                (
+                    "bias".to_string(),
+                    SamplerSlot::new_single(
+                        || Box::new(SampleFlatBias::new([(3, f32::NEG_INFINITY)])),
+                        Option::<SampleFlatBias>::None,
+                    ),
+                ),
+                (
+                    "bias2".to_string(),
+                    SamplerSlot::new_chain(
+                        || Box::new(SampleFlatBias::new([(2, f32::NEG_INFINITY)])),
+                        [],
+                    ),
+                ),
+                (
+                    "greedy".to_string(),
+                    SamplerSlot::new_static(|| Box::new(SampleGreedy::new())),
+                ),
+            ])
+        };
+        let logits = Logits::try_from_iter([0.1f32, 0.2, 0.3, 0.4])?;
+
+        // The unconfigured slots are left out.
+        let mut sc = builder().into_chain();
+        assert_eq!(sc.sample_token(&mut (), &mut logits.clone())?, Some(3));
+
+        // Now they contribute their defaults.
+        let mut sc = builder().into_chain_with_defaults();
+        assert_eq!(sc.sample_token(&mut (), &mut logits.clone())?, Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_llama_cpp_defaults() -> Result<()> {
+        use rand::SeedableRng;
+
+        let mut ss = SamplerChainBuilder::llama_cpp_defaults();
+        assert_eq!(
+            ss.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(),
+            [
+                "flat_bias",
+                "repetition",
+                "freq_presence",
+                "top_k",
+                "tail_free",
+                "locally_typical",
+                "top_p",
+                "temperature",
+                "rand_distrib"
+            ]
+        );
+        ss.configure("temperature", "0.01")?;
+        ss.configure("top_k", "k=2")?;
+
+        let mut sc = ss.into_chain();
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+            Some(vec![]),
+        );
+        let mut logits = Logits::try_from_iter([0.1, 0.2, 0.3, 0.4])?;
+        assert_eq!(sc.sample_token(&mut res, &mut logits)?, Some(3));
+        assert_eq!(logits.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mirostat_defaults() -> Result<()> {
+        use rand::SeedableRng;
+
+        let ss = SamplerChainBuilder::mirostat_v2_defaults(5.0, 0.1);
+        assert_eq!(
+            ss.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(),
+            [
+                "flat_bias",
+                "repetition",
+                "freq_presence",
+                "temperature",
+                "mirostat"
+            ]
+        );
+        let mut sc = ss.into_chain();
+        let mut res = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(123))),
+            Some(vec![]),
+        );
+        let mut logits = Logits::try_from_iter([0.0f32, 0.0, 1.0].map(|i| i.ln()))?;
+        assert_eq!(sc.sample_token(&mut res, &mut logits)?, Some(2));
+
+        let mut ss = SamplerChainBuilder::mirostat_v1_defaults(3, 5.0, 0.1);
+        ss.configure("temperature", "0.5")?;
+        let mut logits = Logits::try_from_iter([0.0f32, 1.0, 0.0].map(|i| i.ln()))?;
+        assert_eq!(
+            ss.into_chain().sample_token(&mut res, &mut logits)?,
+            Some(1)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_option_keys() -> Result<()> {
+        let mut ss = SamplerChainBuilder::llama_cpp_defaults();
+        assert_eq!(
+            ss.option_keys("top_p")?,
+            [
+                ("p", SamplerOptionType::Float),
+                ("min_keep", SamplerOptionType::UInt),
+                ("inclusive", SamplerOptionType::Bool),
+            ]
+        );
+        assert_eq!(
+            ss.option_keys("rand_distrib")?,
+            [("deterministic", SamplerOptionType::Bool)]
+        );
+        assert!(ss.option_keys("top_q").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_registry_apply() -> Result<()> {
+        let mut registry = SamplerRegistry::with_builtins();
+        let mut res = NilSamplerResources;
+
+        let mut logits = Logits::try_from_iter(T1.iter().copied())?;
+        assert_eq!(registry.apply("top_k", "k=2", &mut res, &mut logits)?, None);
+        assert_eq!(
+            logits.iter().map(|l| l.token_id).collect::<Vec<_>>(),
+            vec![3, 2]
+        );
+        assert_eq!(
+            registry.apply("greedy", "", &mut res, &mut logits)?,
+            Some(3)
+        );
+
+        assert!(registry.apply("top_z", "", &mut res, &mut logits).is_err());
+        assert!(registry
+            .apply("top_k", "z=1", &mut res, &mut logits)
+            .is_err());
+        Ok(())
+    }
+}
+
+mod send_sync {
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_samplers_send_sync() {
+        assert_send_sync::<SamplerChain>();
+        assert_send_sync::<SampleClamp>();
+        assert_send_sync::<SampleCollect>();
+        assert_send_sync::<SampleEosBias>();
+        assert_send_sync::<SampleFlatBias>();
+        assert_send_sync::<SampleFreqPresence>();
+        assert_send_sync::<SampleGreedy>();
+        assert_send_sync::<SampleLocallyTypical>();
+        assert_send_sync::<SampleMinP>();
+        assert_send_sync::<SampleMirostat1>();
+        assert_send_sync::<SampleMirostat2>();
+        assert_send_sync::<SamplePenalties>();
+        assert_send_sync::<SamplePercentile>();
+        assert_send_sync::<SampleRandDistrib>();
+        assert_send_sync::<SampleRepetition>();
+        assert_send_sync::<SampleScale>();
+        assert_send_sync::<SampleScheduledTemperature>();
+        assert_send_sync::<SampleSeqRepetition>();
+        assert_send_sync::<SampleSpeculative>();
+        assert_send_sync::<SampleTailFree>();
+        assert_send_sync::<SampleTemperature>();
+        assert_send_sync::<ToggleSampler<SampleGreedy>>();
+        assert_send_sync::<RangeScoped<SampleGreedy>>();
+        assert_send_sync::<SampleTopA>();
+        assert_send_sync::<SampleTopK>();
+        assert_send_sync::<SampleTopP>();
+        assert_send_sync::<SampleTruncate>();
+        assert_send_sync::<SampleWindowedRepetition>();
+    }
+
+    #[test]
+    fn test_resources_send_sync() {
+        assert_send_sync::<NilSamplerResources>();
+        assert_send_sync::<TokenSliceSamplerResources<'static>>();
+        assert_send_sync::<SimpleSamplerResources>();
+        assert_send_sync::<SeedableSamplerResources>();
+        assert_send_sync::<SharedTokenResources>();
+    }
 }
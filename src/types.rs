@@ -1,4 +1,6 @@
 use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
     fmt::Debug,
     ops::{Deref, DerefMut},
     sync::{Arc, Mutex},
@@ -7,12 +9,20 @@ use std::{
 use anyhow::Result;
 use thiserror::Error;
 
+use crate::configure::SamplerMetadata;
 pub use crate::{chain::*, resource::*};
 
 /// Type for token IDs.
+///
+/// The samplers, [Logits] and [SamplerChain] all use this type: token ids
+/// are always `u32`. Convert ids to and from your model's type at the boundary.
 pub type TID = u32;
 
 /// Type for logits.
+///
+/// Like [TID], this is fixed: logits and probabilities are always `f32`.
+/// Note that this is separate from the numeric types used to configure
+/// samplers (see [crate::configure]), which may differ.
 pub type L = f32;
 
 #[derive(Debug, Error)]
@@ -30,6 +40,28 @@ pub enum SamplerError {
     /// Container for errors that occured while processing logits.
     LogitsError(LogitsError),
 
+    #[error("no token could be selected: logits are empty")]
+    /// A token selecting sampler in strict mode was given empty logits.
+    EmptyLogits,
+
+    #[error("token id could not be converted to or from an index")]
+    /// A token id couldn't be converted to or from `usize`.
+    TokenIdConversion,
+
+    #[error("sampler {0} selected a token after an earlier sampler already selected one")]
+    /// In strict mode, a [SamplerChain] had more than one sampler select a token.
+    /// Contains the index of the offending sampler in the chain.
+    ConflictingSelection(usize),
+
+    #[error("token id {0} is out of range for the logits")]
+    /// A token id didn't correspond to any entry in the logits.
+    TokenIdOutOfRange(usize),
+
+    #[error("{0} sampler requires an RNG resource")]
+    /// A sampler that needs randomness was run without an RNG resource.
+    /// Contains the name of the sampler from its metadata.
+    MissingRng(&'static str),
+
     #[error("rand error: {0}")]
     /// RNG-related errors
     RandError(rand::Error),
@@ -46,6 +78,12 @@ pub enum LogitsError {
     /// Contains the position (AKA token id) of the offending logit.
     /// Logits cannot be NaN.
     InvalidLogit(usize),
+    #[error("Duplicate token id at index {0}")]
+    /// Contains the index of an entry whose token id already appeared earlier.
+    DuplicateTokenId(usize),
+    #[error("Missing entry for token id {0}")]
+    /// Contains a token id that has no entry in the logits.
+    MissingTokenId(TID),
     #[error("internal logits error: {0}")]
     /// General internal error type.
     InternalError(String),
@@ -57,7 +95,14 @@ impl From<LogitsError> for SamplerError {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Returns the number of entries a filtering sampler should keep for the
+/// given `min_keep` setting. Filtering down to nothing is never useful, so
+/// `0` is treated as `1`.
+pub fn effective_min_keep(min_keep: usize) -> usize {
+    min_keep.max(1)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 /// An individual logit with some additional metadata for use by the samplers.
 pub struct Logit {
     /// The token id.
@@ -68,6 +113,39 @@ pub struct Logit {
     pub prob: L,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+/// Summary statistics for a [Logits] distribution. See [Logits::stats].
+pub struct LogitsStats {
+    /// Highest logit value.
+    pub max_logit: L,
+    /// Lowest logit value.
+    pub min_logit: L,
+    /// Mean of the logit values.
+    pub mean: L,
+    /// Population variance of the logit values.
+    pub variance: L,
+    /// Standard deviation of the logit values.
+    pub std_dev: L,
+    /// Entropy of the softmax distribution in nats.
+    pub entropy: L,
+    /// Probability of the most likely token.
+    pub top_prob: L,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// How [Logits::softmax_with_policy] handles entries with a NaN or negative
+/// infinity logit. Positive infinity is always handled like [Logits::ensure_softmax] does.
+pub enum NanPolicy {
+    /// Remove the entries before applying softmax.
+    Skip,
+    /// Return [LogitsError::InvalidLogit] with the index of the first entry.
+    Error,
+    /// Keep the entries with a probability of zero, unless every logit is negative
+    /// infinity (see [Logits::ensure_softmax]). NaN logits are replaced with negative infinity.
+    #[default]
+    ZeroProb,
+}
+
 #[derive(Debug, Clone, Default)]
 /// A collection of [Logit]s. You normally will need to build this from the result of
 /// evaluating the LLM.
@@ -162,6 +240,153 @@ impl Logits {
                 }),
         })
     }
+
+    /// Make a new [Logits] from a contiguous slice of `L`. This is equivalent to
+    /// [Logits::try_from_iter] but avoids the per-element iterator overhead.
+    pub fn try_from_contiguous(logits: &[L]) -> Result<Self, LogitsError> {
+        if let Some(idx) = logits.iter().position(|l| l.is_nan()) {
+            Err(LogitsError::InvalidLogit(idx))?
+        }
+        Ok(Self {
+            sorted: false,
+            has_softmax: false,
+            logits: (0..)
+                .zip(logits)
+                .map(|(token_id, &logit)| Logit {
+                    token_id,
+                    logit,
+                    prob: 0f32,
+                })
+                .collect(),
+        })
+    }
+
+    /// Make a new [Logits] from probabilities, for backends that return already
+    /// softmaxed values. The probabilities are normalized to sum to `1.0` and stored
+    /// as `prob` with the logits set to `ln(prob)`. The result is sorted and has the
+    /// softmax flag set, so samplers won't recompute the probabilities unless they
+    /// modify the logits.
+    ///
+    /// Returns an error if any value is negative, infinite or NaN, or if all of them
+    /// are zero.
+    pub fn from_probs(probs: &[L]) -> Result<Self, LogitsError> {
+        if let Some(idx) = probs.iter().position(|p| !p.is_finite() || *p < 0f32) {
+            Err(LogitsError::InvalidLogit(idx))?
+        }
+        let sum = probs.iter().sum::<L>();
+        if !sum.is_normal() {
+            Err(LogitsError::InternalError(
+                "probabilities must have a positive sum".to_string(),
+            ))?
+        }
+        let mut logits = (0..)
+            .zip(probs)
+            .map(|(token_id, &prob)| {
+                let prob = prob / sum;
+                Logit {
+                    token_id,
+                    logit: prob.ln(),
+                    prob,
+                }
+            })
+            .collect::<Vec<_>>();
+        logits.sort_by(|a, b| b.prob.total_cmp(&a.prob));
+        Ok(Self {
+            sorted: true,
+            has_softmax: true,
+            logits,
+        })
+    }
+
+    /// Make a new [Logits] from a one dimensional `ndarray` view.
+    ///
+    /// Returns an error if the view isn't contiguous in standard order or
+    /// contains NaN.
+    #[cfg(feature = "ndarray")]
+    pub fn try_from_ndarray(logits: &ndarray::ArrayView1<'_, L>) -> Result<Self, LogitsError> {
+        Self::try_from_contiguous(logits.as_slice().ok_or_else(|| {
+            LogitsError::InternalError("ndarray logits must be contiguous".to_string())
+        })?)
+    }
+
+    /// Make a new [Logits] from a one dimensional `f32` candle `Tensor`.
+    /// The tensor is copied to the CPU first if necessary.
+    ///
+    /// Returns an error if the tensor isn't one dimensional or its dtype
+    /// isn't `f32`.
+    #[cfg(feature = "candle")]
+    pub fn try_from_candle(tensor: &candle_core::Tensor) -> Result<Self> {
+        if tensor.rank() != 1 {
+            Err(LogitsError::InternalError(format!(
+                "candle logits must be one dimensional, got shape {:?}",
+                tensor.dims()
+            )))?
+        }
+        if tensor.dtype() != candle_core::DType::F32 {
+            Err(LogitsError::InternalError(format!(
+                "candle logits must be f32, got {:?}",
+                tensor.dtype()
+            )))?
+        }
+        let logits = tensor
+            .to_device(&candle_core::Device::Cpu)?
+            .to_vec1::<L>()?;
+        Ok(Self::try_from_contiguous(&logits)?)
+    }
+}
+
+impl Logits {
+    /// Make a new empty [Logits] with space for `capacity` entries.
+    ///
+    /// Together with [Logits::clear_flags] this allows reusing the same buffer
+    /// in a generation loop without reallocating:
+    ///
+    /// ```rust
+    /// use llm_samplers::prelude::*;
+    ///
+    /// # fn main() -> anyhow::Result<()> {
+    /// let mut logits = Logits::with_capacity(4);
+    /// for _step in 0..2 {
+    ///     // Clear the entries from the last step and reset the flags.
+    ///     logits.clear();
+    ///     logits.clear_flags();
+    ///     logits.extend((0..4).map(|token_id| Logit {
+    ///         token_id,
+    ///         logit: token_id as f32,
+    ///         prob: 0.0,
+    ///     }));
+    ///     assert_eq!(logits.sample_token(&mut (), &mut SampleGreedy::new())?, Some(3));
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            sorted: false,
+            has_softmax: false,
+            logits: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Resets the sorted and softmax flags without touching the entries.
+    /// This should be called after changing the entries directly.
+    pub fn clear_flags(&mut self) -> &mut Self {
+        self.sorted = false;
+        self.has_softmax = false;
+        self
+    }
+
+    /// Returns the index of the entry for `token_id` if present. This is fast when
+    /// the entry is still at the position matching its token id, otherwise the
+    /// entries are searched. Returns [None] rather than failing when no entry
+    /// has the token id, for example when it's larger than the number of entries.
+    pub fn logit_index_for(&self, token_id: TID) -> Option<usize> {
+        let idx = usize::try_from(token_id).ok();
+        match idx {
+            Some(idx) if self.get(idx).is_some_and(|l| l.token_id == token_id) => Some(idx),
+            _ => self.iter().position(|l| l.token_id == token_id),
+        }
+    }
 }
 
 impl TryFrom<Vec<L>> for Logits {
@@ -216,6 +441,11 @@ impl Logits {
     }
 
     /// Ensure the softmax function has been applied to the [Logits].
+    ///
+    /// Degenerate inputs don't produce NaN probabilities: if every logit is
+    /// negative infinity (fully masked) the result is a uniform distribution, and
+    /// if any logits are positive infinity the probability is split evenly
+    /// between those entries.
     pub fn ensure_softmax(&mut self) -> Result<&mut Self> {
         if self.is_empty() || self.has_softmax {
             self.has_softmax = true;
@@ -224,15 +454,405 @@ impl Logits {
         }
         self.ensure_sorted()?;
         let max_l = self[0].logit;
+        if max_l == L::INFINITY {
+            let count = self.iter().take_while(|l| l.logit == max_l).count();
+            self.iter_mut()
+                .enumerate()
+                .for_each(|(idx, l)| l.prob = if idx < count { 1f32 / count as L } else { 0f32 });
+        } else {
+            let cum_sum = self.iter_mut().fold(0f32, |cs, l| {
+                l.prob = (l.logit - max_l).exp();
+                cs + l.prob
+            });
+            if max_l == L::NEG_INFINITY || !cum_sum.is_normal() {
+                let prob = 1f32 / self.len() as L;
+                self.iter_mut().for_each(|l| l.prob = prob);
+            } else {
+                self.iter_mut().for_each(|l| l.prob /= cum_sum);
+            }
+        }
+        self.has_softmax = true;
+        Ok(self)
+    }
+
+    /// Divides the logits by `temp` and applies softmax in a single pass. This is
+    /// equivalent to running [crate::samplers::SampleTemperature] followed by
+    /// [Self::ensure_softmax]. If `temp` is `0.0` or lower, the most likely entry
+    /// gets a probability of `1.0` and every other logit is set to negative infinity.
+    pub fn softmax_temperature(&mut self, temp: L) -> Result<&mut Self> {
+        if self.is_empty() {
+            return Ok(self);
+        }
+        self.has_softmax = false;
+        self.ensure_sorted()?;
+        if temp <= 0f32 {
+            self.iter_mut().enumerate().for_each(|(idx, l)| {
+                if idx > 0 {
+                    l.logit = L::NEG_INFINITY;
+                }
+                l.prob = if idx == 0 { 1f32 } else { 0f32 };
+            });
+            self.has_softmax = true;
+            return Ok(self);
+        }
+        let max_l = self[0].logit / temp;
+        if !max_l.is_finite() {
+            // Leave the special cases to the normal softmax.
+            self.iter_mut().for_each(|l| l.logit /= temp);
+            return self.ensure_softmax();
+        }
         let cum_sum = self.iter_mut().fold(0f32, |cs, l| {
+            l.logit /= temp;
             l.prob = (l.logit - max_l).exp();
             cs + l.prob
         });
-        self.iter_mut().for_each(|l| l.prob /= cum_sum);
+        if cum_sum.is_normal() {
+            self.iter_mut().for_each(|l| l.prob /= cum_sum);
+        } else {
+            let prob = 1f32 / self.len() as L;
+            self.iter_mut().for_each(|l| l.prob = prob);
+        }
         self.has_softmax = true;
         Ok(self)
     }
 
+    /// Applies softmax like [Self::ensure_softmax] but lets the caller choose
+    /// how NaN and negative infinity logits are handled. See [NanPolicy].
+    pub fn softmax_with_policy(&mut self, policy: NanPolicy) -> Result<&mut Self> {
+        let non_finite = |l: &Logit| l.logit.is_nan() || l.logit == L::NEG_INFINITY;
+        match policy {
+            NanPolicy::Skip => {
+                let len = self.len();
+                self.logits.retain(|l| !non_finite(l));
+                if self.len() != len {
+                    self.has_softmax = false;
+                }
+            }
+            NanPolicy::Error => {
+                if let Some(idx) = self.iter().position(non_finite) {
+                    Err(LogitsError::InvalidLogit(idx))?
+                }
+            }
+            NanPolicy::ZeroProb => {
+                let mut changed = false;
+                for l in self.logits.iter_mut().filter(|l| l.logit.is_nan()) {
+                    l.logit = L::NEG_INFINITY;
+                    changed = true;
+                }
+                if changed {
+                    self.clear_flags();
+                }
+            }
+        }
+        self.ensure_softmax()
+    }
+
+    /// Blends `other` into these logits: each logit becomes
+    /// `(1 - weight) * self + weight * other`, matching entries by token id.
+    /// This can be used for simple logit averaging ensembles.
+    ///
+    /// Returns an error if both [Logits] don't contain the same token ids. The
+    /// sorted and softmax flags are cleared.
+    pub fn blend(&mut self, other: &Logits, weight: L) -> Result<&mut Self> {
+        if self.len() != other.len() {
+            Err(LogitsError::InternalError(format!(
+                "cannot blend logits of length {} with length {}",
+                self.len(),
+                other.len()
+            )))?
+        }
+        let aligned = self
+            .iter()
+            .zip(other.iter())
+            .all(|(l, ol)| l.token_id == ol.token_id);
+        let other_logits = if aligned {
+            other.iter().map(|l| l.logit).collect::<Vec<_>>()
+        } else {
+            let by_tid = other
+                .iter()
+                .map(|l| (l.token_id, l.logit))
+                .collect::<HashMap<_, _>>();
+            self.iter()
+                .map(|l| {
+                    by_tid
+                        .get(&l.token_id)
+                        .copied()
+                        .ok_or(LogitsError::MissingTokenId(l.token_id))
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        self.iter_mut()
+            .zip(other_logits)
+            .for_each(|(l, ol)| l.logit = (1f32 - weight) * l.logit + weight * ol);
+        self.clear_flags();
+        Ok(self)
+    }
+
+    /// Checks that no entry has a NaN logit, which would break sorting and softmax.
+    /// This is useful after editing the entries directly. On failure the error contains
+    /// the index of the first offending entry.
+    ///
+    /// See [Self::validate_token_ids] to check for duplicate token ids as well.
+    pub fn validate(&self) -> Result<(), LogitsError> {
+        match self.iter().position(|l| l.logit.is_nan()) {
+            Some(idx) => Err(LogitsError::InvalidLogit(idx)),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that every token id appears at most once. On failure the error contains
+    /// the index of the first entry with a token id that was already seen.
+    pub fn validate_token_ids(&self) -> Result<(), LogitsError> {
+        let mut seen = HashSet::with_capacity(self.len());
+        match self.iter().position(|l| !seen.insert(l.token_id)) {
+            Some(idx) => Err(LogitsError::DuplicateTokenId(idx)),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets the logit of every token not allowed by `allowed` to negative infinity
+    /// in a single pass. `allowed` is indexed by token id and token ids past the end
+    /// of it are treated as disallowed. This is a cheap primitive for constrained
+    /// decoding where the set of allowed tokens changes every step.
+    ///
+    /// The sorted and softmax flags are cleared if any logits changed.
+    pub fn apply_mask(&mut self, allowed: &[bool]) -> &mut Self {
+        self.apply_mask_fn(|tid| allowed.get(tid as usize).copied().unwrap_or(false))
+    }
+
+    /// Like [Self::apply_mask] but uses a function to determine whether a token id
+    /// is allowed.
+    pub fn apply_mask_fn(&mut self, mut allowed: impl FnMut(TID) -> bool) -> &mut Self {
+        let mut changed = false;
+        self.iter_mut()
+            .filter(|l| l.logit != L::NEG_INFINITY && !allowed(l.token_id))
+            .for_each(|l| {
+                l.logit = L::NEG_INFINITY;
+                changed = true;
+            });
+        if changed {
+            self.clear_flags();
+        }
+        self
+    }
+
+    /// Returns summary statistics for the distribution. The probabilities used for
+    /// `entropy` and `top_prob` are computed from the logits, so this doesn't
+    /// require (or apply) softmax. Like [Self::ensure_softmax], if every logit is
+    /// negative infinity they're based on a uniform distribution, and if any
+    /// logits are positive infinity the probability is split evenly between those.
+    ///
+    /// All fields are `0.0` if the [Logits] are empty.
+    pub fn stats(&self) -> LogitsStats {
+        if self.is_empty() {
+            return LogitsStats::default();
+        }
+        // Welford's algorithm for the mean and variance.
+        let (max_logit, min_logit, mean, m2, _) = self.iter().fold(
+            (L::NEG_INFINITY, L::INFINITY, 0f32, 0f32, 0f32),
+            |(max_l, min_l, mean, m2, n), l| {
+                let n = n + 1f32;
+                let delta = l.logit - mean;
+                let mean = mean + delta / n;
+                let m2 = m2 + delta * (l.logit - mean);
+                (max_l.max(l.logit), min_l.min(l.logit), mean, m2, n)
+            },
+        );
+        let variance = m2 / self.len() as L;
+        let (entropy, top_prob) = if max_logit.is_infinite() {
+            let count = if max_logit == L::INFINITY {
+                self.iter().filter(|l| l.logit == max_logit).count()
+            } else {
+                self.len()
+            } as L;
+            (count.ln(), 1f32 / count)
+        } else {
+            // With z_i = l_i - max, p_i = e^z_i / sum and entropy = ln(sum) - sum(p_i * z_i).
+            let (sum, weighted) = self.iter().fold((0f32, 0f32), |(sum, weighted), l| {
+                let z = l.logit - max_logit;
+                let e = z.exp();
+                (sum + e, if e > 0f32 { weighted + e * z } else { weighted })
+            });
+            (sum.ln() - weighted / sum, 1f32 / sum)
+        };
+        LogitsStats {
+            max_logit,
+            min_logit,
+            mean,
+            variance,
+            std_dev: variance.sqrt(),
+            entropy,
+            top_prob,
+        }
+    }
+
+    /// Returns `(token_id, probability)` pairs in the current order. Softmax
+    /// is applied first if necessary, so the result will be sorted.
+    pub fn to_prob_pairs(&mut self) -> Result<Vec<(TID, L)>> {
+        Ok(self
+            .ensure_softmax()?
+            .iter()
+            .map(|l| (l.token_id, l.prob))
+            .collect())
+    }
+
+    /// Returns `(token_id, logit)` pairs in the current order.
+    pub fn to_logit_pairs(&self) -> Vec<(TID, L)> {
+        self.iter().map(|l| (l.token_id, l.logit)).collect()
+    }
+
+    /// Returns `true` if both [Logits] have the same token ids in the same order
+    /// and each pair of logits differs by at most `tol`. Identical infinite
+    /// values compare as equal. Mainly useful for testing samplers.
+    pub fn approx_eq(&self, other: &Logits, tol: L) -> bool {
+        self.len() == other.len()
+            && self.iter().zip(other.iter()).all(|(a, b)| {
+                a.token_id == b.token_id && (a.logit == b.logit || (a.logit - b.logit).abs() <= tol)
+            })
+    }
+
+    /// Like [Self::approx_eq] but compares the `prob` fields instead of the logits.
+    /// Note that this doesn't apply softmax: make sure both [Logits] have
+    /// current probabilities first.
+    pub fn probs_approx_eq(&self, other: &Logits, tol: L) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .zip(other.iter())
+                .all(|(a, b)| a.token_id == b.token_id && (a.prob - b.prob).abs() <= tol)
+    }
+
+    /// Returns the cumulative probability of all tokens at least as probable as
+    /// `token_id`, including `token_id` itself. In other words, this is the smallest
+    /// top-P `p` value that would still keep the token.
+    ///
+    /// Returns [None] if the token isn't present. Note that this will sort the
+    /// [Logits] and apply softmax if necessary.
+    pub fn cumulative_probability(&mut self, token_id: TID) -> Result<Option<L>> {
+        self.ensure_softmax()?;
+        let mut cum_sum = 0f32;
+        Ok(self
+            .iter()
+            .find(|l| {
+                cum_sum += l.prob;
+                l.token_id == token_id
+            })
+            .map(|_| cum_sum))
+    }
+
+    /// Returns the number of tokens top-P sampling with `p` would retain
+    /// (disregarding `min_keep`).
+    ///
+    /// Note that this will sort the [Logits] and apply softmax if necessary.
+    pub fn nucleus_size(&mut self, p: L) -> Result<usize> {
+        self.ensure_softmax()?;
+        let mut cum_sum = 0f32;
+        Ok(self
+            .iter()
+            .position(|l| {
+                cum_sum += l.prob;
+                cum_sum >= p
+            })
+            .map_or_else(|| self.len(), |idx| idx + 1))
+    }
+
+    /// Truncates the [Logits] to the first `keep` entries while ensuring at least
+    /// `min_keep` entries survive. Filtering samplers should use this so
+    /// `min_keep` has the same meaning everywhere. A `min_keep` of `0` is treated
+    /// as `1` (see [effective_min_keep]) so non-empty logits never become empty.
+    /// The softmax flag is cleared if any entries were removed.
+    pub fn truncate_keeping(&mut self, keep: usize, min_keep: usize) -> &mut Self {
+        let len = keep.max(effective_min_keep(min_keep));
+        if len < self.len() {
+            self.logits.truncate(len);
+            self.has_softmax = false;
+        }
+        self
+    }
+
+    /// Returns a new sorted [Logits] containing at most the top `k` entries.
+    /// This uses a bounded heap so it only needs a single pass over the
+    /// logits and is `O(n log k)` rather than `O(n log n)` for a full sort.
+    ///
+    /// Ties are resolved in favor of the entry that appears first, which
+    /// matches sorting and then truncating.
+    pub fn top_k(&self, k: usize) -> Self {
+        Self {
+            sorted: true,
+            has_softmax: false,
+            logits: self
+                .top_k_indices(k)
+                .into_iter()
+                .map(|idx| self.logits[idx])
+                .collect(),
+        }
+    }
+
+    /// Consuming version of [Self::top_k].
+    pub fn into_top_k(mut self, k: usize) -> Self {
+        if self.sorted {
+            if k < self.len() {
+                self.logits.truncate(k);
+                self.has_softmax = false;
+            }
+            return self;
+        }
+        let indices = self.top_k_indices(k);
+        let mut logits = self.logits.into_iter().map(Some).collect::<Vec<_>>();
+        Self {
+            sorted: true,
+            has_softmax: false,
+            logits: indices
+                .into_iter()
+                .filter_map(|idx| logits[idx].take())
+                .collect(),
+        }
+    }
+
+    fn top_k_indices(&self, k: usize) -> Vec<usize> {
+        /// Heap entry ordered so the worst candidate (lowest logit,
+        /// latest position for ties) is the maximum.
+        struct Candidate(L, usize);
+
+        impl PartialEq for Candidate {
+            fn eq(&self, other: &Self) -> bool {
+                self.cmp(other) == Ordering::Equal
+            }
+        }
+
+        impl Eq for Candidate {}
+
+        impl PartialOrd for Candidate {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for Candidate {
+            fn cmp(&self, other: &Self) -> Ordering {
+                other.0.total_cmp(&self.0).then(self.1.cmp(&other.1))
+            }
+        }
+
+        if k == 0 {
+            return Vec::default();
+        }
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        self.iter().enumerate().for_each(|(idx, l)| {
+            let cand = Candidate(l.logit, idx);
+            if heap.len() < k {
+                heap.push(cand);
+            } else if heap.peek().is_some_and(|worst| &cand < worst) {
+                heap.pop();
+                heap.push(cand);
+            }
+        });
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Candidate(_, idx)| idx)
+            .collect()
+    }
+
     /// Convenience method
     pub fn sample<S: Sampler>(
         &mut self,
@@ -250,9 +870,35 @@ impl Logits {
     ) -> Result<Option<TID>> {
         sampler.sample_token(res, self)
     }
+
+    /// Runs the filtering `sampler` and then returns up to `n` of the most
+    /// probable remaining tokens as `(token_id, probability)` pairs, sorted
+    /// in descending order of probability. Unlike [Logits::sample_token] this
+    /// doesn't collapse the distribution to a single token, so it can be used
+    /// to expand multiple beams.
+    ///
+    /// Softmax is applied after the sampler runs, so the probabilities are
+    /// relative to the tokens that survived filtering.
+    pub fn sample_top_candidates<S: Sampler>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        n: usize,
+        sampler: &mut S,
+    ) -> Result<Vec<(TID, L)>> {
+        Ok(sampler
+            .sample(res, self)?
+            .ensure_softmax()?
+            .iter()
+            .take(n)
+            .map(|l| (l.token_id, l.prob))
+            .collect())
+    }
 }
 
 /// The main sampler trait.
+///
+/// Samplers must be `Send + Sync` so chains can be shared between threads. Any state
+/// a sampler keeps (caches, RNGs and so on) needs to satisfy those bounds too.
 pub trait Sampler: Debug + Send + Sync {
     /// Runs the [Sampler]. Depending on the type of [Sampler], this may produce a token id.
     fn sample<'a>(
@@ -268,6 +914,23 @@ pub trait Sampler: Debug + Send + Sync {
         None
     }
 
+    /// Returns the full [Logit] entry of the last sampled token as it was when the
+    /// token was selected, if available.
+    ///
+    /// A default implemenation is provided which simply returns [None]. Samplers that
+    /// select a token should override it.
+    fn sampled_logit(&self) -> Option<Logit> {
+        None
+    }
+
+    /// Like [Sampler::sampled_token_id] but allows reporting failures, for example
+    /// a poisoned lock when the sampler is shared between threads.
+    ///
+    /// A default implementation is provided which just wraps [Sampler::sampled_token_id].
+    fn try_sampled_token_id(&self) -> Result<Option<TID>> {
+        Ok(self.sampled_token_id())
+    }
+
     /// Run the sampler and return the last sampled token id if available.
     ///
     /// A default implementation is provided which just calls [Sampler::sample] followed by
@@ -280,6 +943,53 @@ pub trait Sampler: Debug + Send + Sync {
         let _ = self.sample(res, logits)?;
         Ok(self.sampled_token_id())
     }
+
+    /// Returns a short name for the [Sampler], used for diagnostics.
+    ///
+    /// A default implementation is provided which returns the name of the type
+    /// without the module path or generic parameters. Samplers that wrap another
+    /// one should return the name of the wrapped sampler.
+    fn name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        let name = name.split('<').next().unwrap_or(name);
+        name.rsplit("::").next().unwrap_or(name)
+    }
+
+    /// Clears any state left over from previous runs, such as the last sampled token.
+    /// Adaptive samplers like Mirostat also return to their initial state.
+    ///
+    /// A default implementation is provided which does nothing.
+    fn reset(&mut self) {}
+
+    /// Returns the state of a stateful sampler that isn't part of its configuration,
+    /// like Mirostat's `mu` or the last selected token, so it can be restored later
+    /// with [Sampler::load_state]. See [SamplerChain::snapshot].
+    ///
+    /// A default implementation is provided which returns [None].
+    fn save_state(&self) -> Option<SamplerState> {
+        None
+    }
+
+    /// Restores state previously returned by [Sampler::save_state].
+    ///
+    /// A default implementation is provided which fails with [SamplerError::InternalError]
+    /// since samplers without state have nothing to restore.
+    fn load_state(&mut self, state: &SamplerState) -> Result<()> {
+        Err(state.mismatch(self.name()))?
+    }
+
+    /// Returns the [SamplerMetadata] for the [Sampler], used for introspection.
+    ///
+    /// A default implementation is provided which returns metadata named `unknown`
+    /// with no options. The built in samplers return the same value as their
+    /// [crate::configure::HasSamplerMetadata] implementation.
+    fn metadata(&self) -> SamplerMetadata {
+        SamplerMetadata {
+            name: "unknown",
+            description: None,
+            options: vec![],
+        }
+    }
 }
 
 impl Sampler for Box<dyn Sampler> {
@@ -287,6 +997,34 @@ impl Sampler for Box<dyn Sampler> {
         (**self).sampled_token_id()
     }
 
+    fn try_sampled_token_id(&self) -> Result<Option<TID>> {
+        (**self).try_sampled_token_id()
+    }
+
+    fn sampled_logit(&self) -> Option<Logit> {
+        (**self).sampled_logit()
+    }
+
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
+    fn save_state(&self) -> Option<SamplerState> {
+        (**self).save_state()
+    }
+
+    fn load_state(&mut self, state: &SamplerState) -> Result<()> {
+        (**self).load_state(state)
+    }
+
+    fn metadata(&self) -> SamplerMetadata {
+        (**self).metadata()
+    }
+
     fn sample_token(
         &mut self,
         res: &mut dyn HasSamplerResources,
@@ -305,10 +1043,56 @@ impl Sampler for Box<dyn Sampler> {
 }
 
 impl Sampler for Arc<Mutex<dyn Sampler>> {
+    /// Returns [None] if the lock is poisoned, see [Sampler::try_sampled_token_id]
+    /// to distinguish that from no token being selected.
     fn sampled_token_id(&self) -> Option<TID> {
         self.lock().ok()?.sampled_token_id()
     }
 
+    fn try_sampled_token_id(&self) -> Result<Option<TID>> {
+        self.lock()
+            .map_err(|e| SamplerError::InternalError(format!("Couldn't acquire lock: {e}")))?
+            .try_sampled_token_id()
+    }
+
+    fn sampled_logit(&self) -> Option<Logit> {
+        self.lock().ok()?.sampled_logit()
+    }
+
+    fn name(&self) -> &'static str {
+        self.lock().map_or("unknown", |sampler| sampler.name())
+    }
+
+    /// Does nothing if the lock is poisoned.
+    fn reset(&mut self) {
+        if let Ok(mut sampler) = self.lock() {
+            sampler.reset()
+        }
+    }
+
+    /// Returns [None] if the lock is poisoned.
+    fn save_state(&self) -> Option<SamplerState> {
+        self.lock().ok()?.save_state()
+    }
+
+    fn load_state(&mut self, state: &SamplerState) -> Result<()> {
+        self.lock()
+            .map_err(|e| SamplerError::InternalError(format!("Couldn't acquire lock: {e}")))?
+            .load_state(state)
+    }
+
+    /// Returns the default `unknown` metadata if the lock is poisoned.
+    fn metadata(&self) -> SamplerMetadata {
+        self.lock().map_or_else(
+            |_| SamplerMetadata {
+                name: "unknown",
+                description: None,
+                options: vec![],
+            },
+            |sampler| sampler.metadata(),
+        )
+    }
+
     fn sample_token(
         &mut self,
         res: &mut dyn HasSamplerResources,